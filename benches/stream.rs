@@ -0,0 +1,119 @@
+//! Compares `scrape_stream` with `scrape` on a generated document, reporting the time taken
+//! and the peak heap memory of each.
+//!
+//! Run with `cargo bench --bench stream`. The number of records of the document defaults to
+//! 2000 and can be set with the `BENCH_ITEMS` environment variable.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use web_scrapper::scrapper::scrap::{scrape, ClassFilter, FilterType, ScrapeOptions, TagFilter};
+use web_scrapper::scrapper::stream::scrape_stream;
+
+/// Allocator keeping track of the bytes in use and of their peak since the last `reset`.
+struct PeakAllocator {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = System.alloc(layout);
+        if !pointer.is_null() {
+            let current = self.current.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak.fetch_max(current, Ordering::Relaxed);
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        System.dealloc(pointer, layout);
+        self.current.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+impl PeakAllocator {
+    fn reset(&self) -> usize {
+        let current = self.current.load(Ordering::Relaxed);
+        self.peak.store(current, Ordering::Relaxed);
+        current
+    }
+
+    fn peak_since(&self, start: usize) -> usize {
+        self.peak.load(Ordering::Relaxed) - start
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator {
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+fn large_document(items: usize) -> String {
+    let mut raw_html = String::from("<div id='root'>");
+    for index in 0..items {
+        raw_html.push_str(&format!(
+            "<div class='item'><h2>Item {0}</h2><p>Description of item {0}, <a href='/items/{0}'>more</a></p><span class='price'>{0}.99</span></div>",
+            index
+        ));
+    }
+    raw_html.push_str("</div>");
+    raw_html
+}
+
+/// Runs `run` and returns its result with the time taken and the peak heap memory it used.
+fn measure<T>(run: impl FnOnce() -> T) -> (T, Duration, usize) {
+    let start = ALLOCATOR.reset();
+    let started = Instant::now();
+    let result = run();
+    (result, started.elapsed(), ALLOCATOR.peak_since(start))
+}
+
+fn report(name: &str, matches: usize, time: Duration, peak: usize) {
+    println!("{:<14} {:>8} matches {:>10.1?} {:>10.2} MB peak", name, matches, time, peak as f64 / 1_000_000.0);
+}
+
+fn main() {
+    let items = env::var("BENCH_ITEMS").ok().and_then(|items| items.parse().ok()).unwrap_or(2_000);
+    let path = env::temp_dir().join(format!("web_scrapper_bench_{}.html", items));
+    fs::write(&path, large_document(items)).expect("the document can not be written");
+    let size = fs::metadata(&path).expect("the document was not written").len();
+    println!("document: {} records, {:.2} MB", items, size as f64 / 1_000_000.0);
+
+    let options = ScrapeOptions {
+        class_filter: Some(ClassFilter {
+            filter: vec!["price".to_string()],
+            filter_type: FilterType::And,
+        }),
+        ..ScrapeOptions::new(TagFilter {
+            filter: vec!["span".to_string()],
+            case_sensitive: None,
+        })
+    };
+
+    // both read the document from disk, `scrape` has to load it whole
+    let (scraped, time, peak) = measure(|| {
+        let raw_html = fs::read_to_string(&path).expect("the document can not be read");
+        scrape(&raw_html, &options).expect("the document can not be parsed").len()
+    });
+    report("scrape", scraped, time, peak);
+
+    let (streamed, time, peak) = measure(|| {
+        let file = File::open(&path).expect("the document can not be opened");
+        let mut matches = 0;
+        for element in scrape_stream(BufReader::new(file), &options) {
+            element.expect("the document can not be read");
+            matches += 1;
+        }
+        matches
+    });
+    report("scrape_stream", streamed, time, peak);
+
+    fs::remove_file(&path).ok();
+    assert_eq!(scraped, streamed);
+}
@@ -1,5 +1,10 @@
+use std::time::Duration;
+
 use reqwest::Error;
 
+/// Time allowed for a `post_json` request, a hung endpoint must not block a watch job forever.
+const POST_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub fn fetch(url: &str) -> Result<String, Error> {
     let rt = tokio::runtime::Runtime::new().unwrap();
     let response = reqwest::get(url);
@@ -8,3 +13,48 @@ pub fn fetch(url: &str) -> Result<String, Error> {
     let body = rt.block_on(body)?;
     Ok(body)
 }
+
+/// Sends `body` as a JSON `POST` request to `url`. Responses with an error status and requests
+/// taking longer than 30 seconds are errors.
+pub fn post_json(url: &str, body: &str) -> Result<(), Error> {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let request = reqwest::Client::new()
+        .post(url)
+        .timeout(POST_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string());
+    // the timeout timer is created when the request is sent, inside the runtime
+    rt.block_on(async { request.send().await })?.error_for_status()?;
+    Ok(())
+}
+
+/// A fetched page with the details of the response, for provenance.
+///
+/// * `url` - Final URL of the page, after redirects.
+/// * `status` - HTTP status code of the response.
+/// * `fetched_at` - RFC 3339 timestamp in UTC of the response.
+/// * `body` - The page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchedPage {
+    pub url: String,
+    pub status: u16,
+    pub fetched_at: String,
+    pub body: String,
+}
+
+/// Same as `fetch`, keeping the final URL, the status and the time of the response. Like
+/// `fetch`, responses with an error status are returned, not reported as errors.
+pub fn fetch_with_metadata(url: &str) -> Result<FetchedPage, Error> {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let response = rt.block_on(reqwest::get(url))?;
+    let fetched_at = chrono::Utc::now().to_rfc3339();
+    let url = response.url().to_string();
+    let status = response.status().as_u16();
+    let body = rt.block_on(response.text())?;
+    Ok(FetchedPage {
+        url,
+        status,
+        fetched_at,
+        body,
+    })
+}
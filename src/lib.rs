@@ -0,0 +1,4 @@
+pub mod fetcher;
+pub mod monitor;
+pub mod scrapper;
+pub mod storage;
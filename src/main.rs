@@ -1,32 +1,47 @@
-mod fetcher;
-mod scrapper;
-mod storage;
+use std::path::{Path, PathBuf};
 
-use fetcher::fetch::fetch;
-use scrapper::scrap::{scrape, ScrapeOptions, TagFilter};
-use storage::storage::{store, StorageOptions, FileFormat};
+use web_scrapper::fetcher::fetch::fetch_with_metadata;
+use web_scrapper::monitor::notify::Notifier;
+use web_scrapper::monitor::schedule::Schedule;
+use web_scrapper::monitor::snapshot::SnapshotStore;
+use web_scrapper::monitor::watch::{watch, WatchJob};
+use web_scrapper::scrapper::parse::ParseMode;
+use web_scrapper::scrapper::provenance::scrape_with_provenance;
+use web_scrapper::scrapper::scrap::{ScrapeOptions, TagFilter};
+use web_scrapper::storage::storage::{store_with_metadata, StorageOptions, FileFormat};
 
 fn main() {
     // Fetch HTML content
     let url = "https://scholar.google.com/scholar?hl=es&as_sdt=0%2C5&q=random+number+generator+for+cryptography&btnG=&oq=random+number+generator+for+cryptogra";
-    let raw_html = fetch(url)
-        .expect(format!("error fetching url:{}", url).as_str());
 
     // Define scraping options
     let scrape_options = ScrapeOptions {
-        tags: TagFilter {
+        parse_mode: Some(ParseMode::Lenient),
+        ..ScrapeOptions::new(TagFilter {
             filter: vec!["div".to_string(), "span".to_string()],
-        },
-        id_filter: None,
-        class_filter: None,
-        attributes_include: None,
-        attributes_exclude: None,
-        text_include: None,
-        text_exclude: None,
+            case_sensitive: None,
+        })
     };
 
-    // Scrape the HTML content
-    let scraped_data = scrape(&raw_html, scrape_options);
+    // `watch <schedule>` re-runs the job on an interval such as `10m` or a cron expression
+    // such as `"*/30 * * * *"` and appends the changes of every run to changes.log
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("watch") {
+        let schedule = Schedule::parse(&args.next().unwrap_or("1h".to_string())).expect("Invalid schedule");
+        let job = WatchJob {
+            notifiers: vec![Notifier::ChangeLog(PathBuf::from("changes.log"))],
+            ..WatchJob::new(url, scrape_options, schedule)
+        };
+        watch(&job, &SnapshotStore::new(Path::new("snapshots")));
+        return;
+    }
+
+    let page = fetch_with_metadata(url)
+        .unwrap_or_else(|_| panic!("error fetching url:{}", url));
+
+    // Scrape the HTML content, keeping where every element comes from
+    let (scraped_data, provenance) = scrape_with_provenance(&page.body, &scrape_options, Some(&page))
+        .expect("Failed to scrape data");
 
     // Define storage options
     let storage_options = StorageOptions {
@@ -36,10 +51,13 @@ fn main() {
         include_attributes: Some(vec!["class".to_string(), "id".to_string(), "data-role".to_string()]),
         include_text_content: Some(true),
         include_tag_names: Some(true),
+        include_metadata: Some(true),
         pretty_print: Some(true),
         delimiter: None,
+        custom_data_storage: None,
+        text_options: None,
     };
 
     // Store the scraped data
-    store(&scraped_data, &storage_options).expect("Failed to store data");
+    store_with_metadata(&scraped_data, &provenance, &storage_options).expect("Failed to store data");
 }
@@ -0,0 +1,360 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+use serde_json::json;
+
+use super::snapshot::{record_to_json, Snapshot, SnapshotStore};
+use crate::storage::records::{json_object, Record, Value};
+
+/// Options for comparing two snapshots.
+///
+/// # Fields
+///
+/// * `key` - Fields identifying a record across runs, for example `id` or `href`. A record whose
+///   key is unchanged but whose other fields differ is reported as modified. Records sharing a
+///   key are paired in document order. Defaults to every field, a changed record is then
+///   reported as removed and added.
+/// * `ignore_fields` - Fields left out of the comparison, for example a timestamp rendered in
+///   the page. Defaults to none.
+pub struct DiffOptions {
+    pub key: Option<Vec<String>>,
+    pub ignore_fields: Option<Vec<String>>,
+}
+
+impl DiffOptions {
+    pub fn new() -> Self {
+        Self {
+            key: None,
+            ignore_fields: None,
+        }
+    }
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+        }
+    }
+}
+
+/// A record that appeared, disappeared or changed between two snapshots.
+///
+/// `before` is `None` for added records and `after` for removed ones. `changed_fields` lists the
+/// fields of a modified record whose value differs, in the order of the records.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub key: String,
+    pub before: Option<Record>,
+    pub after: Option<Record>,
+    pub changed_fields: Vec<String>,
+}
+
+/// The changes of a URL between two snapshots. `previous_taken_at` is `None` on the first run,
+/// every record is then reported as added.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeReport {
+    pub url: String,
+    pub previous_taken_at: Option<String>,
+    pub current_taken_at: String,
+    pub changes: Vec<Change>,
+    pub unchanged: usize,
+}
+
+impl ChangeReport {
+    pub fn has_changes(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.changes.iter().filter(|change| change.kind == kind).count()
+    }
+
+    /// The report as a JSON object, with the records of every change nested in it.
+    pub fn to_json(&self) -> serde_json::Value {
+        let changes: Vec<serde_json::Value> = self.changes
+            .iter()
+            .map(|change| {
+                json!({
+                    "change": change.kind.as_str(),
+                    "key": change.key,
+                    "changed_fields": change.changed_fields,
+                    "before": change.before.as_ref().map(record_to_json),
+                    "after": change.after.as_ref().map(record_to_json),
+                })
+            })
+            .collect();
+        json!({
+            "url": self.url,
+            "previous_taken_at": self.previous_taken_at,
+            "current_taken_at": self.current_taken_at,
+            "added": self.count(ChangeKind::Added),
+            "removed": self.count(ChangeKind::Removed),
+            "modified": self.count(ChangeKind::Modified),
+            "unchanged": self.unchanged,
+            "changes": changes,
+        })
+    }
+
+    /// The report as rows for `store_records`, to be written as JSON or CSV.
+    ///
+    /// A modified record gives one row per changed field with its `before` and `after` values.
+    /// An added or removed record gives a single row whose `before` or `after` is the whole
+    /// record as a JSON object.
+    pub fn to_records(&self) -> Vec<Record> {
+        let mut records = vec![];
+        let mut push = |change: &Change, field: &str, before: String, after: String| {
+            let mut record = Record::new();
+            record.insert("url", Value::Text(self.url.clone()));
+            record.insert("change", Value::Text(change.kind.as_str().to_string()));
+            record.insert("key", Value::Text(change.key.clone()));
+            record.insert("field", Value::Text(field.to_string()));
+            record.insert("before", Value::Text(before));
+            record.insert("after", Value::Text(after));
+            records.push(record);
+        };
+        let whole = |record: &Option<Record>| record.as_ref().map(|record| json_object(record, false, 0)).unwrap_or_default();
+        let field = |record: &Option<Record>, name: &str| {
+            record.as_ref().and_then(|record| record.get(name)).map(Value::as_text).unwrap_or_default()
+        };
+        for change in self.changes.iter() {
+            match change.kind {
+                ChangeKind::Added | ChangeKind::Removed => push(change, "", whole(&change.before), whole(&change.after)),
+                ChangeKind::Modified => {
+                    for name in change.changed_fields.iter() {
+                        push(change, name, field(&change.before, name), field(&change.after, name));
+                    }
+                }
+            }
+        }
+        records
+    }
+}
+
+/// Compares two snapshots of the same URL. Removed and modified records come first, in the
+/// order of `previous`, followed by the added records in the order of `current`.
+pub fn diff_snapshots(previous: Option<&Snapshot>, current: &Snapshot, options: &DiffOptions) -> ChangeReport {
+    let empty = vec![];
+    let previous_records = previous.map_or(&empty, |snapshot| &snapshot.records);
+    let ignored = options.ignore_fields.clone().unwrap_or_default();
+    let key = |record: &Record| record_key(record, options.key.as_deref(), &ignored);
+
+    let mut current_by_key: HashMap<String, VecDeque<usize>> = HashMap::new();
+    for (index, record) in current.records.iter().enumerate() {
+        current_by_key.entry(key(record)).or_default().push_back(index);
+    }
+    let mut paired = vec![false; current.records.len()];
+    let mut changes = vec![];
+    let mut unchanged = 0;
+    for before in previous_records.iter() {
+        let record_key = key(before);
+        let Some(index) = current_by_key.get_mut(&record_key).and_then(|indices| indices.pop_front()) else {
+            changes.push(Change {
+                kind: ChangeKind::Removed,
+                key: record_key,
+                before: Some(before.clone()),
+                after: None,
+                changed_fields: vec![],
+            });
+            continue;
+        };
+        paired[index] = true;
+        let after = &current.records[index];
+        let changed_fields = changed_fields(before, after, &ignored);
+        if changed_fields.is_empty() {
+            unchanged += 1;
+        } else {
+            changes.push(Change {
+                kind: ChangeKind::Modified,
+                key: record_key,
+                before: Some(before.clone()),
+                after: Some(after.clone()),
+                changed_fields,
+            });
+        }
+    }
+    for (_, after) in current.records.iter().enumerate().filter(|(index, _)| !paired[*index]) {
+        changes.push(Change {
+            kind: ChangeKind::Added,
+            key: key(after),
+            before: None,
+            after: Some(after.clone()),
+            changed_fields: vec![],
+        });
+    }
+    ChangeReport {
+        url: current.url.clone(),
+        previous_taken_at: previous.map(|snapshot| snapshot.taken_at.clone()),
+        current_taken_at: current.taken_at.clone(),
+        changes,
+        unchanged,
+    }
+}
+
+/// Compares `current` with the snapshot last saved for its URL in `store`, then saves `current`
+/// in its place for the next run. Callers that notify the changes should save after notifying,
+/// like `run_once` does, so a failed notification is not lost.
+pub fn detect_changes(store: &SnapshotStore, current: &Snapshot, options: &DiffOptions) -> io::Result<ChangeReport> {
+    let previous = store.load(&current.url)?;
+    let report = diff_snapshots(previous.as_ref(), current, options);
+    store.save(current)?;
+    Ok(report)
+}
+
+/// The values of the key fields joined with ` | `, or every compared field when no key is set.
+fn record_key(record: &Record, key: Option<&[String]>, ignored: &[String]) -> String {
+    let value = |name: &str| record.get(name).map(Value::as_text).unwrap_or_default();
+    match key {
+        Some(fields) => fields.iter().map(|name| value(name)).collect::<Vec<String>>().join(" | "),
+        None => record.fields
+            .iter()
+            .filter(|(name, _)| !ignored.contains(name))
+            .map(|(name, value)| format!("{}={}", name, value.as_text()))
+            .collect::<Vec<String>>()
+            .join(" | "),
+    }
+}
+
+fn changed_fields(before: &Record, after: &Record, ignored: &[String]) -> Vec<String> {
+    let mut names: Vec<&String> = before.fields.iter().map(|(name, _)| name).collect();
+    for (name, _) in after.fields.iter() {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+        .into_iter()
+        .filter(|name| !ignored.contains(name) && !same_value(before.get(name), after.get(name)))
+        .cloned()
+        .collect()
+}
+
+/// Non finite numbers are saved as `null` in snapshots and read back as NaN, which is not equal
+/// to itself. They are all treated as the same value.
+fn same_value(before: Option<&Value>, after: Option<&Value>) -> bool {
+    match (before, after) {
+        (Some(Value::Number(before)), Some(Value::Number(after))) if !before.is_finite() && !after.is_finite() => true,
+        _ => before == after,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(items: &[(&str, &str, &str)]) -> Snapshot {
+        let records = items
+            .iter()
+            .map(|(href, name, price)| {
+                let mut record = Record::new();
+                record.insert("href", Value::Text(href.to_string()));
+                record.insert("name", Value::Text(name.to_string()));
+                record.insert("price", Value::Text(price.to_string()));
+                record
+            })
+            .collect();
+        Snapshot::new("https://example.com/products", records)
+    }
+
+    fn keyed(fields: &[&str]) -> DiffOptions {
+        DiffOptions {
+            key: Some(fields.iter().map(|field| field.to_string()).collect()),
+            ..DiffOptions::new()
+        }
+    }
+
+    #[test]
+    fn test_diff_snapshots_keyed() {
+        let previous = snapshot(&[("/a", "Alpha", "10"), ("/b", "Beta", "20"), ("/c", "Gamma", "30")]);
+        let current = snapshot(&[("/a", "Alpha", "10"), ("/c", "Gamma", "25"), ("/d", "Delta", "40")]);
+        let report = diff_snapshots(Some(&previous), &current, &keyed(&["href"]));
+        let summary: Vec<(ChangeKind, &str, Vec<String>)> = report.changes
+            .iter()
+            .map(|change| (change.kind, change.key.as_str(), change.changed_fields.clone()))
+            .collect();
+        assert_eq!(summary, vec![
+            (ChangeKind::Removed, "/b", vec![]),
+            (ChangeKind::Modified, "/c", vec!["price".to_string()]),
+            (ChangeKind::Added, "/d", vec![]),
+        ]);
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(report.previous_taken_at, Some(previous.taken_at.clone()));
+
+        // without a key a modified record is removed and added
+        let report = diff_snapshots(Some(&previous), &current, &DiffOptions::new());
+        assert_eq!(report.changes.len(), 4);
+        assert!(report.changes.iter().all(|change| change.kind != ChangeKind::Modified));
+    }
+
+    #[test]
+    fn test_diff_snapshots_ignore_fields_and_first_run() {
+        let previous = snapshot(&[("/a", "Alpha", "10")]);
+        let current = snapshot(&[("/a", "Alpha", "12")]);
+        let options = DiffOptions {
+            ignore_fields: Some(vec!["price".to_string()]),
+            ..keyed(&["href"])
+        };
+        assert!(!diff_snapshots(Some(&previous), &current, &options).has_changes());
+
+        let report = diff_snapshots(None, &current, &options);
+        assert_eq!(report.previous_taken_at, None);
+        assert_eq!(report.changes[0].kind, ChangeKind::Added);
+    }
+
+    #[test]
+    fn test_change_report_to_records() {
+        let previous = snapshot(&[("/a", "Alpha", "10"), ("/b", "Beta", "20")]);
+        let current = snapshot(&[("/a", "Alpha v2", "11")]);
+        let records = diff_snapshots(Some(&previous), &current, &keyed(&["href"])).to_records();
+        let rows: Vec<Vec<String>> = records
+            .iter()
+            .map(|record| ["change", "key", "field", "before", "after"].iter().map(|name| record.get(name).unwrap().as_text()).collect())
+            .collect();
+        assert_eq!(rows, vec![
+            vec!["modified", "/a", "name", "Alpha", "Alpha v2"],
+            vec!["modified", "/a", "price", "10", "11"],
+            vec!["removed", "/b", "", r#"{"href":"/b","name":"Beta","price":"20"}"#, ""],
+        ]);
+    }
+
+    #[test]
+    fn test_detect_changes_saves_snapshot() {
+        let directory = std::env::temp_dir().join(format!("diff-test-{}", std::process::id()));
+        let store = SnapshotStore::new(&directory);
+        let first = detect_changes(&store, &snapshot(&[("/a", "Alpha", "10")]), &keyed(&["href"])).unwrap();
+        let second = detect_changes(&store, &snapshot(&[("/a", "Alpha", "15")]), &keyed(&["href"])).unwrap();
+        let third = detect_changes(&store, &snapshot(&[("/a", "Alpha", "15")]), &keyed(&["href"])).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(first.changes.len(), 1);
+        assert_eq!(second.changes[0].kind, ChangeKind::Modified);
+        assert!(!third.has_changes());
+    }
+
+    #[test]
+    fn test_detect_changes_non_finite_numbers() {
+        let directory = std::env::temp_dir().join(format!("diff-nan-test-{}", std::process::id()));
+        let store = SnapshotStore::new(&directory);
+        let mut current = snapshot(&[("/a", "Alpha", "")]);
+        current.records[0].insert("price", Value::Number(f64::NAN));
+        detect_changes(&store, &current, &keyed(&["href"])).unwrap();
+        let second = detect_changes(&store, &current, &keyed(&["href"])).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(!second.has_changes(), "should not report a number saved as null as modified");
+    }
+}
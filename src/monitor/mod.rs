@@ -0,0 +1,5 @@
+pub mod diff;
+pub mod notify;
+pub mod schedule;
+pub mod snapshot;
+pub mod watch;
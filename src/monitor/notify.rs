@@ -0,0 +1,174 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use super::diff::{ChangeKind, ChangeReport};
+use crate::fetcher::fetch::post_json;
+
+/// Where a watch job reports changes. Every notifier receives the report as the JSON object of
+/// `ChangeReport::to_json`.
+///
+/// * `Command` - Runs a program with arguments, the report is written to its standard input and
+///   `WATCH_URL`, `WATCH_ADDED`, `WATCH_REMOVED` and `WATCH_MODIFIED` are set in its
+///   environment. A non zero exit status is an error.
+/// * `Webhook` - Sends the report as a JSON `POST` request to a URL.
+/// * `ChangeLog` - Appends the report to a file as a single line, in JSON Lines format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notifier {
+    Command { program: String, args: Vec<String> },
+    Webhook(String),
+    ChangeLog(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Io(io::Error),
+    Http(reqwest::Error),
+    Command(String),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::Io(error) => write!(f, "{}", error),
+            NotifyError::Http(error) => write!(f, "{}", error),
+            NotifyError::Command(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+impl From<io::Error> for NotifyError {
+    fn from(error: io::Error) -> Self {
+        NotifyError::Io(error)
+    }
+}
+
+impl From<reqwest::Error> for NotifyError {
+    fn from(error: reqwest::Error) -> Self {
+        NotifyError::Http(error)
+    }
+}
+
+impl Notifier {
+    pub fn notify(&self, report: &ChangeReport) -> Result<(), NotifyError> {
+        let json = report.to_json().to_string();
+        match self {
+            Notifier::Command { program, args } => {
+                let mut child = Command::new(program)
+                    .args(args)
+                    .env("WATCH_URL", &report.url)
+                    .env("WATCH_ADDED", report.count(ChangeKind::Added).to_string())
+                    .env("WATCH_REMOVED", report.count(ChangeKind::Removed).to_string())
+                    .env("WATCH_MODIFIED", report.count(ChangeKind::Modified).to_string())
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    // a command that does not read its input closes the pipe early, that is fine
+                    if let Err(error) = stdin.write_all(json.as_bytes()) {
+                        if error.kind() != io::ErrorKind::BrokenPipe {
+                            return Err(error.into());
+                        }
+                    }
+                }
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(NotifyError::Command(format!("{} exited with {}", program, status)));
+                }
+            }
+            Notifier::Webhook(url) => post_json(url, &json)?,
+            Notifier::ChangeLog(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", json)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::monitor::diff::{diff_snapshots, DiffOptions};
+    use crate::monitor::snapshot::Snapshot;
+    use crate::storage::records::{Record, Value};
+
+    fn report() -> ChangeReport {
+        let mut record = Record::new();
+        record.insert("href", Value::Text("/a".to_string()));
+        let current = Snapshot::new("https://example.com/", vec![record]);
+        diff_snapshots(None, &current, &DiffOptions::new())
+    }
+
+    #[test]
+    fn test_notify_change_log_and_command() {
+        let directory = std::env::temp_dir().join(format!("notify-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let log = directory.join("changes.log");
+        Notifier::ChangeLog(log.clone()).notify(&report()).unwrap();
+        Notifier::ChangeLog(log.clone()).notify(&report()).unwrap();
+
+        let output = directory.join("command.txt");
+        let command = Notifier::Command {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), format!("echo \"$WATCH_ADDED $(cat)\" > {}", output.display())],
+        };
+        command.notify(&report()).unwrap();
+        let failing = Notifier::Command {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), "exit 3".to_string()],
+        };
+        let failed = failing.notify(&report());
+
+        let lines = fs::read_to_string(&log).unwrap();
+        let written = fs::read_to_string(&output).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+        assert_eq!(first["added"], 1);
+        assert!(written.starts_with("1 {\"url\":\"https://example.com/\""));
+        assert!(matches!(failed, Err(NotifyError::Command(_))));
+    }
+
+    #[test]
+    fn test_notify_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/changes", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n").unwrap();
+            (request_line, String::from_utf8(body).unwrap())
+        });
+        Notifier::Webhook(url).notify(&report()).unwrap();
+        let (request_line, body) = server.join().unwrap();
+        assert_eq!(request_line.trim(), "POST /hooks/changes HTTP/1.1");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["changes"][0]["after"]["href"], "/a");
+    }
+}
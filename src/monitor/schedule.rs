@@ -0,0 +1,216 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDateTime, TimeDelta, Timelike};
+
+/// When a watch job runs.
+///
+/// * `Interval` - Every interval, measured from the start of the previous run. The first run
+///   starts immediately.
+/// * `Cron` - At the minutes matching a cron expression, evaluated in local time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// Reads `300s`, `5m`, `2h` or `1d` as an interval, a plain number as seconds and anything
+    /// else as a cron expression.
+    pub fn parse(value: &str) -> Result<Self, ScheduleError> {
+        let value = value.trim();
+        let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+        let seconds = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            _ => return CronSchedule::parse(value).map(Schedule::Cron),
+        };
+        match number.parse::<u64>().ok().filter(|number| *number > 0).and_then(|number| number.checked_mul(seconds)) {
+            Some(seconds) => Ok(Schedule::Interval(Duration::from_secs(seconds))),
+            None => Err(ScheduleError::new(&format!("invalid interval {:?}", value))),
+        }
+    }
+
+    /// Start of the next run, given the start of the previous run if any.
+    pub fn next_run(&self, previous: Option<NaiveDateTime>, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            Schedule::Interval(interval) => match previous {
+                Some(previous) => Some(previous + TimeDelta::from_std(*interval).ok()?),
+                None => Some(now),
+            },
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleError {
+    pub message: String,
+}
+
+impl ScheduleError {
+    pub fn new(message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+/// A cron expression of five fields: minute, hour, day of month, month and day of week.
+///
+/// Fields accept `*`, numbers, ranges `1-5`, lists `1,15` and steps `*/10` or `0-30/5`. Days
+/// of week go from `0` (Sunday) to `7` (Sunday again). Like in cron, when both day fields are
+/// restricted a day matching either of them matches. A field starting with `*`, `*/2` for
+/// example, is not restricted: the day must match both fields. `@hourly`, `@daily`, `@weekly` and
+/// `@monthly` are accepted as shortcuts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    // bit `n` is set when value `n` matches
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, ScheduleError> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ScheduleError::new(&format!("expected 5 fields in cron expression {:?}", expression)));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // 7 is another name for Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    /// The first matching minute strictly after `time`, `None` if there is none in the next
+    /// five years (`0 0 31 2 *` for example).
+    pub fn next_after(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut next = time.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        let end = time + TimeDelta::days(5 * 366);
+        while next < end {
+            if !self.matches_day(next) {
+                next = next.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << next.hour()) == 0 {
+                next = next.with_minute(0)? + TimeDelta::hours(1);
+            } else if self.minutes & (1 << next.minute()) == 0 {
+                next += TimeDelta::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: NaiveDateTime) -> bool {
+        if self.months & (1 << time.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, ScheduleError> {
+    let error = || ScheduleError::new(&format!("invalid cron field {:?}", field));
+    let number = |value: &str| value.parse::<u32>().ok().filter(|value| (min..=max).contains(value)).ok_or_else(error);
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0).ok_or_else(error)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/15` runs from 5 to the end of the range
+                None if step > 1 => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(error());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_schedule_parse() {
+        assert_eq!(Schedule::parse("90").unwrap(), Schedule::Interval(Duration::from_secs(90)));
+        assert_eq!(Schedule::parse("5m").unwrap(), Schedule::Interval(Duration::from_secs(300)));
+        assert!(matches!(Schedule::parse("*/5 * * * *").unwrap(), Schedule::Cron(_)));
+        assert!(Schedule::parse("0m").is_err());
+        assert!(Schedule::parse("61 * * * *").is_err());
+        assert!(Schedule::parse("* * *").is_err());
+        assert!(Schedule::parse("999999999999999999d").is_err());
+
+        let interval = Schedule::parse("1h").unwrap();
+        let now = time("2024-05-01 10:30:00");
+        assert_eq!(interval.next_run(None, now), Some(now));
+        assert_eq!(interval.next_run(Some(now), now), Some(time("2024-05-01 11:30:00")));
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let next = |expression: &str, after: &str| CronSchedule::parse(expression).unwrap().next_after(time(after));
+        assert_eq!(next("*/15 * * * *", "2024-05-01 10:07:42"), Some(time("2024-05-01 10:15:00")));
+        assert_eq!(next("*/15 * * * *", "2024-05-01 10:45:00"), Some(time("2024-05-01 11:00:00")));
+        assert_eq!(next("30 9 * * 1-5", "2024-05-03 10:00:00"), Some(time("2024-05-06 09:30:00")));
+        assert_eq!(next("@monthly", "2024-12-15 00:00:00"), Some(time("2025-01-01 00:00:00")));
+        // Sunday as 7, and day of month or day of week when both are set
+        assert_eq!(next("0 12 * * 7", "2024-05-01 00:00:00"), Some(time("2024-05-05 12:00:00")));
+        assert_eq!(next("0 0 10 * 0", "2024-05-01 00:00:00"), Some(time("2024-05-05 00:00:00")));
+        // a stepped `*` field does not restrict, odd days that are a Monday
+        assert_eq!(next("0 0 */2 * 1", "2024-05-01 00:00:00"), Some(time("2024-05-13 00:00:00")));
+        assert_eq!(next("0 0 29 2 *", "2024-03-01 00:00:00"), Some(time("2028-02-29 00:00:00")));
+        assert_eq!(next("0 0 31 2 *", "2024-03-01 00:00:00"), None);
+    }
+}
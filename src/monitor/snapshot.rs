@@ -0,0 +1,214 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde_json::{json, Map};
+
+use crate::scrapper::hash::fnv1a;
+use crate::scrapper::parse::{attribute, parse_element, ParseError};
+use crate::scrapper::text;
+use crate::storage::records::{Record, Value};
+
+/// The records scraped from a URL at one point in time.
+///
+/// `taken_at` is an RFC 3339 timestamp in UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub url: String,
+    pub taken_at: String,
+    pub records: Vec<Record>,
+}
+
+impl Snapshot {
+    /// A snapshot of `records` taken now.
+    pub fn new(url: &str, records: Vec<Record>) -> Self {
+        Self {
+            url: url.to_string(),
+            taken_at: Utc::now().to_rfc3339(),
+            records,
+        }
+    }
+
+    /// A snapshot of the elements returned by `scrape`, see `element_record`.
+    pub fn from_elements(url: &str, elements: &[String]) -> Result<Self, ParseError> {
+        let records = elements
+            .iter()
+            .map(|element| element_record(element))
+            .collect::<Result<Vec<Record>, ParseError>>()?;
+        Ok(Self::new(url, records))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "url": self.url,
+            "taken_at": self.taken_at,
+            "records": self.records.iter().map(record_to_json).collect::<Vec<serde_json::Value>>(),
+        })
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            url: value.get("url")?.as_str()?.to_string(),
+            taken_at: value.get("taken_at")?.as_str()?.to_string(),
+            records: value.get("records")?.as_array()?.iter().map(record_from_json).collect::<Option<Vec<Record>>>()?,
+        })
+    }
+}
+
+/// Turns an element returned by `scrape` into a record with its `tag`, every attribute (`id`
+/// and `class` included, values decoded) and its visible `text`, so that results can be keyed
+/// by `id` or `href`.
+/// Attributes named `tag` or `text` are stored as `@tag` and `@text`, they never replace the
+/// fields of the element itself.
+pub fn element_record(raw_html: &str) -> Result<Record, ParseError> {
+    let element = parse_element(raw_html)?;
+    let mut record = Record::new();
+    record.insert("tag", Value::Text(element.name.to_lowercase()));
+    if let Some(id) = attribute(&element, "id") {
+        record.insert("id", Value::Text(id));
+    }
+    if let Some(classes) = attribute(&element, "class") {
+        record.insert("class", Value::List(classes.split_whitespace().map(String::from).collect()));
+    }
+    // attributes are kept in a hash map, sort them for a stable field order
+    let mut names: Vec<&String> = element.attributes.keys().collect();
+    names.sort();
+    for name in names {
+        let value = attribute(&element, name).unwrap_or_default();
+        let name = match name.to_lowercase() {
+            name if name == "tag" || name == "text" => format!("@{}", name),
+            name => name,
+        };
+        record.insert(&name, Value::Text(value));
+    }
+    record.insert("text", Value::Text(text::visible_text(&element, true)));
+    Ok(record)
+}
+
+/// Snapshots stored as JSON files in a directory, one file per URL.
+pub struct SnapshotStore {
+    pub directory: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(directory: &Path) -> Self {
+        Self {
+            directory: directory.to_path_buf(),
+        }
+    }
+
+    /// File of the snapshot of `url`: the URL with unsafe characters replaced, followed by a
+    /// hash of the full URL so that distinct URLs never share a file.
+    pub fn path(&self, url: &str) -> PathBuf {
+        let readable: String = url
+            .split("://")
+            .last()
+            .unwrap_or(url)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+            .take(80)
+            .collect();
+        self.directory.join(format!("{}-{:016x}.json", readable, fnv1a(url)))
+    }
+
+    /// The last snapshot saved for `url`, `None` if there is none yet.
+    pub fn load(&self, url: &str) -> io::Result<Option<Snapshot>> {
+        let content = match fs::read_to_string(self.path(url)) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let value: serde_json::Value = serde_json::from_str(&content).map_err(io::Error::other)?;
+        Snapshot::from_json(&value)
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid snapshot file"))
+    }
+
+    /// Saves `snapshot`, replacing the previous snapshot of its URL.
+    pub fn save(&self, snapshot: &Snapshot) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let content = serde_json::to_string_pretty(&snapshot.to_json()).map_err(io::Error::other)?;
+        // written next to the target and renamed, a crash never leaves a truncated snapshot
+        let path = self.path(&snapshot.url);
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, &path)
+    }
+}
+
+pub(crate) fn record_to_json(record: &Record) -> serde_json::Value {
+    let mut object = Map::new();
+    for (name, value) in record.fields.iter() {
+        let value = match value {
+            Value::Text(text) => json!(text),
+            Value::List(items) => json!(items),
+            Value::Number(number) => json!(number),
+        };
+        object.insert(name.clone(), value);
+    }
+    serde_json::Value::Object(object)
+}
+
+fn record_from_json(value: &serde_json::Value) -> Option<Record> {
+    let mut record = Record::new();
+    for (name, value) in value.as_object()? {
+        let value = match value {
+            serde_json::Value::String(text) => Value::Text(text.clone()),
+            serde_json::Value::Number(number) => Value::Number(number.as_f64()?),
+            // non finite numbers are stored as null
+            serde_json::Value::Null => Value::Number(f64::NAN),
+            serde_json::Value::Array(items) => Value::List(
+                items.iter().map(|item| item.as_str().map(str::to_string)).collect::<Option<Vec<String>>>()?,
+            ),
+            _ => return None,
+        };
+        record.insert(name, value);
+    }
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_element_record() {
+        let record = element_record(r#"<a href="/item/1" class="product new" data-sku="A1">Widget <b>XL</b></a>"#).unwrap();
+        assert_eq!(record.fields, vec![
+            ("tag".to_string(), Value::Text("a".to_string())),
+            ("class".to_string(), Value::List(vec!["product".to_string(), "new".to_string()])),
+            ("data-sku".to_string(), Value::Text("A1".to_string())),
+            ("href".to_string(), Value::Text("/item/1".to_string())),
+            ("text".to_string(), Value::Text("Widget XL".to_string())),
+        ]);
+
+        let record = element_record(r#"<button tag="primary" text="Buy">Add to cart</button>"#).unwrap();
+        assert_eq!(record.get("tag"), Some(&Value::Text("button".to_string())));
+        assert_eq!(record.get("@tag"), Some(&Value::Text("primary".to_string())));
+        assert_eq!(record.get("text"), Some(&Value::Text("Add to cart".to_string())));
+        assert_eq!(record.get("@text"), Some(&Value::Text("Buy".to_string())));
+
+        let record = element_record(r#"<a id="a&amp;b" href="/a?x=1&amp;y=2" title="A &#38; B">A</a>"#).unwrap();
+        assert_eq!(record.get("id"), Some(&Value::Text("a&b".to_string())));
+        assert_eq!(record.get("href"), Some(&Value::Text("/a?x=1&y=2".to_string())));
+        assert_eq!(record.get("title"), Some(&Value::Text("A & B".to_string())));
+    }
+
+    #[test]
+    fn test_snapshot_store_round_trip() {
+        let directory = std::env::temp_dir().join(format!("snapshot-test-{}", std::process::id()));
+        let store = SnapshotStore::new(&directory);
+        let url = "https://example.com/products?page=1";
+        assert_eq!(store.load(url).unwrap(), None);
+
+        let mut record = element_record("<li id='a'>One</li>").unwrap();
+        record.insert("price", Value::Number(9.5));
+        let snapshot = Snapshot::new(url, vec![record]);
+        store.save(&snapshot).unwrap();
+        let loaded = store.load(url).unwrap();
+        assert_ne!(store.path(url), store.path("https://example.com/products?page=2"));
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(loaded, Some(snapshot));
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+use std::io;
+use std::thread;
+
+use chrono::Local;
+
+use super::diff::{diff_snapshots, ChangeKind, ChangeReport, DiffOptions};
+use super::notify::{NotifyError, Notifier};
+use super::schedule::Schedule;
+use super::snapshot::{Snapshot, SnapshotStore};
+use crate::fetcher::fetch::fetch_with_metadata;
+use crate::scrapper::parse::ParseError;
+use crate::scrapper::scrap::{scrape, ScrapeOptions};
+
+/// A fetch and scrape job re-run on a schedule.
+///
+/// # Fields
+///
+/// * `url` - The page to fetch.
+/// * `scrape_options` - The elements compared between runs, see `Snapshot::from_elements`.
+/// * `diff_options` - How runs are compared, set its `key` to report modified elements.
+/// * `schedule` - When the job runs.
+/// * `notifiers` - Notified when a run finds changes. Defaults to none.
+/// * `notify_first_run` - Whether the first run, which has no snapshot to compare with and
+///   reports every element as added, notifies. Defaults to `false`.
+/// * `max_runs` - Number of runs before `watch` returns. Defaults to running forever.
+pub struct WatchJob {
+    pub url: String,
+    pub scrape_options: ScrapeOptions,
+    pub diff_options: DiffOptions,
+    pub schedule: Schedule,
+    pub notifiers: Vec<Notifier>,
+    pub notify_first_run: Option<bool>,
+    pub max_runs: Option<usize>,
+}
+
+impl WatchJob {
+    pub fn new(url: &str, scrape_options: ScrapeOptions, schedule: Schedule) -> Self {
+        Self {
+            url: url.to_string(),
+            scrape_options,
+            diff_options: DiffOptions::new(),
+            schedule,
+            notifiers: vec![],
+            notify_first_run: None,
+            max_runs: None,
+        }
+    }
+}
+
+/// Error of a single run. `Status` is a response with an error status, the page is not compared.
+/// A failed notifier does not prevent the others from running, but the snapshot of the run is
+/// not saved so the next run reports the same changes again.
+#[derive(Debug)]
+pub enum WatchError {
+    Fetch(reqwest::Error),
+    Status(u16),
+    Parse(ParseError),
+    Io(io::Error),
+    Notify(Vec<NotifyError>),
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchError::Fetch(error) => write!(f, "{}", error),
+            WatchError::Status(status) => write!(f, "the page returned status {}", status),
+            WatchError::Parse(error) => write!(f, "{}", error),
+            WatchError::Io(error) => write!(f, "{}", error),
+            WatchError::Notify(errors) => write!(
+                f,
+                "notification failed: {}",
+                errors.iter().map(|error| error.to_string()).collect::<Vec<String>>().join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+impl From<reqwest::Error> for WatchError {
+    fn from(error: reqwest::Error) -> Self {
+        WatchError::Fetch(error)
+    }
+}
+
+impl From<ParseError> for WatchError {
+    fn from(error: ParseError) -> Self {
+        WatchError::Parse(error)
+    }
+}
+
+impl From<io::Error> for WatchError {
+    fn from(error: io::Error) -> Self {
+        WatchError::Io(error)
+    }
+}
+
+/// Runs `job` on its schedule, fetching the page with `fetch_page`, until `max_runs` is reached.
+/// Every run is printed, a failed run is reported and the job keeps running.
+pub fn watch(job: &WatchJob, store: &SnapshotStore) {
+    watch_with(job, store, fetch_page, |run, result| match result {
+        Ok(report) => println!(
+            "run {} of {}: {} added, {} removed, {} modified",
+            run,
+            report.url,
+            report.count(ChangeKind::Added),
+            report.count(ChangeKind::Removed),
+            report.count(ChangeKind::Modified)
+        ),
+        Err(error) => eprintln!("run {} of {} failed: {}", run, job.url, error),
+    });
+}
+
+/// Fetches the page of a run. Responses with an error status, a 404 or 503 page for example,
+/// are errors so they are never compared with the snapshot.
+pub fn fetch_page(url: &str) -> Result<String, WatchError> {
+    let page = fetch_with_metadata(url)?;
+    if !(200..300).contains(&page.status) {
+        return Err(WatchError::Status(page.status));
+    }
+    Ok(page.body)
+}
+
+/// Same as `watch` with the page obtained from `fetch` and the result of every run, numbered
+/// from 1, passed to `on_run`.
+pub fn watch_with<F, R>(job: &WatchJob, store: &SnapshotStore, mut fetch: F, mut on_run: R)
+where
+    F: FnMut(&str) -> Result<String, WatchError>,
+    R: FnMut(usize, Result<ChangeReport, WatchError>),
+{
+    let mut previous = None;
+    let mut run = 0;
+    while job.max_runs.is_none_or(|max_runs| run < max_runs) {
+        let now = Local::now().naive_local();
+        let Some(next) = job.schedule.next_run(previous, now) else {
+            return;
+        };
+        if let Ok(delay) = (next - now).to_std() {
+            thread::sleep(delay);
+        }
+        previous = Some(next);
+        run += 1;
+        let result = fetch(&job.url).and_then(|raw_html| run_once(job, store, &raw_html));
+        on_run(run, result);
+    }
+}
+
+/// Scrapes `raw_html`, compares the result with the previous snapshot of the job, notifies the
+/// notifiers of the job if anything changed and saves the new snapshot once they all succeeded.
+pub fn run_once(job: &WatchJob, store: &SnapshotStore, raw_html: &str) -> Result<ChangeReport, WatchError> {
+    let elements = scrape(raw_html, &job.scrape_options)?;
+    let current = Snapshot::from_elements(&job.url, &elements)?;
+    let previous = store.load(&current.url)?;
+    let report = diff_snapshots(previous.as_ref(), &current, &job.diff_options);
+    let first_run = report.previous_taken_at.is_none();
+    if report.has_changes() && (!first_run || job.notify_first_run.unwrap_or(false)) {
+        let errors: Vec<NotifyError> = job.notifiers
+            .iter()
+            .filter_map(|notifier| notifier.notify(&report).err())
+            .collect();
+        if !errors.is_empty() {
+            return Err(WatchError::Notify(errors));
+        }
+    }
+    store.save(&current)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use crate::scrapper::scrap::TagFilter;
+
+    #[test]
+    fn test_watch_with_notifies_changes() {
+        let directory = std::env::temp_dir().join(format!("watch-test-{}", std::process::id()));
+        let store = SnapshotStore::new(&directory.join("snapshots"));
+        let log = directory.join("changes.log");
+        let job = WatchJob {
+            diff_options: DiffOptions {
+                key: Some(vec!["href".to_string()]),
+                ..DiffOptions::new()
+            },
+            notifiers: vec![Notifier::ChangeLog(log.clone())],
+            max_runs: Some(3),
+            ..WatchJob::new(
+                "https://example.com/products",
+                ScrapeOptions::new(TagFilter {
+                    filter: vec!["a".to_string()],
+                    case_sensitive: None,
+                }),
+                Schedule::Interval(Duration::from_millis(10)),
+            )
+        };
+        let pages = ["<a href='/a'>A</a>", "<a href='/a'>A</a>", "<a href='/a'>A2</a><a href='/b'>B</a>"];
+        let mut fetched = 0;
+        let mut reports = vec![];
+        watch_with(&job, &store, |_| {
+            fetched += 1;
+            Ok(pages[fetched - 1].to_string())
+        }, |run, result| reports.push((run, result.unwrap())));
+
+        let log_content = fs::read_to_string(&log).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].1.count(ChangeKind::Added), 1);
+        assert!(!reports[1].1.has_changes());
+        assert_eq!(reports[2].0, 3);
+        assert_eq!(reports[2].1.count(ChangeKind::Modified), 1);
+        assert_eq!(reports[2].1.count(ChangeKind::Added), 1);
+        // the first run and the run without changes do not notify
+        assert_eq!(log_content.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_run_once_keeps_snapshot_when_notify_fails() {
+        let directory = std::env::temp_dir().join(format!("watch-notify-test-{}", std::process::id()));
+        let store = SnapshotStore::new(&directory.join("snapshots"));
+        let log_directory = directory.join("logs");
+        let job = WatchJob {
+            notifiers: vec![Notifier::ChangeLog(log_directory.join("changes.log"))],
+            ..WatchJob::new(
+                "https://example.com/products",
+                ScrapeOptions::new(TagFilter {
+                    filter: vec!["a".to_string()],
+                    case_sensitive: None,
+                }),
+                Schedule::Interval(Duration::from_secs(60)),
+            )
+        };
+        run_once(&job, &store, "<a href='/a'>A</a>").unwrap();
+        // the log directory does not exist yet, the notification fails
+        let failed = run_once(&job, &store, "<a href='/a'>A</a><a href='/b'>B</a>");
+        assert!(matches!(failed, Err(WatchError::Notify(_))));
+        fs::create_dir_all(&log_directory).unwrap();
+        let retried = run_once(&job, &store, "<a href='/a'>A</a><a href='/b'>B</a>").unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(retried.count(ChangeKind::Added), 1, "should report the changes of the failed run again");
+    }
+
+    #[test]
+    fn test_fetch_page_error_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/products", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nnot found").unwrap();
+        });
+        let result = fetch_page(&url);
+        server.join().unwrap();
+        assert!(matches!(result, Err(WatchError::Status(404))));
+    }
+}
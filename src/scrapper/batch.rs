@@ -0,0 +1,233 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use super::parse::ParseError;
+use super::scrap::{scrape, ScrapeOptions};
+
+/// A document of a batch: HTML already in memory, for example fetched pages, or a saved file
+/// that is read by the worker thread processing it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Html { source: String, html: String },
+    File(PathBuf),
+}
+
+impl Input {
+    pub fn html(source: &str, html: &str) -> Self {
+        Input::Html {
+            source: source.to_string(),
+            html: html.to_string(),
+        }
+    }
+
+    /// Name of the document in the results: the given source or the file path.
+    pub fn source(&self) -> String {
+        match self {
+            Input::Html { source, .. } => source.clone(),
+            Input::File(path) => path.display().to_string(),
+        }
+    }
+
+    fn read(&self) -> io::Result<String> {
+        match self {
+            Input::Html { html, .. } => Ok(html.clone()),
+            Input::File(path) => fs::read_to_string(path),
+        }
+    }
+}
+
+/// Lists the `.html` and `.htm` files of `directory` and its subdirectories, sorted by path.
+pub fn html_files(directory: &Path) -> io::Result<Vec<Input>> {
+    let mut files = vec![];
+    let mut pending = vec![directory.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm"))
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files.into_iter().map(Input::File).collect())
+}
+
+/// Options of a batch run.
+///
+/// # Fields
+///
+/// * `threads` - Number of worker threads. Defaults to the available parallelism of the machine.
+/// * `ordered` - Whether results are returned in input order. Otherwise they are returned in
+///   the order the documents finished. Results are only returned once every document is done
+///   either way, so this only changes their order. Defaults to `true`.
+pub struct BatchOptions {
+    pub threads: Option<usize>,
+    pub ordered: Option<bool>,
+}
+
+impl BatchOptions {
+    pub fn new() -> Self {
+        Self {
+            threads: None,
+            ordered: None,
+        }
+    }
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error of a single document of a batch.
+#[derive(Debug)]
+pub enum BatchError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Io(error) => write!(f, "{}", error),
+            BatchError::Parse(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+impl From<io::Error> for BatchError {
+    fn from(error: io::Error) -> Self {
+        BatchError::Io(error)
+    }
+}
+
+impl From<ParseError> for BatchError {
+    fn from(error: ParseError) -> Self {
+        BatchError::Parse(error)
+    }
+}
+
+/// Result of one document, tagged with its source and its index in the input.
+#[derive(Debug)]
+pub struct BatchResult<T> {
+    pub index: usize,
+    pub source: String,
+    pub result: Result<T, BatchError>,
+}
+
+/// Runs `scrape` with `options` on every input, in parallel. A document that can not be read
+/// or parsed only fails its own result.
+pub fn scrape_batch(inputs: &[Input], options: &ScrapeOptions, batch: &BatchOptions) -> Vec<BatchResult<Vec<String>>> {
+    run_batch(inputs, batch, |html| Ok(scrape(html, options)?))
+}
+
+/// Runs `process` on the HTML of every input, in parallel. Use it for any other extraction,
+/// for example `|html| Ok(query.records(html)?)` with a `CompiledQuery`.
+pub fn run_batch<T, F>(inputs: &[Input], batch: &BatchOptions, process: F) -> Vec<BatchResult<T>>
+where
+    T: Send,
+    F: Fn(&str) -> Result<T, BatchError> + Sync,
+{
+    let threads = batch.threads
+        .unwrap_or_else(|| thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1))
+        .clamp(1, inputs.len().max(1));
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut results: Vec<BatchResult<T>> = thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (next, process) = (&next, &process);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(input) = inputs.get(index) else {
+                    break;
+                };
+                let result = input.read().map_err(BatchError::from).and_then(|html| process(&html));
+                let sent = sender.send(BatchResult {
+                    index,
+                    source: input.source(),
+                    result,
+                });
+                if sent.is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        receiver.iter().collect()
+    });
+    if batch.ordered.unwrap_or(true) {
+        results.sort_by_key(|result| result.index);
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrapper::scrap::TagFilter;
+
+    fn spans() -> ScrapeOptions {
+        ScrapeOptions::new(TagFilter {
+            filter: vec!["span".to_string()],
+            case_sensitive: None,
+        })
+    }
+
+    #[test]
+    fn test_scrape_batch_ordered() {
+        let inputs: Vec<Input> = (0..50)
+            .map(|index| Input::html(&format!("page-{}", index), &format!("<div><span>{}</span></div>", index)))
+            .collect();
+        let batch = BatchOptions {
+            threads: Some(4),
+            ..BatchOptions::new()
+        };
+        let results = scrape_batch(&inputs, &spans(), &batch);
+        assert_eq!(results.len(), 50);
+        for (index, result) in results.iter().enumerate() {
+            assert_eq!(result.index, index);
+            assert_eq!(result.source, format!("page-{}", index));
+            assert_eq!(result.result.as_ref().unwrap(), &vec![format!("<span>{}</span>", index)]);
+        }
+    }
+
+    #[test]
+    fn test_scrape_batch_errors_and_files() {
+        let directory = std::env::temp_dir().join(format!("batch-test-{}", std::process::id()));
+        fs::create_dir_all(directory.join("nested")).unwrap();
+        fs::write(directory.join("b.html"), "<span>b</span>").unwrap();
+        fs::write(directory.join("nested/a.htm"), "<span>a</span>").unwrap();
+        fs::write(directory.join("notes.txt"), "<span>skipped</span>").unwrap();
+        let mut inputs = html_files(&directory).unwrap();
+        inputs.push(Input::html("broken", "<!-- never closed"));
+        inputs.push(Input::File(directory.join("missing.html")));
+        let batch = BatchOptions {
+            ordered: Some(false),
+            ..BatchOptions::new()
+        };
+        let mut results = scrape_batch(&inputs, &spans(), &batch);
+        fs::remove_dir_all(&directory).unwrap();
+
+        results.sort_by_key(|result| result.index);
+        assert!(results[0].source.ends_with("b.html"));
+        assert_eq!(results[0].result.as_ref().unwrap(), &vec!["<span>b</span>".to_string()]);
+        assert_eq!(results[1].result.as_ref().unwrap(), &vec!["<span>a</span>".to_string()]);
+        assert!(matches!(results[2].result, Err(BatchError::Parse(_))));
+        assert!(matches!(results[3].result, Err(BatchError::Io(_))));
+    }
+}
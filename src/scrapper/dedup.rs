@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+
+use unicode_normalization::UnicodeNormalization;
+
+use super::hash::fnv1a;
+use super::parse::{attribute, parse_element};
+use super::text::{self, TextOptions};
+use crate::storage::records::{Record, Value};
+
+/// What makes two results duplicates.
+///
+/// * `OuterHtml` - The exact outer HTML of elements. For records, every field.
+/// * `NormalizedText` - The visible text, NFKC normalized, lowercased and with whitespace
+///   collapsed. For records, the text of every field.
+/// * `Field` - The value of one attribute of elements, such as `href`, or of one record field.
+///   Results without it are never duplicates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DedupKey {
+    OuterHtml,
+    NormalizedText,
+    Field(String),
+}
+
+/// Near-duplicate detection with SimHash over word shingles of the key.
+///
+/// # Fields
+///
+/// * `shingle_size` - Number of consecutive words of a shingle. Defaults to `3`.
+/// * `max_distance` - Maximum number of differing bits between the 64 bit fingerprints of two
+///   near-duplicates. Defaults to `3`, `0` only matches texts whose shingles hash alike.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NearDuplicates {
+    pub shingle_size: Option<usize>,
+    pub max_distance: Option<u32>,
+}
+
+impl NearDuplicates {
+    pub fn new() -> Self {
+        Self {
+            shingle_size: None,
+            max_distance: None,
+        }
+    }
+}
+
+impl Default for NearDuplicates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Options of `dedup` and `dedup_records`. The first of every group of duplicates is kept, in
+/// its original position.
+///
+/// # Fields
+///
+/// * `key` - What identifies duplicates.
+/// * `near_duplicates` - Also drop results whose key is almost the same as the key of a kept
+///   result. Every result is compared with every kept one. Defaults to exact matching only.
+pub struct DedupOptions {
+    pub key: DedupKey,
+    pub near_duplicates: Option<NearDuplicates>,
+}
+
+impl DedupOptions {
+    pub fn new(key: DedupKey) -> Self {
+        Self {
+            key,
+            near_duplicates: None,
+        }
+    }
+}
+
+/// Drops duplicate elements from the results of `scrape`, for example the same widget repeated
+/// in the header and the footer of a page. Elements nested in another match are a different
+/// problem, set `skip_nested` of `ScrapeOptions` for those.
+pub fn dedup(elements: &[String], options: &DedupOptions) -> Vec<String> {
+    let keys: Vec<Option<String>> = elements.iter().map(|element| element_key(element, &options.key)).collect();
+    kept(&keys, options).into_iter().map(|index| elements[index].clone()).collect()
+}
+
+/// Drops duplicate records, see `dedup`.
+pub fn dedup_records(records: &[Record], options: &DedupOptions) -> Vec<Record> {
+    let keys: Vec<Option<String>> = records.iter().map(|record| record_key(record, &options.key)).collect();
+    kept(&keys, options).into_iter().map(|index| records[index].clone()).collect()
+}
+
+fn element_key(raw_html: &str, key: &DedupKey) -> Option<String> {
+    match key {
+        DedupKey::OuterHtml => Some(raw_html.to_string()),
+        DedupKey::NormalizedText => Some(match parse_element(raw_html) {
+            Ok(element) => normalize(&text::extract_text(&element, &TextOptions::new())),
+            // what can not be parsed is compared as text
+            Err(_) => normalize(raw_html),
+        }),
+        DedupKey::Field(name) => parse_element(raw_html).ok().and_then(|element| attribute(&element, name)),
+    }
+}
+
+fn record_key(record: &Record, key: &DedupKey) -> Option<String> {
+    let fields = || record.fields.iter().map(|(name, value)| format!("{}\u{1f}{}", name, value.as_text()));
+    match key {
+        DedupKey::OuterHtml => Some(fields().collect::<Vec<String>>().join("\u{1e}")),
+        DedupKey::NormalizedText => Some(normalize(
+            &record.fields.iter().map(|(_, value)| value.as_text()).collect::<Vec<String>>().join(" "),
+        )),
+        DedupKey::Field(name) => record.get(name).map(Value::as_text),
+    }
+}
+
+fn normalize(value: &str) -> String {
+    text::collapse_whitespace(&value.nfkc().collect::<String>()).to_lowercase()
+}
+
+/// Indices of the results to keep.
+fn kept(keys: &[Option<String>], options: &DedupOptions) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    let mut fingerprints: Vec<u64> = vec![];
+    let mut kept = vec![];
+    for (index, key) in keys.iter().enumerate() {
+        let Some(key) = key else {
+            kept.push(index);
+            continue;
+        };
+        if !seen.insert(key.as_str()) {
+            continue;
+        }
+        if let Some(ref near_duplicates) = options.near_duplicates {
+            let fingerprint = simhash(key, near_duplicates.shingle_size.unwrap_or(3));
+            let max_distance = near_duplicates.max_distance.unwrap_or(3);
+            if fingerprints.iter().any(|other| (fingerprint ^ other).count_ones() <= max_distance) {
+                continue;
+            }
+            fingerprints.push(fingerprint);
+        }
+        kept.push(index);
+    }
+    kept
+}
+
+/// 64 bit SimHash of the word shingles of `value`: similar texts give fingerprints differing in
+/// few bits.
+pub fn simhash(value: &str, shingle_size: usize) -> u64 {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    let shingles: Vec<String> = if words.len() <= shingle_size.max(1) {
+        vec![words.join(" ")]
+    } else {
+        words.windows(shingle_size.max(1)).map(|window| window.join(" ")).collect()
+    };
+    let mut weights = [0i64; 64];
+    for shingle in shingles.iter() {
+        let hash = mix(fnv1a(shingle));
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash & (1 << bit) != 0 { 1 } else { -1 };
+        }
+    }
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |fingerprint, (bit, _)| fingerprint | (1 << bit))
+}
+
+/// Spreads the bits of a FNV hash, whose high bits barely change between similar strings.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_dedup_elements() {
+        let elements = strings(&[
+            "<li><a href='/a'>Alpha</a></li>",
+            "<li><a href='/b'>Beta</a></li>",
+            "<li><a href='/a'>Alpha</a></li>",
+            "<li class='x'>  ALPHA </li>",
+        ]);
+        assert_eq!(dedup(&elements, &DedupOptions::new(DedupKey::OuterHtml)), vec![
+            elements[0].clone(),
+            elements[1].clone(),
+            elements[3].clone(),
+        ]);
+        assert_eq!(dedup(&elements, &DedupOptions::new(DedupKey::NormalizedText)), vec![
+            elements[0].clone(),
+            elements[1].clone(),
+        ]);
+        let links = strings(&["<a href='/a'>One</a>", "<a href='/a'>Two</a>", "<a>No href</a>", "<a>No href</a>"]);
+        assert_eq!(dedup(&links, &DedupOptions::new(DedupKey::Field("href".to_string()))), vec![
+            links[0].clone(),
+            links[2].clone(),
+            links[3].clone(),
+        ]);
+    }
+
+    #[test]
+    fn test_dedup_near_duplicates() {
+        let elements = strings(&[
+            "<p>The quick brown fox jumps over the lazy dog near the old river bank today</p>",
+            "<p>The quick brown fox jumps over the lazy dog near the old river bank tonight</p>",
+            "<p>Completely unrelated announcement about the spring sale of garden furniture</p>",
+        ]);
+        let options = DedupOptions {
+            near_duplicates: Some(NearDuplicates {
+                shingle_size: Some(2),
+                max_distance: Some(10),
+            }),
+            ..DedupOptions::new(DedupKey::NormalizedText)
+        };
+        assert_eq!(dedup(&elements, &options), vec![elements[0].clone(), elements[2].clone()]);
+        assert_eq!(dedup(&elements, &DedupOptions::new(DedupKey::NormalizedText)).len(), 3);
+    }
+
+    #[test]
+    fn test_dedup_records() {
+        let record = |name: &str, url: &str| {
+            let mut record = Record::new();
+            record.insert("name", Value::Text(name.to_string()));
+            record.insert("url", Value::Text(url.to_string()));
+            record
+        };
+        let records = vec![record("A", "/a"), record("A", "/a"), record("a ", "/a"), record("B", "/a")];
+        assert_eq!(dedup_records(&records, &DedupOptions::new(DedupKey::OuterHtml)).len(), 3);
+        assert_eq!(dedup_records(&records, &DedupOptions::new(DedupKey::NormalizedText)).len(), 2);
+        assert_eq!(dedup_records(&records, &DedupOptions::new(DedupKey::Field("url".to_string()))), vec![records[0].clone()]);
+    }
+}
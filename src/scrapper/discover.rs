@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+
+use html_parser::{Dom, Element};
+
+use super::parse::{attribute, parse_html, source, ParseError, ParseMode};
+use super::query::{Schema, SchemaField};
+use super::scrap::{ClassFilter, FilterType, IdFilter, ScrapeOptions, TagFilter};
+use super::text;
+
+/// Options of `discover`.
+///
+/// # Fields
+///
+/// * `min_count` - Minimum number of siblings of a candidate. Defaults to `3`.
+/// * `max_candidates` - Maximum number of candidates returned. Defaults to `10`.
+/// * `min_coverage` - Minimum share of the records, from `0.0` to `1.0`, in which a field is
+///   found for it to be suggested. Defaults to `0.5`.
+pub struct DiscoverOptions {
+    pub min_count: Option<usize>,
+    pub max_candidates: Option<usize>,
+    pub min_coverage: Option<f64>,
+}
+
+impl DiscoverOptions {
+    pub fn new() -> Self {
+        Self {
+            min_count: None,
+            max_candidates: None,
+            min_coverage: None,
+        }
+    }
+}
+
+impl Default for DiscoverOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A group of sibling elements with the same tag and classes, likely the records of a list.
+///
+/// # Fields
+///
+/// * `selector` - Selector of the records for `CompiledQuery::from_selector`, the parent
+///   followed by the records.
+/// * `count` - Number of records in the document.
+/// * `average_text_length` - Average length of the visible text of a record, in characters.
+/// * `score` - Rank of the candidate, higher for more records with more text.
+/// * `fields` - Values found in most records, see `SuggestedField`.
+/// * `sample` - Source of the first record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub selector: String,
+    pub count: usize,
+    pub average_text_length: usize,
+    pub score: f64,
+    pub fields: Vec<SuggestedField>,
+    pub sample: String,
+    parent: Signature,
+    record: Signature,
+}
+
+/// A value found inside the records of a `Candidate`.
+///
+/// # Fields
+///
+/// * `name` - Name proposed for the field, from the class or the tag of the element.
+/// * `selector` - Selector of the element among the descendants of the record, `None` for the
+///   record element itself.
+/// * `attribute` - Attribute holding the value, `href` of links and `src` of images. `None`
+///   for the visible text.
+/// * `coverage` - Share of the records in which the element is found.
+/// * `sample` - Value in the first record having it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuggestedField {
+    pub name: String,
+    pub selector: Option<String>,
+    pub attribute: Option<String>,
+    pub coverage: f64,
+    pub sample: String,
+}
+
+/// Tag and classes of an element, the id of parents with one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Signature {
+    tag: String,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Signature {
+    fn of(element: &Element, with_id: bool) -> Self {
+        let mut classes: Vec<String> = element.classes.iter().filter(|class| is_identifier(class)).cloned().collect();
+        classes.sort();
+        classes.dedup();
+        Self {
+            tag: element.name.to_lowercase(),
+            id: element.id.clone().filter(|id| with_id && is_identifier(id)),
+            classes,
+        }
+    }
+
+    fn selector(&self) -> String {
+        match self.id {
+            Some(ref id) => format!("{}#{}", self.tag, id),
+            None => format!("{}{}", self.tag, self.classes.iter().map(|class| format!(".{}", class)).collect::<String>()),
+        }
+    }
+
+    fn scrape_options(&self) -> ScrapeOptions {
+        ScrapeOptions {
+            id_filter: self.id.as_ref().map(|id| IdFilter { filter: vec![id.clone()] }),
+            class_filter: match (&self.id, self.classes.is_empty()) {
+                (None, false) => Some(ClassFilter {
+                    filter: self.classes.clone(),
+                    filter_type: FilterType::And,
+                }),
+                _ => None,
+            },
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec![self.tag.clone()],
+                case_sensitive: None,
+            })
+        }
+    }
+}
+
+impl Candidate {
+    /// Options of `scrape` matching the records of the candidate inside its parent.
+    pub fn scrape_options(&self) -> ScrapeOptions {
+        ScrapeOptions {
+            ancestor: Some(Box::new(self.parent.scrape_options())),
+            ..self.record.scrape_options()
+        }
+    }
+
+    /// A schema extracting the suggested fields, for `CompiledQuery::from_schema`.
+    pub fn schema(&self) -> Schema {
+        Schema {
+            selector: self.selector.clone(),
+            fields: self.fields
+                .iter()
+                .map(|field| SchemaField {
+                    selector: field.selector.clone(),
+                    attribute: field.attribute.clone(),
+                    ..SchemaField::new(&field.name)
+                })
+                .collect(),
+        }
+    }
+}
+
+// elements that never hold records
+const IGNORED_TAGS: [&str; 12] = ["script", "style", "noscript", "template", "meta", "link", "br", "hr", "head", "svg", "path", "option"];
+
+/// Finds the repeated blocks of a page, product cards or search results for example, to
+/// bootstrap the options of a new site. Sibling elements sharing a tag and classes are
+/// grouped, with the groups of parents of the same tag and classes merged, and ranked on
+/// their number and the amount of text they hold. Best candidates come first.
+pub fn discover(raw_html: &str, mode: &ParseMode, options: &DiscoverOptions) -> Result<Vec<Candidate>, ParseError> {
+    let html = parse_html(raw_html, mode)?;
+    Ok(candidates_in_dom(&html, options))
+}
+
+/// Same as `discover` for an already parsed document.
+pub fn candidates_in_dom(html: &Dom, options: &DiscoverOptions) -> Vec<Candidate> {
+    let min_count = options.min_count.unwrap_or(3).max(2);
+    // groups in order of first appearance
+    let mut groups: Vec<((Signature, Signature), Vec<&Element>)> = vec![];
+    let mut positions: HashMap<(Signature, Signature), usize> = HashMap::new();
+    let mut pending: Vec<&Element> = html.children.iter().rev().filter_map(|node| node.element()).collect();
+    while let Some(parent) = pending.pop() {
+        let children: Vec<&Element> = parent.children.iter().filter_map(|node| node.element()).collect();
+        let signatures: Vec<Signature> = children.iter().map(|child| Signature::of(child, false)).collect();
+        for (child, signature) in children.iter().zip(signatures.iter()) {
+            // a single child, the title of every card for example, is not a list
+            let repeated = signatures.iter().filter(|other| *other == signature).count() > 1;
+            if !repeated || IGNORED_TAGS.contains(&signature.tag.as_str()) {
+                continue;
+            }
+            let key = (Signature::of(parent, true), signature.clone());
+            let position = *positions.entry(key.clone()).or_insert_with(|| {
+                groups.push((key, vec![]));
+                groups.len() - 1
+            });
+            groups[position].1.push(child);
+        }
+        pending.extend(children.into_iter().rev());
+    }
+
+    let mut candidates: Vec<Candidate> = groups
+        .into_iter()
+        .filter(|(_, records)| records.len() >= min_count)
+        .filter_map(|((parent, record), records)| {
+            let text_length: usize = records.iter().map(|record| text::visible_text(record, true).trim().chars().count()).sum();
+            let average_text_length = text_length / records.len();
+            if average_text_length == 0 {
+                return None;
+            }
+            Some(Candidate {
+                selector: format!("{} {}", parent.selector(), record.selector()),
+                count: records.len(),
+                average_text_length,
+                score: records.len() as f64 * (1.0 + average_text_length as f64).ln(),
+                fields: suggest_fields(&records, options.min_coverage.unwrap_or(0.5)),
+                sample: source(records[0]).into_owned(),
+                parent,
+                record,
+            })
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates.truncate(options.max_candidates.unwrap_or(10));
+    candidates
+}
+
+/// Text, links and images found in at least `min_coverage` of the records, in the order they
+/// first appear.
+fn suggest_fields(records: &[&Element], min_coverage: f64) -> Vec<SuggestedField> {
+    let mut found: Vec<(Option<String>, Option<String>, String, String)> = vec![];
+    let mut counts: HashMap<(Option<String>, Option<String>), usize> = HashMap::new();
+    for record in records.iter() {
+        let mut values = vec![];
+        collect_values(record, true, &mut values);
+        let mut seen = vec![];
+        for (selector, attribute, name, sample) in values {
+            let key = (selector.clone(), attribute.clone());
+            if seen.contains(&key) {
+                continue;
+            }
+            seen.push(key.clone());
+            let count = counts.entry(key).or_insert(0);
+            if *count == 0 {
+                found.push((selector, attribute, name, sample));
+            }
+            *count += 1;
+        }
+    }
+    let mut names: Vec<String> = vec![];
+    found
+        .into_iter()
+        .filter_map(|(selector, attribute, name, sample)| {
+            let coverage = counts[&(selector.clone(), attribute.clone())] as f64 / records.len() as f64;
+            if coverage < min_coverage {
+                return None;
+            }
+            let mut unique = name.clone();
+            let mut index = 1;
+            while names.contains(&unique) {
+                index += 1;
+                unique = format!("{}_{}", name, index);
+            }
+            names.push(unique.clone());
+            Some(SuggestedField {
+                name: unique,
+                selector,
+                attribute,
+                coverage,
+                sample,
+            })
+        })
+        .collect()
+}
+
+/// Values of `element` and its descendants as selector, attribute, proposed name and value.
+fn collect_values(element: &Element, is_record: bool, values: &mut Vec<(Option<String>, Option<String>, String, String)>) {
+    let tag = element.name.to_lowercase();
+    if IGNORED_TAGS.contains(&tag.as_str()) {
+        return;
+    }
+    let signature = Signature::of(element, false);
+    let selector = if is_record { None } else { Some(signature.selector()) };
+    let name = field_name(&signature);
+    let own_text = text::collapse_whitespace(&text::visible_text(element, false));
+    let only_text = own_text.trim();
+    if !only_text.is_empty() {
+        let has_element_children = element.children.iter().any(|node| node.element().is_some());
+        // text mixed with child elements is taken whole
+        let value = if has_element_children { text::collapse_whitespace(&text::visible_text(element, true)) } else { only_text.to_string() };
+        values.push((selector.clone(), None, name.clone(), value.trim().to_string()));
+    }
+    let link = match tag.as_str() {
+        "a" => Some(("href", "url")),
+        "img" => Some(("src", "image")),
+        _ => None,
+    };
+    if let Some((link_attribute, link_name)) = link {
+        if let Some(value) = attribute(element, link_attribute).filter(|value| !value.trim().is_empty()) {
+            let link_name = if signature.classes.is_empty() { link_name.to_string() } else { format!("{}_{}", name, link_name) };
+            values.push((selector, Some(link_attribute.to_string()), link_name, value.trim().to_string()));
+        }
+    }
+    for child in element.children.iter().filter_map(|node| node.element()) {
+        collect_values(child, false, values);
+    }
+}
+
+fn field_name(signature: &Signature) -> String {
+    if let Some(class) = signature.classes.first() {
+        return class.replace('-', "_");
+    }
+    match signature.tag.as_str() {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => "title".to_string(),
+        "a" => "link".to_string(),
+        "p" => "description".to_string(),
+        "img" => "image".to_string(),
+        tag => tag.to_string(),
+    }
+}
+
+/// Whether `value` can be written in a selector without escaping.
+fn is_identifier(value: &str) -> bool {
+    !value.is_empty()
+        && !value.starts_with(|c: char| c.is_ascii_digit())
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrapper::query::CompiledQuery;
+    use crate::scrapper::scrap::scrape;
+    use crate::storage::records::Value;
+
+    const PAGE: &str = r#"<html><body>
+        <nav><a href="/">Home</a><a href="/shop">Shop</a></nav>
+        <ul class="products" id="list">
+            <li class="product"><h2>Widget</h2><span class="price">10 EUR</span><a href="/p/1"><img src="/1.png"></a></li>
+            <li class="product"><h2>Gadget</h2><span class="price">12 EUR</span><a href="/p/2"><img src="/2.png"></a></li>
+            <li class="product"><h2>Doohickey with a long name</h2><a href="/p/3"><img src="/3.png"></a></li>
+            <li class="product"><h2>Gizmo</h2><span class="price">7 EUR</span><a href="/p/4"></a></li>
+        </ul>
+        <footer><p>One</p><p>Two</p><p>Three</p></footer>
+    </body></html>"#;
+
+    #[test]
+    fn test_discover_candidates() {
+        let candidates = discover(PAGE, &ParseMode::Strict, &DiscoverOptions::new()).unwrap();
+        assert_eq!(candidates.len(), 2);
+        let best = &candidates[0];
+        assert_eq!(best.selector, "ul#list li.product");
+        assert_eq!(best.count, 4);
+        assert!(best.sample.starts_with("<li class=\"product\"><h2>Widget</h2>"));
+        assert_eq!(candidates[1].selector, "footer p");
+
+        let fields: Vec<(&str, Option<&str>, Option<&str>)> = best.fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.selector.as_deref(), field.attribute.as_deref()))
+            .collect();
+        assert_eq!(fields, vec![
+            ("title", Some("h2"), None),
+            ("price", Some("span.price"), None),
+            ("url", Some("a"), Some("href")),
+            ("image", Some("img"), Some("src")),
+        ]);
+        assert_eq!(best.fields[1].coverage, 0.75);
+        assert_eq!(best.fields[1].sample, "10 EUR");
+
+        let strict = DiscoverOptions {
+            min_count: Some(4),
+            min_coverage: Some(1.0),
+            ..DiscoverOptions::new()
+        };
+        let candidates = discover(PAGE, &ParseMode::Strict, &strict).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].fields.len(), 2);
+    }
+
+    #[test]
+    fn test_candidate_options_and_schema() {
+        let best = &discover(PAGE, &ParseMode::Strict, &DiscoverOptions::new()).unwrap()[0];
+        assert_eq!(scrape(PAGE, &best.scrape_options()).unwrap().len(), 4);
+        let query = CompiledQuery::from_schema(&best.schema()).unwrap();
+        let records = query.records(PAGE).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].get("title"), Some(&Value::Text("Gadget".to_string())));
+        assert_eq!(records[1].get("url"), Some(&Value::Text("/p/2".to_string())));
+    }
+}
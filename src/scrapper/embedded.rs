@@ -0,0 +1,229 @@
+use std::sync::OnceLock;
+
+use html_parser::{Dom, Element, Node};
+use regex::Regex;
+
+use super::parse::{attribute, parse_html, ParseError, ParseMode};
+
+/// JSON data embedded in a `<script>` element.
+///
+/// * `name` - The variable the data is assigned to, such as `window.__INITIAL_STATE__` or
+///   `data` for `var data = ...`. For JSON script blocks, the `id` of the element, empty when
+///   it has none.
+/// * `value` - The parsed data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedJson {
+    pub name: String,
+    pub value: serde_json::Value,
+}
+
+// `window.x =`, `self.x.y =`, `globalThis.x =`, `var x =`, `let x =` and `const x =`
+const ASSIGNMENT: &str = r"(?:^|[^\w$.])((?:window|self|globalThis)(?:\.[\w$]+)+|(?:var|let|const)\s+[\w$]+)\s*=";
+
+/// Extracts the JSON embedded in the `<script>` elements of the page, in document order.
+///
+/// The content of scripts whose `type` is JSON, such as `application/json` or
+/// `application/ld+json`, is parsed whole. In other scripts, objects and arrays assigned to
+/// `window.*`, `self.*`, `globalThis.*` or declared variables are extracted, as well as
+/// `JSON.parse('...')` of a string literal. Values that are not valid JSON, object literals
+/// with unquoted keys for example, are skipped.
+pub fn extract_script_json(raw_html: &str, mode: &ParseMode) -> Result<Vec<EmbeddedJson>, ParseError> {
+    let html = parse_html(raw_html, mode)?;
+    Ok(script_json_in_dom(&html))
+}
+
+/// Same as `extract_script_json` for an already parsed document.
+pub fn script_json_in_dom(html: &Dom) -> Vec<EmbeddedJson> {
+    static ASSIGNMENT_REGEX: OnceLock<Regex> = OnceLock::new();
+    let assignment = ASSIGNMENT_REGEX.get_or_init(|| Regex::new(ASSIGNMENT).unwrap());
+    let mut found = vec![];
+    let mut pending: Vec<&Element> = html.children.iter().rev().filter_map(|node| node.element()).collect();
+    while let Some(element) = pending.pop() {
+        if element.name.eq_ignore_ascii_case("script") {
+            let source: String = element.children.iter().filter_map(Node::text).collect();
+            let kind = attribute(element, "type").unwrap_or_default().trim().to_lowercase();
+            if kind.ends_with("json") {
+                if let Ok(value) = serde_json::from_str(&source) {
+                    found.push(EmbeddedJson {
+                        name: element.id.clone().unwrap_or_default(),
+                        value,
+                    });
+                }
+            } else if kind.is_empty() || kind.contains("javascript") || kind == "module" {
+                found.extend(assigned_json(&source, assignment));
+            }
+        }
+        pending.extend(element.children.iter().rev().filter_map(|node| node.element()));
+    }
+    found
+}
+
+fn assigned_json(source: &str, assignment: &Regex) -> Vec<EmbeddedJson> {
+    let mut found = vec![];
+    let mut position = 0;
+    while let Some(captures) = assignment.captures_at(source, position) {
+        let whole = captures.get(0).unwrap();
+        position = whole.end();
+        let rest = &source[whole.end()..];
+        // `==`, `===` and `=>` are not assignments
+        if rest.starts_with('=') || rest.starts_with('>') {
+            continue;
+        }
+        let name = captures[1].split_whitespace().last().unwrap_or("").to_string();
+        let value = rest.trim_start();
+        let skipped = rest.len() - value.len();
+        let parsed = if let Some(argument) = value.strip_prefix("JSON.parse(") {
+            let literal = argument.trim_start();
+            let spaces = argument.len() - literal.len();
+            string_literal(literal).and_then(|(text, length)| {
+                Some((serde_json::from_str(&text).ok()?, "JSON.parse(".len() + spaces + length))
+            })
+        } else {
+            balanced_length(value).and_then(|length| Some((serde_json::from_str(&value[..length]).ok()?, length)))
+        };
+        if let Some((value, length)) = parsed {
+            found.push(EmbeddedJson { name, value });
+            // assignments inside the value, in its strings for example, are part of it
+            position = whole.end() + skipped + length;
+        }
+    }
+    found
+}
+
+/// Length of the object or array at the start of `value`, up to its closing bracket. Brackets
+/// inside strings do not count.
+fn balanced_length(value: &str) -> Option<usize> {
+    if !value.starts_with('{') && !value.starts_with('[') {
+        return None;
+    }
+    let mut depth = 0;
+    let mut chars = value.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            '"' | '\'' | '`' => {
+                let (_, length) = string_literal(&value[index..])?;
+                while chars.peek().is_some_and(|(next, _)| *next < index + length) {
+                    chars.next();
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The value and length of the JavaScript string literal at the start of `value`.
+fn string_literal(value: &str) -> Option<(String, usize)> {
+    let quote = value.chars().next().filter(|quote| matches!(quote, '"' | '\'' | '`'))?;
+    let mut text = String::new();
+    let mut chars = value.char_indices().skip(1);
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let (_, escaped) = chars.next()?;
+                match escaped {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    'r' => text.push('\r'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    '0' => text.push('\0'),
+                    'x' | 'u' => {
+                        let digits = &value[index + 2..];
+                        let (code, length) = if escaped == 'x' { hex_digits(digits, 2)? } else { unicode_escape(digits)? };
+                        // the digits are ASCII, one char per byte
+                        for _ in 0..length {
+                            chars.next();
+                        }
+                        // lone surrogates are not characters
+                        text.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    // line continuation
+                    '\n' => {}
+                    other => text.push(other),
+                }
+            }
+            c if c == quote => return Some((text, index + 1)),
+            c => text.push(c),
+        }
+    }
+    None
+}
+
+/// Code point and length of the `\u` escape whose digits start `rest`: `XXXX`, `{X...}` or
+/// the `XXXX\uXXXX` surrogate pair of a character outside of the basic plane.
+fn unicode_escape(rest: &str) -> Option<(u32, usize)> {
+    if let Some(braced) = rest.strip_prefix('{') {
+        let end = braced.find('}')?;
+        let (code, _) = hex_digits(braced, end)?;
+        return Some((code, end + 2));
+    }
+    let (high, length) = hex_digits(rest, 4)?;
+    if (0xd800..0xdc00).contains(&high) {
+        let low = rest[length..].strip_prefix("\\u").and_then(|low| hex_digits(low, 4));
+        if let Some((low, _)) = low.filter(|(low, _)| (0xdc00..0xe000).contains(low)) {
+            return Some((0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00), length + 6));
+        }
+    }
+    Some((high, length))
+}
+
+/// Value of the `count` hexadecimal digits at the start of `rest`.
+fn hex_digits(rest: &str, count: usize) -> Option<(u32, usize)> {
+    let digits = rest.get(..count).filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit()))?;
+    Some((u32::from_str_radix(digits, 16).ok()?, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_script_json() {
+        let html = r#"<html><head>
+            <script type="application/ld+json">{"@type": "Product", "name": "Widget"}</script>
+            <script id="__NEXT_DATA__" type="application/json">{"page": "/products"}</script>
+            <script>
+                window.__INITIAL_STATE__ = {"cart": {"items": [1, 2]}, "note": "a } in a string; window.x = {\"no\": 1}"};
+                var config = [{"key": "value"}];
+                const loose = {unquoted: true};
+                if (window.ready == {}) {}
+                self.__APOLLO__ = JSON.parse('{"user":{"id":7,"name":"O\'Neil é"}}');
+            </script>
+            <script type="text/template">window.template = {"ignored": true};</script>
+        </head><body></body></html>"#;
+        let found = extract_script_json(html, &ParseMode::Strict).unwrap();
+        let names: Vec<&str> = found.iter().map(|embedded| embedded.name.as_str()).collect();
+        assert_eq!(names, vec!["", "__NEXT_DATA__", "window.__INITIAL_STATE__", "config", "self.__APOLLO__"]);
+        assert_eq!(found[0].value["name"], "Widget");
+        assert_eq!(found[1].value, json!({"page": "/products"}));
+        assert_eq!(found[2].value["cart"]["items"], json!([1, 2]));
+        assert_eq!(found[3].value, json!([{"key": "value"}]));
+        assert_eq!(found[4].value["user"]["name"], "O'Neil é");
+    }
+
+    #[test]
+    fn test_string_literal_unicode_escapes() {
+        assert_eq!(string_literal(r#""\u00e9 \x41""#), Some(("é A".to_string(), 13)));
+        assert_eq!(string_literal(r#""\uD83D\uDE00!""#), Some(("😀!".to_string(), 15)));
+        assert_eq!(string_literal(r#""\u{1F600}""#), Some(("😀".to_string(), 11)));
+        assert_eq!(string_literal(r#""\uD83D""#), Some(("\u{fffd}".to_string(), 8)));
+        assert_eq!(string_literal(r#""\u{zz}""#), None);
+    }
+
+    #[test]
+    fn test_balanced_length() {
+        assert_eq!(balanced_length(r#"{"a": "}"} tail"#), Some(10));
+        assert_eq!(balanced_length("[1, [2]];"), Some(8));
+        assert_eq!(balanced_length("{\"a\": 1"), None);
+        assert_eq!(balanced_length("42"), None);
+    }
+}
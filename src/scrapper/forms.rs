@@ -0,0 +1,428 @@
+use html_parser::{Dom, Element, Node};
+use url::Url;
+
+use super::links::{base_url, resolve_url};
+use super::parse::{attribute, parse_html, ParseError, ParseMode};
+use super::text::{self, TextOptions};
+use crate::storage::records::{Record, Value};
+
+/// Names of hidden inputs carrying anti-forgery tokens, compared case insensitively.
+const CSRF_NAMES: [&str; 9] = [
+    "csrf",
+    "xsrf",
+    "_token",
+    "authenticity_token",
+    "__requestverificationtoken",
+    "csrfmiddlewaretoken",
+    "anticsrf",
+    "form_key",
+    "nonce",
+];
+
+/// A `<form>` of the page with the controls submitted with it.
+///
+/// `action` is resolved to an absolute URL and defaults to the page URL like browsers do.
+/// `method` is lowercased and defaults to `get`, `enctype` defaults to
+/// `application/x-www-form-urlencoded`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Form {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub action: String,
+    pub method: String,
+    pub enctype: String,
+    pub fields: Vec<FormField>,
+}
+
+/// A control of a form: `<input>`, `<select>`, `<textarea>` or `<button>`.
+///
+/// # Fields
+///
+/// * `tag` - Lowercased tag name of the control.
+/// * `field_type` - `type` of inputs and buttons, lowercased, defaulting to `text` and
+///   `submit`. `select`, `select-multiple` or `textarea` for the other controls.
+/// * `value` - The default value: the `value` attribute, the text of a `<textarea>` or the
+///   value of the selected option of a `<select>`.
+/// * `options` - The options of a `<select>`, including those in `<optgroup>`.
+/// * `label` - Text of the `<label for>` pointing at the control or of the label wrapping it.
+/// * `csrf_token` - Whether the control is a hidden input whose name looks like an anti-forgery
+///   token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormField {
+    pub tag: String,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub field_type: String,
+    pub value: Option<String>,
+    pub options: Vec<SelectOption>,
+    pub label: Option<String>,
+    pub required: bool,
+    pub disabled: bool,
+    pub checked: bool,
+    pub csrf_token: bool,
+}
+
+/// An `<option>` of a `<select>`. `value` defaults to the text of the option.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectOption {
+    pub value: String,
+    pub text: String,
+    pub selected: bool,
+}
+
+impl Form {
+    /// The name and value pairs the form submits when sent unchanged: controls with a name that
+    /// are not disabled, checked checkboxes and radio buttons, the selected options of selects.
+    /// Buttons, file and image inputs are left out.
+    pub fn default_values(&self) -> Vec<(String, String)> {
+        let mut values = vec![];
+        for field in self.fields.iter().filter(|field| !field.disabled) {
+            let Some(ref name) = field.name else {
+                continue;
+            };
+            match field.field_type.as_str() {
+                "submit" | "reset" | "button" | "image" | "file" => {}
+                "checkbox" | "radio" => {
+                    if field.checked {
+                        values.push((name.clone(), field.value.clone().unwrap_or_else(|| "on".to_string())));
+                    }
+                }
+                "select-multiple" => {
+                    for option in field.options.iter().filter(|option| option.selected) {
+                        values.push((name.clone(), option.value.clone()));
+                    }
+                }
+                _ => values.push((name.clone(), field.value.clone().unwrap_or_default())),
+            }
+        }
+        values
+    }
+
+    /// The URL a `get` form navigates to, with the default values replaced by `values` of the
+    /// same name and other `values` appended. `None` for other methods or an invalid action.
+    pub fn submission_url(&self, values: &[(&str, &str)]) -> Option<Url> {
+        if self.method != "get" {
+            return None;
+        }
+        let mut url = Url::parse(&self.action).ok()?;
+        let mut pairs: Vec<(String, String)> = self.default_values()
+            .into_iter()
+            .filter(|(name, _)| !values.iter().any(|(key, _)| key == name))
+            .collect();
+        pairs.extend(values.iter().map(|(name, value)| (name.to_string(), value.to_string())));
+        url.set_fragment(None);
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+        Some(url)
+    }
+
+    /// The CSRF token fields of the form as name and value pairs.
+    pub fn csrf_tokens(&self) -> Vec<(String, String)> {
+        self.fields
+            .iter()
+            .filter(|field| field.csrf_token)
+            .filter_map(|field| Some((field.name.clone()?, field.value.clone().unwrap_or_default())))
+            .collect()
+    }
+
+    /// One record per field, with the form `action`, `method` and `enctype` repeated in each.
+    pub fn to_records(&self) -> Vec<Record> {
+        let flag = |value: bool| Value::Text(value.to_string());
+        self.fields
+            .iter()
+            .map(|field| {
+                let mut record = Record::new();
+                record.insert("action", Value::Text(self.action.clone()));
+                record.insert("method", Value::Text(self.method.clone()));
+                record.insert("enctype", Value::Text(self.enctype.clone()));
+                record.insert("name", Value::Text(field.name.clone().unwrap_or_default()));
+                record.insert("type", Value::Text(field.field_type.clone()));
+                record.insert("value", Value::Text(field.value.clone().unwrap_or_default()));
+                record.insert("options", Value::List(field.options.iter().map(|option| option.value.clone()).collect()));
+                record.insert("label", Value::Text(field.label.clone().unwrap_or_default()));
+                record.insert("required", flag(field.required));
+                record.insert("csrf_token", flag(field.csrf_token));
+                record
+            })
+            .collect()
+    }
+}
+
+/// Lists every form of the page in document order with its fields.
+///
+/// Controls belong to their enclosing `<form>` or, listed after its own controls, to the form
+/// named by their `form` attribute. Controls of no form are left out. Relative actions are
+/// resolved against `<base href>` and `page_url`.
+pub fn extract_forms(raw_html: &str, page_url: &Url, mode: &ParseMode) -> Result<Vec<Form>, ParseError> {
+    let html = parse_html(raw_html, mode)?;
+    Ok(forms_in_dom(&html, page_url))
+}
+
+/// Same as `extract_forms` for an already parsed document.
+pub fn forms_in_dom(html: &Dom, page_url: &Url) -> Vec<Form> {
+    let base_url = base_url(html, page_url);
+    let mut collector = Collector {
+        base_url,
+        forms: vec![],
+        // controls with a `form` attribute are assigned once every form id is known
+        detached: vec![],
+        labels: vec![],
+    };
+    for node in html.children.iter() {
+        if let Node::Element(element) = node {
+            collector.collect(element, None, None);
+        }
+    }
+    let Collector { mut forms, detached, labels, .. } = collector;
+    for (form_id, field) in detached {
+        if let Some(form) = forms.iter_mut().find(|form| form.id.as_deref() == Some(form_id.as_str())) {
+            form.fields.push(field);
+        }
+    }
+    // labels may follow the control they point at
+    for field in forms.iter_mut().flat_map(|form| form.fields.iter_mut()) {
+        if field.label.is_none() {
+            if let Some(ref id) = field.id {
+                field.label = labels.iter().find(|(target, _)| target == id).map(|(_, text)| text.clone());
+            }
+        }
+    }
+    forms
+}
+
+struct Collector {
+    base_url: Url,
+    forms: Vec<Form>,
+    detached: Vec<(String, FormField)>,
+    // `for` target and text of every `<label for>`
+    labels: Vec<(String, String)>,
+}
+
+impl Collector {
+    fn collect(&mut self, element: &Element, form: Option<usize>, label: Option<&str>) {
+        let name = element.name.to_lowercase();
+        match name.as_str() {
+            "form" => {
+                let action = attribute(element, "action")
+                    .filter(|action| !action.trim().is_empty())
+                    .and_then(|action| resolve_url(&self.base_url, &action))
+                    .unwrap_or_else(|| String::from(self.base_url.clone()));
+                self.forms.push(Form {
+                    id: element.id.clone(),
+                    name: attribute(element, "name"),
+                    action,
+                    method: attribute(element, "method").map_or("get".to_string(), |method| method.trim().to_lowercase()),
+                    enctype: attribute(element, "enctype")
+                        .map_or("application/x-www-form-urlencoded".to_string(), |enctype| enctype.trim().to_lowercase()),
+                    fields: vec![],
+                });
+                let index = self.forms.len() - 1;
+                self.collect_children(element, Some(index), label);
+                return;
+            }
+            "label" => {
+                let text = text::visible_text(element, true);
+                if let Some(target) = attribute(element, "for") {
+                    self.labels.push((target.to_string(), text));
+                    self.collect_children(element, form, None);
+                } else {
+                    self.collect_children(element, form, Some(&text));
+                }
+                return;
+            }
+            "input" | "select" | "textarea" | "button" => {
+                let mut field = field(element, &name);
+                field.label = label.map(str::to_string);
+                match (attribute(element, "form"), form) {
+                    (Some(form_id), _) => self.detached.push((form_id.to_string(), field)),
+                    (None, Some(index)) => self.forms[index].fields.push(field),
+                    (None, None) => {}
+                }
+                return;
+            }
+            _ => {}
+        }
+        self.collect_children(element, form, label);
+    }
+
+    fn collect_children(&mut self, element: &Element, form: Option<usize>, label: Option<&str>) {
+        for child in element.children.iter() {
+            if let Node::Element(child) = child {
+                self.collect(child, form, label);
+            }
+        }
+    }
+}
+
+fn field(element: &Element, tag: &str) -> FormField {
+    let has = |name: &str| element.attributes.keys().any(|key| key.eq_ignore_ascii_case(name));
+    let name = attribute(element, "name");
+    let mut options = vec![];
+    let (field_type, value) = match tag {
+        "select" => {
+            collect_options(element, &mut options);
+            let multiple = has("multiple");
+            if !multiple && !options.iter().any(|option| option.selected) {
+                if let Some(first) = options.first_mut() {
+                    first.selected = true;
+                }
+            }
+            let value = options.iter().find(|option| option.selected).map(|option| option.value.clone());
+            (if multiple { "select-multiple" } else { "select" }.to_string(), value)
+        }
+        "textarea" => {
+            let text = text::extract_text(element, &TextOptions {
+                collapse_whitespace: Some(false),
+                ..TextOptions::new()
+            });
+            // the newline right after the start tag is not part of the value
+            let text = text.strip_prefix('\n').map(str::to_string).unwrap_or(text);
+            ("textarea".to_string(), Some(text))
+        }
+        _ => {
+            let default_type = if tag == "button" { "submit" } else { "text" };
+            let field_type = attribute(element, "type")
+                .map(|field_type| field_type.trim().to_lowercase())
+                .filter(|field_type| !field_type.is_empty())
+                .unwrap_or_else(|| default_type.to_string());
+            (field_type, attribute(element, "value"))
+        }
+    };
+    let csrf_token = field_type == "hidden"
+        && name.as_ref().is_some_and(|name| {
+            let name = name.to_lowercase();
+            CSRF_NAMES.iter().any(|token| name.contains(token))
+        });
+    FormField {
+        tag: tag.to_string(),
+        id: element.id.clone(),
+        name,
+        field_type,
+        value,
+        options,
+        label: None,
+        required: has("required"),
+        disabled: has("disabled"),
+        checked: has("checked"),
+        csrf_token,
+    }
+}
+
+fn collect_options(element: &Element, options: &mut Vec<SelectOption>) {
+    for child in element.children.iter() {
+        let Node::Element(child) = child else {
+            continue;
+        };
+        if child.name.eq_ignore_ascii_case("option") {
+            let text = text::visible_text(child, true);
+            options.push(SelectOption {
+                value: attribute(child, "value").unwrap_or_else(|| text.clone()),
+                text,
+                selected: child.attributes.keys().any(|key| key.eq_ignore_ascii_case("selected")),
+            });
+        } else if child.name.eq_ignore_ascii_case("optgroup") {
+            collect_options(child, options);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<html><body>
+        <form action="/search" id="search">
+            <label>Query <input name="q" value="rust" required></label>
+            <select name="sort">
+                <option value="relevance">Relevance</option>
+                <optgroup label="Date"><option value="new" selected>Newest</option><option>Oldest</option></optgroup>
+            </select>
+            <input type="checkbox" name="exact" checked>
+            <input type="checkbox" name="archived" value="yes">
+            <button>Search</button>
+        </form>
+        <form method="POST" action="https://accounts.example.com/login" enctype="multipart/form-data">
+            <input type="hidden" name="authenticity_token" value="abc123">
+            <label for="user">User name</label><input id="user" name="user">
+            <textarea name="note">
+Hello &amp; welcome</textarea>
+            <input type="password" name="password" disabled>
+        </form>
+        <input name="page" value="2" form="search">
+        <input name="orphan">
+    </body></html>"#;
+
+    fn forms() -> Vec<Form> {
+        let page_url = Url::parse("https://example.com/blog/post.html?x=1").unwrap();
+        extract_forms(PAGE, &page_url, &ParseMode::Strict).unwrap()
+    }
+
+    #[test]
+    fn test_extract_forms() {
+        let forms = forms();
+        assert_eq!(forms.len(), 2);
+        let search = &forms[0];
+        assert_eq!(search.action, "https://example.com/search");
+        assert_eq!(search.method, "get");
+        assert_eq!(search.enctype, "application/x-www-form-urlencoded");
+        let names: Vec<Option<&str>> = search.fields.iter().map(|field| field.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("q"), Some("sort"), Some("exact"), Some("archived"), None, Some("page")]);
+        assert_eq!(search.fields[0].label.as_deref(), Some("Query"));
+        assert!(search.fields[0].required);
+        assert_eq!(search.fields[1].value.as_deref(), Some("new"));
+        assert_eq!(search.fields[1].options[2], SelectOption {
+            value: "Oldest".to_string(),
+            text: "Oldest".to_string(),
+            selected: false,
+        });
+        assert_eq!(search.fields[4].field_type, "submit");
+
+        let login = &forms[1];
+        assert_eq!(login.method, "post");
+        assert_eq!(login.enctype, "multipart/form-data");
+        assert_eq!(login.csrf_tokens(), vec![("authenticity_token".to_string(), "abc123".to_string())]);
+        assert_eq!(login.fields[1].label.as_deref(), Some("User name"));
+        assert_eq!(login.fields[2].value.as_deref(), Some("Hello & welcome"));
+        assert!(login.fields[3].disabled);
+    }
+
+    #[test]
+    fn test_form_default_values_and_submission_url() {
+        let forms = forms();
+        let pairs = |values: &[(&str, &str)]| -> Vec<(String, String)> {
+            values.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+        };
+        assert_eq!(forms[0].default_values(), pairs(&[("q", "rust"), ("sort", "new"), ("exact", "on"), ("page", "2")]));
+        let url = forms[0].submission_url(&[("q", "html parser"), ("page", "3")]).unwrap();
+        assert_eq!(url.as_str(), "https://example.com/search?sort=new&exact=on&q=html+parser&page=3");
+        assert_eq!(forms[1].submission_url(&[]), None);
+        assert_eq!(forms[1].default_values(), pairs(&[("authenticity_token", "abc123"), ("user", ""), ("note", "Hello & welcome")]));
+    }
+
+    #[test]
+    fn test_form_decodes_entities() {
+        let raw_html = r#"<form action="/s?lang=en&amp;v=2">
+            <input type="hidden" name="token" value="a&amp;b">
+            <select name="q"><option value="x&amp;y" selected>X &amp; Y</option></select>
+        </form>"#;
+        let page_url = Url::parse("https://example.com/").unwrap();
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let form = &extract_forms(raw_html, &page_url, &mode).unwrap()[0];
+            assert_eq!(form.action, "https://example.com/s?lang=en&v=2");
+            assert_eq!(form.default_values(), vec![
+                ("token".to_string(), "a&b".to_string()),
+                ("q".to_string(), "x&y".to_string()),
+            ]);
+            let url = form.submission_url(&[]).unwrap();
+            assert_eq!(url.as_str(), "https://example.com/s?token=a%26b&q=x%26y");
+        }
+    }
+
+    #[test]
+    fn test_form_to_records() {
+        let records = forms()[1].to_records();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].get("csrf_token"), Some(&Value::Text("true".to_string())));
+        assert_eq!(records[0].get("method"), Some(&Value::Text("post".to_string())));
+        assert_eq!(records[1].get("label"), Some(&Value::Text("User name".to_string())));
+    }
+}
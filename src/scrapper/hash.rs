@@ -0,0 +1,4 @@
+/// 64 bit FNV-1a, stable across runs and platforms unlike the standard library hasher.
+pub(crate) fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
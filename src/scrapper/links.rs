@@ -0,0 +1,321 @@
+use html_parser::{Dom, Element, Node};
+use url::Url;
+
+use super::parse::{attribute, parse_html, ParseError, ParseMode};
+use super::text;
+use crate::storage::records::{Record, Value};
+
+/// What a `Link` points to.
+///
+/// * `Anchor` - `<a href>` and `<area href>`.
+/// * `Image` - `<img src>`, `<img srcset>`, `<picture><source srcset>` and `<video poster>`.
+/// * `Script` - `<script src>`.
+/// * `Stylesheet` - `<link rel="stylesheet" href>`.
+/// * `Media` - `src` of `<video>`, `<audio>`, `<track>`, `<embed>` and their `<source>` children.
+/// * `Link` - Any other `<link href>` such as icons, canonical or preload links.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkKind {
+    Anchor,
+    Image,
+    Script,
+    Stylesheet,
+    Media,
+    Link,
+}
+
+impl LinkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkKind::Anchor => "anchor",
+            LinkKind::Image => "image",
+            LinkKind::Script => "script",
+            LinkKind::Stylesheet => "stylesheet",
+            LinkKind::Media => "media",
+            LinkKind::Link => "link",
+        }
+    }
+}
+
+/// A URL referenced by the page, resolved to an absolute URL.
+///
+/// `text` is the visible text of anchors and the `alt` text of images, empty for other kinds.
+/// `tag` and `attribute` tell where the URL was found, for example `img` and `srcset`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub kind: LinkKind,
+    pub url: String,
+    pub text: String,
+    pub rel: Vec<String>,
+    pub tag: String,
+    pub attribute: String,
+}
+
+impl Link {
+    pub fn to_record(&self) -> Record {
+        let mut record = Record::new();
+        record.insert("kind", Value::Text(self.kind.as_str().to_string()));
+        record.insert("url", Value::Text(self.url.clone()));
+        record.insert("text", Value::Text(self.text.clone()));
+        record.insert("rel", Value::List(self.rel.clone()));
+        record.insert("tag", Value::Text(self.tag.clone()));
+        record.insert("attribute", Value::Text(self.attribute.clone()));
+        record
+    }
+}
+
+/// Extracts every link, image, script, stylesheet and media source of the page in document order.
+///
+/// Relative URLs are resolved against the first `<base href>` of the document, itself resolved
+/// against `page_url`. URLs that can not be resolved are skipped.
+pub fn extract_links(raw_html: &str, page_url: &Url, mode: &ParseMode) -> Result<Vec<Link>, ParseError> {
+    let html = parse_html(raw_html, mode)?;
+    Ok(links_in_dom(&html, page_url))
+}
+
+/// Same as `extract_links` for an already parsed document.
+pub fn links_in_dom(html: &Dom, page_url: &Url) -> Vec<Link> {
+    let base_url = base_url(html, page_url);
+    let mut links = vec![];
+    for node in html.children.iter() {
+        if let Node::Element(element) = node {
+            collect_links(element, "", &base_url, &mut links);
+        }
+    }
+    links
+}
+
+/// Resolves `href` against `base_url`, ignoring surrounding whitespace like browsers do.
+pub fn resolve_url(base_url: &Url, href: &str) -> Option<String> {
+    base_url.join(href.trim()).ok().map(String::from)
+}
+
+pub(crate) fn base_url(html: &Dom, page_url: &Url) -> Url {
+    let mut pending: Vec<&Node> = html.children.iter().rev().collect();
+    while let Some(node) = pending.pop() {
+        if let Node::Element(element) = node {
+            if element.name.eq_ignore_ascii_case("base") {
+                if let Some(base) = attribute(element, "href").and_then(|href| page_url.join(href.trim()).ok()) {
+                    return base;
+                }
+            }
+            element.children.iter().rev().for_each(|child| pending.push(child));
+        }
+    }
+    page_url.clone()
+}
+
+fn collect_links(element: &Element, parent: &str, base_url: &Url, links: &mut Vec<Link>) {
+    let name = element.name.to_lowercase();
+    let rel: Vec<String> = attribute(element, "rel")
+        .map(|rel| rel.split_whitespace().map(|value| value.to_lowercase()).collect())
+        .unwrap_or_default();
+    let mut push = |kind: LinkKind, attribute_name: &str, href: &str, text: String| {
+        if let Some(url) = resolve_url(base_url, href) {
+            links.push(Link {
+                kind,
+                url,
+                text,
+                rel: rel.clone(),
+                tag: name.clone(),
+                attribute: attribute_name.to_string(),
+            });
+        }
+    };
+    let alt = || attribute(element, "alt").map(|alt| text::collapse_whitespace(&alt)).unwrap_or_default();
+    match name.as_str() {
+        "a" | "area" => {
+            if let Some(href) = attribute(element, "href") {
+                let anchor_text = if name == "a" { text::visible_text(element, true) } else { alt() };
+                push(LinkKind::Anchor, "href", &href, anchor_text);
+            }
+        }
+        "link" => {
+            if let Some(href) = attribute(element, "href") {
+                let kind = if rel.iter().any(|value| value == "stylesheet") {
+                    LinkKind::Stylesheet
+                } else {
+                    LinkKind::Link
+                };
+                push(kind, "href", &href, String::new());
+            }
+        }
+        "script" => {
+            if let Some(src) = attribute(element, "src") {
+                push(LinkKind::Script, "src", &src, String::new());
+            }
+        }
+        "img" => {
+            if let Some(src) = attribute(element, "src") {
+                push(LinkKind::Image, "src", &src, alt());
+            }
+            for candidate in srcset_urls(&attribute(element, "srcset").unwrap_or_default()) {
+                push(LinkKind::Image, "srcset", candidate, alt());
+            }
+        }
+        "video" | "audio" | "track" | "embed" => {
+            if let Some(src) = attribute(element, "src") {
+                push(LinkKind::Media, "src", &src, String::new());
+            }
+            if let Some(poster) = attribute(element, "poster") {
+                push(LinkKind::Image, "poster", &poster, String::new());
+            }
+        }
+        "source" => {
+            let kind = if parent == "picture" { LinkKind::Image } else { LinkKind::Media };
+            if let Some(src) = attribute(element, "src") {
+                push(kind.clone(), "src", &src, String::new());
+            }
+            for candidate in srcset_urls(&attribute(element, "srcset").unwrap_or_default()) {
+                push(kind.clone(), "srcset", candidate, String::new());
+            }
+        }
+        _ => {}
+    }
+    for child in element.children.iter() {
+        if let Node::Element(child) = child {
+            collect_links(child, &name, base_url, links);
+        }
+    }
+}
+
+/// URLs of a `srcset` attribute, `"a.png 1x, b.png 2x"` gives `a.png` and `b.png`.
+///
+/// Commas only separate candidates after a descriptor or whitespace, a URL may contain commas
+/// as in `/img/a,b.jpg 1x` or `data:image/png;base64,...`. Commas at the end of a URL are
+/// separators.
+fn srcset_urls(srcset: &str) -> Vec<&str> {
+    let mut urls = vec![];
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            return urls;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let url = rest[..end].trim_end_matches(',');
+        let has_descriptors = url.len() == end;
+        rest = &rest[end..];
+        if has_descriptors {
+            // descriptors run up to the next comma outside of parentheses
+            let mut depth = 0;
+            let end = rest
+                .char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        ',' => return depth <= 0,
+                        _ => {}
+                    }
+                    false
+                })
+                .map_or(rest.len(), |(index, _)| index);
+            rest = &rest[end..];
+        }
+        if !url.is_empty() {
+            urls.push(url);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(raw_html: &str) -> Vec<Link> {
+        let page_url = Url::parse("https://example.com/blog/post.html").unwrap();
+        extract_links(raw_html, &page_url, &ParseMode::Strict).unwrap()
+    }
+
+    #[test]
+    fn test_extract_links_anchors() {
+        let result = links(r#"<div><a href='/about' rel='nofollow Author'>About <b>us</b></a><a href='other.html'>Next</a><a>no href</a></div>"#);
+        assert_eq!(result, vec![
+            Link {
+                kind: LinkKind::Anchor,
+                url: "https://example.com/about".to_string(),
+                text: "About us".to_string(),
+                rel: vec!["nofollow".to_string(), "author".to_string()],
+                tag: "a".to_string(),
+                attribute: "href".to_string(),
+            },
+            Link {
+                kind: LinkKind::Anchor,
+                url: "https://example.com/blog/other.html".to_string(),
+                text: "Next".to_string(),
+                rel: vec![],
+                tag: "a".to_string(),
+                attribute: "href".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_extract_links_base_href() {
+        let result = links(r#"<html><head><base href='https://cdn.example.org/assets/'></head><body><a href='page'>x</a></body></html>"#);
+        assert_eq!(result[0].url, "https://cdn.example.org/assets/page");
+    }
+
+    #[test]
+    fn test_extract_links_assets() {
+        let raw_html = r#"
+            <div>
+                <link rel='stylesheet' href='/css/site.css'>
+                <link rel='icon' href='favicon.ico'>
+                <script src='//static.example.com/app.js'></script>
+                <img src='a.png' srcset='a-1x.png 1x, a-2x.png 2x' alt='A picture'>
+                <picture><source srcset='b.webp'></picture>
+                <video src='clip.mp4' poster='poster.jpg'><source src='clip.webm'></video>
+            </div>
+        "#;
+        let result: Vec<(LinkKind, String, String)> = links(raw_html)
+            .into_iter()
+            .map(|link| (link.kind, link.url, link.attribute))
+            .collect();
+        let expected = vec![
+            (LinkKind::Stylesheet, "https://example.com/css/site.css", "href"),
+            (LinkKind::Link, "https://example.com/blog/favicon.ico", "href"),
+            (LinkKind::Script, "https://static.example.com/app.js", "src"),
+            (LinkKind::Image, "https://example.com/blog/a.png", "src"),
+            (LinkKind::Image, "https://example.com/blog/a-1x.png", "srcset"),
+            (LinkKind::Image, "https://example.com/blog/a-2x.png", "srcset"),
+            (LinkKind::Image, "https://example.com/blog/b.webp", "srcset"),
+            (LinkKind::Media, "https://example.com/blog/clip.mp4", "src"),
+            (LinkKind::Image, "https://example.com/blog/poster.jpg", "poster"),
+            (LinkKind::Media, "https://example.com/blog/clip.webm", "src"),
+        ];
+        let expected: Vec<(LinkKind, String, String)> = expected
+            .into_iter()
+            .map(|(kind, url, attribute)| (kind, url.to_string(), attribute.to_string()))
+            .collect();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_extract_links_decodes_entities() {
+        let raw_html = r#"<div><a href="/s?a=1&amp;b=2">search</a><img srcset="/i?w=1&amp;h=2 1x"></div>"#;
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let page_url = Url::parse("https://example.com/").unwrap();
+            let urls: Vec<String> = extract_links(raw_html, &page_url, &mode).unwrap().into_iter().map(|link| link.url).collect();
+            assert_eq!(urls, vec!["https://example.com/s?a=1&b=2", "https://example.com/i?w=1&h=2"]);
+        }
+    }
+
+    #[test]
+    fn test_srcset_urls() {
+        assert_eq!(srcset_urls("a.png 1x, b.png 2x"), vec!["a.png", "b.png"]);
+        assert_eq!(srcset_urls("/img/a,b.jpg 1x, /img/c.jpg 2x"), vec!["/img/a,b.jpg", "/img/c.jpg"]);
+        assert_eq!(srcset_urls("data:image/png;base64,iVBO= 1x,b.png 2x"), vec!["data:image/png;base64,iVBO=", "b.png"]);
+        assert_eq!(srcset_urls("a.png,b.png, c.png 100w"), vec!["a.png,b.png", "c.png"]);
+        assert_eq!(srcset_urls("a.png, b.png"), vec!["a.png", "b.png"]);
+        assert_eq!(srcset_urls(" , "), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_link_to_record() {
+        let record = links("<a href='/x' rel='next'>X</a>")[0].to_record();
+        assert_eq!(record.get("kind"), Some(&Value::Text("anchor".to_string())));
+        assert_eq!(record.get("url"), Some(&Value::Text("https://example.com/x".to_string())));
+        assert_eq!(record.get("rel"), Some(&Value::List(vec!["next".to_string()])));
+    }
+}
@@ -0,0 +1,343 @@
+use html_parser::{Dom, Element, Node};
+use serde_json::{json, Map};
+
+use super::parse::{attribute, parse_html, ParseError, ParseMode};
+use super::text;
+
+/// Structured metadata of a page.
+///
+/// * `title` - Text of the first `<title>`.
+/// * `description`, `keywords` - Content of `<meta name="description">` and `<meta name="keywords">`.
+/// * `meta` - Every `<meta>` with a `name`, `property` or `http-equiv` and a `content`, in document order.
+/// * `open_graph` - `<meta property="og:*">` tags, with the `og:` prefix removed.
+/// * `twitter` - `<meta name="twitter:*">` tags, with the `twitter:` prefix removed.
+/// * `json_ld` - Every `<script type="application/ld+json">` block that is valid JSON.
+/// * `microdata` - Top level schema.org microdata items (`itemscope`/`itemprop`).
+/// * `rdfa` - Top level RDFa items (`typeof`/`property`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+    pub meta: Vec<(String, String)>,
+    pub open_graph: Vec<(String, String)>,
+    pub twitter: Vec<(String, String)>,
+    pub json_ld: Vec<serde_json::Value>,
+    pub microdata: Vec<Item>,
+    pub rdfa: Vec<Item>,
+}
+
+/// A microdata or RDFa item, properties keep document order and can repeat.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Item {
+    pub item_type: Vec<String>,
+    pub id: Option<String>,
+    pub properties: Vec<(String, PropertyValue)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Text(String),
+    Item(Item),
+}
+
+impl PageMetadata {
+    /// Converts the metadata into a JSON value. Repeated keys become arrays.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "title": self.title,
+            "description": self.description,
+            "keywords": self.keywords,
+            "meta": pairs_to_json(&self.meta),
+            "open_graph": pairs_to_json(&self.open_graph),
+            "twitter": pairs_to_json(&self.twitter),
+            "json_ld": self.json_ld,
+            "microdata": self.microdata.iter().map(Item::to_json).collect::<Vec<serde_json::Value>>(),
+            "rdfa": self.rdfa.iter().map(Item::to_json).collect::<Vec<serde_json::Value>>(),
+        })
+    }
+}
+
+impl Item {
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut properties: Vec<(String, serde_json::Value)> = vec![];
+        for (name, value) in self.properties.iter() {
+            let value = match value {
+                PropertyValue::Text(text) => json!(text),
+                PropertyValue::Item(item) => item.to_json(),
+            };
+            properties.push((name.clone(), value));
+        }
+        let mut object = match grouped_to_json(properties) {
+            serde_json::Value::Object(object) => object,
+            _ => Map::new(),
+        };
+        object.insert("@type".to_string(), json!(self.item_type));
+        if let Some(ref id) = self.id {
+            object.insert("@id".to_string(), json!(id));
+        }
+        serde_json::Value::Object(object)
+    }
+}
+
+/// Extracts the title, meta tags, OpenGraph and Twitter card tags, JSON-LD blocks and
+/// microdata/RDFa items of the page.
+pub fn extract_metadata(raw_html: &str, mode: &ParseMode) -> Result<PageMetadata, ParseError> {
+    let html = parse_html(raw_html, mode)?;
+    Ok(metadata_in_dom(&html))
+}
+
+/// Same as `extract_metadata` for an already parsed document.
+pub fn metadata_in_dom(html: &Dom) -> PageMetadata {
+    let mut metadata = PageMetadata::default();
+    let mut pending: Vec<&Element> = top_elements(&html.children);
+    while let Some(element) = pending.pop() {
+        match element.name.to_lowercase().as_str() {
+            "title" if metadata.title.is_none() => metadata.title = Some(text::visible_text(element, true)),
+            "meta" => collect_meta(element, &mut metadata),
+            "script" if attribute(element, "type").is_some_and(|kind| kind.trim().eq_ignore_ascii_case("application/ld+json")) => {
+                let source: String = element.children.iter().filter_map(|node| node.text()).collect();
+                if let Ok(value) = serde_json::from_str(&source) {
+                    metadata.json_ld.push(value);
+                }
+            }
+            _ => {}
+        }
+        if has_attribute(element, "itemscope") && !has_attribute(element, "itemprop") {
+            metadata.microdata.push(build_item(element, &MICRODATA));
+        }
+        if has_attribute(element, "typeof") && !has_attribute(element, "property") {
+            metadata.rdfa.push(build_item(element, &RDFA));
+        }
+        pending.extend(top_elements(&element.children));
+    }
+    metadata
+}
+
+/// Attribute names of a structured data syntax.
+struct Syntax {
+    scope: &'static str,
+    item_type: &'static str,
+    id: &'static str,
+    property: &'static str,
+}
+
+const MICRODATA: Syntax = Syntax {
+    scope: "itemscope",
+    item_type: "itemtype",
+    id: "itemid",
+    property: "itemprop",
+};
+
+const RDFA: Syntax = Syntax {
+    scope: "typeof",
+    item_type: "typeof",
+    id: "resource",
+    property: "property",
+};
+
+fn build_item(element: &Element, syntax: &Syntax) -> Item {
+    let mut item_type: Vec<String> = attribute(element, syntax.item_type)
+        .map(|types| types.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+    // RDFa types are relative to the closest `vocab`, only the element's own is used here
+    if let Some(vocab) = attribute(element, "vocab") {
+        item_type = item_type
+            .into_iter()
+            .map(|kind| if kind.contains(':') { kind } else { format!("{}{}", vocab, kind) })
+            .collect();
+    }
+    let mut item = Item {
+        item_type,
+        id: attribute(element, syntax.id),
+        properties: vec![],
+    };
+    collect_properties(element, syntax, &mut item);
+    item
+}
+
+fn collect_properties(element: &Element, syntax: &Syntax, item: &mut Item) {
+    for child in top_elements(&element.children).into_iter().rev() {
+        let names: Vec<String> = attribute(child, syntax.property)
+            .map(|names| names.split_whitespace().map(String::from).collect())
+            .unwrap_or_default();
+        let is_scope = has_attribute(child, syntax.scope);
+        if !names.is_empty() {
+            let value = if is_scope {
+                PropertyValue::Item(build_item(child, syntax))
+            } else {
+                PropertyValue::Text(property_value(child, syntax))
+            };
+            for name in names {
+                item.properties.push((name, value.clone()));
+            }
+        }
+        // properties inside a nested item belong to that item
+        if !is_scope {
+            collect_properties(child, syntax, item);
+        }
+    }
+}
+
+fn property_value(element: &Element, syntax: &Syntax) -> String {
+    if let Some(content) = attribute(element, "content") {
+        return content;
+    }
+    let url_attribute = match element.name.to_lowercase().as_str() {
+        "audio" | "embed" | "iframe" | "img" | "source" | "track" | "video" => Some("src"),
+        "a" | "area" | "link" => Some("href"),
+        "object" => Some("data"),
+        "data" | "meter" => Some("value"),
+        "time" => Some("datetime"),
+        _ if syntax.property == "property" => Some("resource"),
+        _ => None,
+    };
+    url_attribute
+        .and_then(|name| attribute(element, name))
+        .unwrap_or_else(|| text::visible_text(element, true))
+}
+
+fn collect_meta(element: &Element, metadata: &mut PageMetadata) {
+    let Some(content) = attribute(element, "content") else {
+        return;
+    };
+    let Some(key) = attribute(element, "property")
+        .or_else(|| attribute(element, "name"))
+        .or_else(|| attribute(element, "http-equiv")) else {
+        return;
+    };
+    let key = key.trim().to_string();
+    let content = content.trim().to_string();
+    let lowercase_key = key.to_lowercase();
+    if lowercase_key == "description" && metadata.description.is_none() {
+        metadata.description = Some(content.clone());
+    }
+    if lowercase_key == "keywords" {
+        metadata.keywords.extend(
+            content.split(',').map(str::trim).filter(|keyword| !keyword.is_empty()).map(String::from),
+        );
+    }
+    if let Some(property) = lowercase_key.strip_prefix("og:") {
+        metadata.open_graph.push((property.to_string(), content.clone()));
+    }
+    if let Some(property) = lowercase_key.strip_prefix("twitter:") {
+        metadata.twitter.push((property.to_string(), content.clone()));
+    }
+    metadata.meta.push((key, content));
+}
+
+/// Child elements in reverse document order, ready to be used as a stack.
+fn top_elements(nodes: &[Node]) -> Vec<&Element> {
+    nodes.iter().rev().filter_map(|node| node.element()).collect()
+}
+
+fn has_attribute(element: &Element, name: &str) -> bool {
+    attribute(element, name).is_some()
+}
+
+fn pairs_to_json(pairs: &[(String, String)]) -> serde_json::Value {
+    grouped_to_json(pairs.iter().map(|(key, value)| (key.clone(), json!(value))).collect())
+}
+
+fn grouped_to_json(pairs: Vec<(String, serde_json::Value)>) -> serde_json::Value {
+    let mut object = Map::new();
+    for (key, value) in pairs {
+        match object.get_mut(&key) {
+            Some(serde_json::Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = json!([existing.clone(), value]),
+            None => {
+                object.insert(key, value);
+            }
+        }
+    }
+    serde_json::Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_metadata_meta_tags() {
+        let raw_html = r#"<html><head>
+            <title>Blue Widget &amp; Co</title>
+            <meta name="description" content="The best widget">
+            <meta name="keywords" content="widget, blue ,tools">
+            <meta property="og:title" content="Blue Widget &amp; Co">
+            <meta property="og:image" content="https://example.com/a.png">
+            <meta property="og:image" content="https://example.com/b.png">
+            <meta name="twitter:card" content="summary">
+        </head><body></body></html>"#;
+        let metadata = extract_metadata(raw_html, &ParseMode::Strict).unwrap();
+        assert_eq!(metadata.title, Some("Blue Widget & Co".to_string()));
+        assert_eq!(metadata.description, Some("The best widget".to_string()));
+        assert_eq!(metadata.keywords, vec!["widget", "blue", "tools"]);
+        assert_eq!(metadata.open_graph, vec![
+            ("title".to_string(), "Blue Widget & Co".to_string()),
+            ("image".to_string(), "https://example.com/a.png".to_string()),
+            ("image".to_string(), "https://example.com/b.png".to_string()),
+        ]);
+        assert_eq!(metadata.twitter, vec![("card".to_string(), "summary".to_string())]);
+        assert_eq!(metadata.meta.len(), 6);
+        assert_eq!(metadata.to_json()["open_graph"]["image"], json!(["https://example.com/a.png", "https://example.com/b.png"]));
+    }
+
+    #[test]
+    fn test_extract_metadata_json_ld() {
+        let raw_html = r#"<div>
+            <script type="application/ld+json">{"@type": "Product", "name": "Widget", "offers": {"price": "9.99"}}</script>
+            <script type="application/ld+json">{ not json }</script>
+        </div>"#;
+        let metadata = extract_metadata(raw_html, &ParseMode::Strict).unwrap();
+        assert_eq!(metadata.json_ld.len(), 1);
+        assert_eq!(metadata.json_ld[0]["offers"]["price"], json!("9.99"));
+    }
+
+    #[test]
+    fn test_extract_metadata_microdata() {
+        let raw_html = r#"
+            <div itemscope itemtype="https://schema.org/Product">
+                <h1 itemprop="name">Widget</h1>
+                <img itemprop="image" src="widget.png">
+                <div itemprop="offers" itemscope itemtype="https://schema.org/Offer">
+                    <span itemprop="price" content="9.99">$9.99</span>
+                    <meta itemprop="priceCurrency" content="USD">
+                </div>
+                <time itemprop="releaseDate" datetime="2024-01-31">January</time>
+            </div>
+        "#;
+        let metadata = extract_metadata(raw_html, &ParseMode::Strict).unwrap();
+        assert_eq!(metadata.microdata.len(), 1);
+        let product = &metadata.microdata[0];
+        assert_eq!(product.item_type, vec!["https://schema.org/Product"]);
+        assert_eq!(product.properties[0], ("name".to_string(), PropertyValue::Text("Widget".to_string())));
+        assert_eq!(product.properties[1], ("image".to_string(), PropertyValue::Text("widget.png".to_string())));
+        let PropertyValue::Item(ref offer) = product.properties[2].1 else {
+            panic!("offers should be a nested item");
+        };
+        assert_eq!(offer.properties, vec![
+            ("price".to_string(), PropertyValue::Text("9.99".to_string())),
+            ("priceCurrency".to_string(), PropertyValue::Text("USD".to_string())),
+        ]);
+        assert_eq!(product.properties[3], ("releaseDate".to_string(), PropertyValue::Text("2024-01-31".to_string())));
+        assert_eq!(product.to_json()["offers"]["price"], json!("9.99"));
+    }
+
+    #[test]
+    fn test_extract_metadata_rdfa() {
+        let raw_html = r#"
+            <div vocab="https://schema.org/" typeof="Person">
+                <span property="name">Ada Lovelace</span>
+                <a property="url" href="https://example.com/ada">home</a>
+                <div property="address" typeof="PostalAddress"><span property="addressLocality">London</span></div>
+            </div>
+        "#;
+        let metadata = extract_metadata(raw_html, &ParseMode::Strict).unwrap();
+        assert_eq!(metadata.rdfa.len(), 1);
+        let person = &metadata.rdfa[0];
+        assert_eq!(person.item_type, vec!["https://schema.org/Person"]);
+        assert_eq!(person.properties[0], ("name".to_string(), PropertyValue::Text("Ada Lovelace".to_string())));
+        assert_eq!(person.properties[1], ("url".to_string(), PropertyValue::Text("https://example.com/ada".to_string())));
+        assert_eq!(person.to_json()["address"]["addressLocality"], json!("London"));
+    }
+}
@@ -1 +1,18 @@
-pub mod scrap;
\ No newline at end of file
+pub mod batch;
+pub mod dedup;
+pub mod discover;
+pub mod embedded;
+pub mod forms;
+pub(crate) mod hash;
+pub mod links;
+pub mod metadata;
+pub mod navigate;
+pub mod parse;
+pub mod provenance;
+pub mod query;
+pub mod readability;
+pub mod scrap;
+pub mod stream;
+pub mod table;
+pub mod text;
+pub mod transform;
\ No newline at end of file
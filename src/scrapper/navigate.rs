@@ -0,0 +1,363 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use html_parser::{Dom, Element, Node};
+
+use super::parse::{attribute, parse_html, source, ParseError, ParseMode};
+use super::query::{CompiledFilter, QueryError};
+use super::scrap::{matching_elements, Namespace, ScrapeOptions};
+use super::text;
+
+/// A parsed document whose matches keep their place in the tree, so that the elements around
+/// them can be reached.
+pub struct Document {
+    dom: Dom,
+}
+
+impl Document {
+    pub fn parse(raw_html: &str, mode: &ParseMode) -> Result<Self, ParseError> {
+        Ok(Self::from_dom(parse_html(raw_html, mode)?))
+    }
+
+    pub fn from_dom(dom: Dom) -> Self {
+        Self { dom }
+    }
+
+    pub fn dom(&self) -> &Dom {
+        &self.dom
+    }
+
+    /// The top level elements of the document.
+    pub fn roots(&self) -> Vec<ElementRef<'_>> {
+        element_children(&self.dom.children)
+            .map(|(index, element)| ElementRef {
+                dom: &self.dom,
+                path: vec![(index, element)],
+            })
+            .collect()
+    }
+
+    /// The elements `scrape` returns for `options`, in the same order. `parse_mode` is ignored,
+    /// the document is already parsed.
+    pub fn select(&self, options: &ScrapeOptions) -> Vec<ElementRef<'_>> {
+        let matches = matching_elements(&self.dom, options);
+        let wanted: HashSet<*const Element> = matches.iter().map(|element| *element as *const Element).collect();
+        let mut found = vec![];
+        // matches come in document order, so does a preorder traversal
+        let mut pending: Vec<Vec<(usize, &Element)>> = element_children(&self.dom.children)
+            .rev()
+            .map(|child| vec![child])
+            .collect();
+        while let Some(path) = pending.pop() {
+            if found.len() == wanted.len() {
+                break;
+            }
+            let (_, element) = path[path.len() - 1];
+            if wanted.contains(&(element as *const Element)) {
+                found.push(ElementRef {
+                    dom: &self.dom,
+                    path: path.clone(),
+                });
+            }
+            for child in element_children(&element.children).rev() {
+                let mut child_path = path.clone();
+                child_path.push(child);
+                pending.push(child_path);
+            }
+        }
+        found
+    }
+}
+
+/// An element of a `Document` with the path leading to it.
+///
+/// Only element nodes are navigated, text and comments between them are skipped: the next
+/// sibling of a `<dt>` is its `<dd>` whatever whitespace separates them. `closest`, `find` and
+/// `find_all` use the element and text filters of their `ScrapeOptions`, its `offset`, `limit`,
+/// `ancestor` and tree constraints are ignored. Unlike `scrape`, they return a `QueryError` for
+/// an invalid regular expression instead of matching nothing.
+#[derive(Debug, Clone)]
+pub struct ElementRef<'a> {
+    dom: &'a Dom,
+    // from the top level element down to this one, with the index of every element among the
+    // nodes of its parent
+    path: Vec<(usize, &'a Element)>,
+}
+
+impl PartialEq for ElementRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.element(), other.element())
+    }
+}
+
+impl<'a> ElementRef<'a> {
+    pub fn element(&self) -> &'a Element {
+        self.path[self.path.len() - 1].1
+    }
+
+    /// Lowercased tag name.
+    pub fn name(&self) -> String {
+        self.element().name.to_lowercase()
+    }
+
+    /// Value of the attribute `name`, `id` and `class` included, with its character references decoded.
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        attribute(self.element(), name)
+    }
+
+    /// The source of the element, as returned by `scrape`.
+    pub fn html(&self) -> Cow<'a, str> {
+        source(self.element())
+    }
+
+    /// The visible text of the element and its descendants.
+    pub fn text(&self) -> String {
+        text::visible_text(self.element(), true)
+    }
+
+    /// CSS path of the element from its top level element, such as
+    /// `html > body > ul > li:nth-of-type(2)`. `:nth-of-type` is only added when siblings share
+    /// the tag name.
+    pub fn css_path(&self) -> String {
+        (1..=self.path.len())
+            .map(|length| {
+                let step = self.with_path(self.path[..length].to_vec());
+                let name = step.name();
+                let same_name: Vec<usize> = element_children(step.siblings())
+                    .filter(|(_, sibling)| sibling.name.eq_ignore_ascii_case(&name))
+                    .map(|(index, _)| index)
+                    .collect();
+                if same_name.len() < 2 {
+                    return name;
+                }
+                let (index, _) = step.path[length - 1];
+                let position = same_name.iter().position(|sibling| *sibling == index).unwrap_or(0);
+                format!("{}:nth-of-type({})", name, position + 1)
+            })
+            .collect::<Vec<String>>()
+            .join(" > ")
+    }
+
+    pub fn depth(&self) -> usize {
+        self.path.len() - 1
+    }
+
+    pub fn parent(&self) -> Option<ElementRef<'a>> {
+        if self.path.len() < 2 {
+            return None;
+        }
+        Some(self.with_path(self.path[..self.path.len() - 1].to_vec()))
+    }
+
+    /// The parent, its parent and so on up to the top level element.
+    pub fn ancestors(&self) -> Vec<ElementRef<'a>> {
+        (1..self.path.len()).rev().map(|length| self.with_path(self.path[..length].to_vec())).collect()
+    }
+
+    pub fn children(&self) -> Vec<ElementRef<'a>> {
+        element_children(&self.element().children).map(|child| self.child(child)).collect()
+    }
+
+    pub fn next_sibling(&self) -> Option<ElementRef<'a>> {
+        let (index, _) = self.path[self.path.len() - 1];
+        element_children(self.siblings()).find(|(sibling, _)| *sibling > index).map(|sibling| self.sibling(sibling))
+    }
+
+    pub fn prev_sibling(&self) -> Option<ElementRef<'a>> {
+        let (index, _) = self.path[self.path.len() - 1];
+        element_children(self.siblings()).rev().find(|(sibling, _)| *sibling < index).map(|sibling| self.sibling(sibling))
+    }
+
+    /// The following siblings, nearest first.
+    pub fn next_siblings(&self) -> Vec<ElementRef<'a>> {
+        let (index, _) = self.path[self.path.len() - 1];
+        element_children(self.siblings()).filter(|(sibling, _)| *sibling > index).map(|sibling| self.sibling(sibling)).collect()
+    }
+
+    /// The preceding siblings, nearest first.
+    pub fn prev_siblings(&self) -> Vec<ElementRef<'a>> {
+        let (index, _) = self.path[self.path.len() - 1];
+        element_children(self.siblings()).rev().filter(|(sibling, _)| *sibling < index).map(|sibling| self.sibling(sibling)).collect()
+    }
+
+    /// The element itself or its nearest ancestor matching `options`.
+    pub fn closest(&self, options: &ScrapeOptions) -> Result<Option<ElementRef<'a>>, QueryError> {
+        let filter = CompiledFilter::from_options(options)?;
+        Ok((1..=self.path.len())
+            .rev()
+            .map(|length| self.with_path(self.path[..length].to_vec()))
+            .find(|candidate| candidate.matches_filter(&filter, options)))
+    }
+
+    /// The first descendant matching `options`, in document order.
+    pub fn find(&self, options: &ScrapeOptions) -> Result<Option<ElementRef<'a>>, QueryError> {
+        Ok(self.descendants(options, true)?.into_iter().next())
+    }
+
+    /// Every descendant matching `options`, in document order.
+    pub fn find_all(&self, options: &ScrapeOptions) -> Result<Vec<ElementRef<'a>>, QueryError> {
+        self.descendants(options, false)
+    }
+
+    /// Whether the element itself matches the element and text filters of `options`.
+    pub fn matches(&self, options: &ScrapeOptions) -> Result<bool, QueryError> {
+        Ok(self.matches_filter(&CompiledFilter::from_options(options)?, options))
+    }
+
+    fn matches_filter(&self, filter: &CompiledFilter, options: &ScrapeOptions) -> bool {
+        filter.matches(self.element(), self.namespace(), options.exclude.as_deref().unwrap_or(&[]))
+    }
+
+    fn namespace(&self) -> Namespace {
+        let mut namespace = Namespace::Html;
+        for (position, (_, element)) in self.path.iter().enumerate() {
+            namespace = namespace.of(&element.name);
+            if position < self.path.len() - 1 {
+                namespace = namespace.for_children(&element.name);
+            }
+        }
+        namespace
+    }
+
+    fn descendants(&self, options: &ScrapeOptions, first: bool) -> Result<Vec<ElementRef<'a>>, QueryError> {
+        let filter = CompiledFilter::from_options(options)?;
+        let mut found = vec![];
+        let mut pending: Vec<ElementRef<'a>> = self.children().into_iter().rev().collect();
+        while let Some(candidate) = pending.pop() {
+            if candidate.matches_filter(&filter, options) {
+                found.push(candidate.clone());
+                if first {
+                    break;
+                }
+            }
+            pending.extend(candidate.children().into_iter().rev());
+        }
+        Ok(found)
+    }
+
+    /// The nodes among which this element is, those of its parent or of the document.
+    fn siblings(&self) -> &'a [Node] {
+        match self.path.len() {
+            1 => &self.dom.children,
+            length => &self.path[length - 2].1.children,
+        }
+    }
+
+    fn with_path(&self, path: Vec<(usize, &'a Element)>) -> ElementRef<'a> {
+        ElementRef { dom: self.dom, path }
+    }
+
+    fn child(&self, child: (usize, &'a Element)) -> ElementRef<'a> {
+        let mut path = self.path.clone();
+        path.push(child);
+        self.with_path(path)
+    }
+
+    fn sibling(&self, sibling: (usize, &'a Element)) -> ElementRef<'a> {
+        let mut path = self.path.clone();
+        let last = path.len() - 1;
+        path[last] = sibling;
+        self.with_path(path)
+    }
+}
+
+fn element_children(nodes: &[Node]) -> impl DoubleEndedIterator<Item = (usize, &Element)> {
+    nodes.iter().enumerate().filter_map(|(index, node)| node.element().map(|element| (index, element)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrapper::scrap::{ClassFilter, FilterType, MatchMode, TagFilter, TextFilter};
+
+    fn tags(filter: &[&str]) -> ScrapeOptions {
+        ScrapeOptions::new(TagFilter {
+            filter: filter.iter().map(|tag| tag.to_string()).collect(),
+            case_sensitive: None,
+        })
+    }
+
+    const PAGE: &str = r#"<div class="product" id="p1">
+        <h2>Widget</h2>
+        <dl>
+            <dt>Price</dt>
+            <dd>10 EUR</dd>
+            <dt>Stock</dt>
+            <!-- updated hourly -->
+            <dd>3</dd>
+        </dl>
+        <p><label>Color</label> <span>Red</span></p>
+    </div>"#;
+
+    #[test]
+    fn test_siblings_and_parent() {
+        let document = Document::parse(PAGE, &ParseMode::Strict).unwrap();
+        let terms = document.select(&tags(&["dt"]));
+        let pairs: Vec<(String, String)> = terms
+            .iter()
+            .map(|term| (term.text(), term.next_sibling().unwrap().text()))
+            .collect();
+        assert_eq!(pairs, vec![
+            ("Price".to_string(), "10 EUR".to_string()),
+            ("Stock".to_string(), "3".to_string()),
+        ]);
+        assert_eq!(terms[1].prev_sibling().unwrap().text(), "10 EUR");
+        assert_eq!(terms[0].prev_sibling(), None);
+        assert_eq!(terms[0].next_siblings().len(), 3);
+        assert_eq!(terms[1].prev_siblings().iter().map(|sibling| sibling.name()).collect::<Vec<String>>(), vec!["dd", "dt"]);
+
+        let value = &document.select(&tags(&["span"]))[0];
+        assert_eq!(value.prev_sibling().unwrap().text(), "Color");
+        assert_eq!(value.parent().unwrap().name(), "p");
+        assert_eq!(value.depth(), 2);
+        assert_eq!(value.ancestors().iter().map(|ancestor| ancestor.name()).collect::<Vec<String>>(), vec!["p", "div"]);
+        assert_eq!(document.roots()[0].parent(), None);
+        assert_eq!(terms[1].css_path(), "div > dl > dt:nth-of-type(2)");
+        assert_eq!(value.css_path(), "div > p > span");
+    }
+
+    #[test]
+    fn test_closest_and_find() {
+        let document = Document::parse(PAGE, &ParseMode::Strict).unwrap();
+        let price = &document.select(&tags(&["dd"]))[0];
+        let product = price
+            .closest(&ScrapeOptions {
+                class_filter: Some(ClassFilter {
+                    filter: vec!["product".to_string()],
+                    filter_type: FilterType::And,
+                }),
+                ..tags(&["*"])
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(product.attribute("id").as_deref(), Some("p1"));
+        assert_eq!(product.find(&tags(&["h2"])).unwrap().unwrap().text(), "Widget");
+        assert_eq!(product.find_all(&tags(&["dt", "dd"])).unwrap().len(), 4);
+        assert_eq!(product.children().len(), 3);
+        assert!(price.closest(&tags(&["table"])).unwrap().is_none());
+        // the element itself is its closest match
+        assert_eq!(price.closest(&tags(&["dd"])).unwrap().as_ref(), Some(price));
+        assert_eq!(price.matches(&tags(&["dd"])), Ok(true));
+        assert!(product.html().starts_with("<div class=\"product\""));
+    }
+
+    #[test]
+    fn test_find_invalid_regex() {
+        let document = Document::parse(PAGE, &ParseMode::Strict).unwrap();
+        let product = &document.roots()[0];
+        let options = ScrapeOptions {
+            text_include: Some(TextFilter {
+                filter: vec!["(".to_string()],
+                filter_type: FilterType::And,
+                match_mode: Some(MatchMode::Regex),
+                case_insensitive: None,
+                text_source: None,
+            }),
+            ..tags(&["dd"])
+        };
+        assert!(product.find(&options).is_err());
+        assert!(product.find_all(&options).is_err());
+        assert!(product.closest(&options).is_err());
+        assert!(product.matches(&options).is_err());
+    }
+}
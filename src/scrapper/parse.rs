@@ -0,0 +1,303 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::panic;
+use std::sync::OnceLock;
+
+use html5ever::tendril::TendrilSink;
+use html_parser::{Dom, DomVariant, Element, ElementVariant, Node};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use regex::Regex;
+
+use super::text;
+
+/// How raw HTML is turned into a `Dom`.
+///
+/// * `Strict` - Uses the `html_parser` grammar, markup it can not parse is reported as a `ParseError`. Default.
+///   An internal assertion failing in the parser is reported as a `ParseError` too, but the panic
+///   hook still prints its message to stderr first.
+/// * `Lenient` - Uses an HTML5 spec compliant tree builder that recovers from broken markup the way
+///   browsers do: unclosed tags are closed, stray text is kept and doctype nodes are dropped.
+///   Elements keep no source, it is serialized from the recovered tree when read, so it has no
+///   line or column information.
+///
+/// Both modes give the same representation: text and attribute values are kept as written in the
+/// source, with their character references, and are decoded once when read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseMode {
+    Strict,
+    Lenient,
+}
+
+/// Error returned when HTML can not be parsed. `line` and `column` are 1-based and only present
+/// when the parser reported where the error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl ParseError {
+    pub fn new(message: &str) -> Self {
+        static POSITION: OnceLock<Regex> = OnceLock::new();
+        let position = POSITION
+            .get_or_init(|| Regex::new(r"-->\s*(\d+):(\d+)").unwrap())
+            .captures(message)
+            .map(|found| (found[1].parse().ok(), found[2].parse().ok()));
+        let (line, column) = position.unwrap_or((None, None));
+        Self {
+            message: message.trim().to_string(),
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "parse error at {}:{}: {}", line, column, self.message),
+            _ => write!(f, "parse error: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses `raw_html` with the given `mode`.
+pub fn parse_html(raw_html: &str, mode: &ParseMode) -> Result<Dom, ParseError> {
+    match mode {
+        ParseMode::Strict => parse_strict(raw_html),
+        ParseMode::Lenient => Ok(parse_lenient(raw_html)),
+    }
+}
+
+/// Parses a single scraped element, as returned by `scrape`.
+pub fn parse_element(raw_html: &str) -> Result<Element, ParseError> {
+    parse_strict(raw_html)?
+        .children
+        .into_iter()
+        .find_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+        .ok_or_else(|| ParseError::new("no element found"))
+}
+
+fn parse_strict(raw_html: &str) -> Result<Dom, ParseError> {
+    // the grammar based parser has internal assertions, a malformed document must not bring the
+    // whole process down with it. The panic hook is global and left alone, so the assertion
+    // message is still printed to stderr; no input is known to reach one
+    match panic::catch_unwind(|| Dom::parse(raw_html)) {
+        Ok(Ok(dom)) => Ok(dom),
+        Ok(Err(error)) => Err(ParseError::new(&error.to_string())),
+        Err(_) => Err(ParseError::new("the parser failed on malformed input")),
+    }
+}
+
+fn parse_lenient(raw_html: &str) -> Dom {
+    let rc_dom = html5ever::parse_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .read_from(&mut raw_html.as_bytes())
+        .unwrap(); // reading from a byte slice can not fail
+    Dom {
+        tree_type: DomVariant::Document,
+        children: convert_children(&rc_dom.document, false),
+        errors: rc_dom.errors.iter().map(|error| error.to_string()).collect(),
+    }
+}
+
+pub(crate) const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// The tree builder decodes character references, they are escaped again so lenient and strict
+/// documents hold text and attribute values the same way. `raw_text` is set for the children of
+/// `<script>` and `<style>`, whose text is never decoded.
+fn convert_children(handle: &Handle, raw_text: bool) -> Vec<Node> {
+    handle.children.borrow().iter().filter_map(|child| convert_node(child, raw_text)).collect()
+}
+
+fn convert_node(handle: &Handle, raw_text: bool) -> Option<Node> {
+    match handle.data {
+        NodeData::Text { ref contents } if raw_text => Some(Node::Text(contents.borrow().to_string())),
+        NodeData::Text { ref contents } => Some(Node::Text(escape_text(&contents.borrow()))),
+        NodeData::Comment { ref contents } => Some(Node::Comment(contents.to_string())),
+        NodeData::Element { ref name, ref attrs, ref template_contents, .. } => {
+            let name = name.local.to_string();
+            let mut element = Element {
+                name: name.clone(),
+                variant: if VOID_ELEMENTS.contains(&name.as_str()) {
+                    ElementVariant::Void
+                } else {
+                    ElementVariant::Normal
+                },
+                ..Element::default()
+            };
+            for attribute in attrs.borrow().iter() {
+                let key = attribute.name.local.to_string();
+                let value = escape_attribute(&attribute.value);
+                match key.as_str() {
+                    "id" => element.id = Some(value),
+                    "class" => element.classes = value.split_whitespace().map(String::from).collect(),
+                    _ => {
+                        element.attributes.insert(key, Some(value));
+                    }
+                }
+            }
+            let raw_text = RAW_TEXT_ELEMENTS.contains(&name.as_str());
+            element.children = match template_contents.borrow().as_ref() {
+                Some(contents) => convert_children(contents, raw_text),
+                None => convert_children(handle, raw_text),
+            };
+            Some(Node::Element(element))
+        }
+        NodeData::Document | NodeData::Doctype { .. } | NodeData::ProcessingInstruction { .. } => None,
+    }
+}
+
+/// The source of `element`, as returned by `scrape`. Elements of a lenient document have no
+/// source text, theirs is serialized from the tree.
+pub(crate) fn source(element: &Element) -> Cow<'_, str> {
+    if element.source_span.text.is_empty() {
+        Cow::Owned(outer_html(element))
+    } else {
+        Cow::Borrowed(&element.source_span.text)
+    }
+}
+
+/// Serializes `element` and its descendants from the tree, ignoring `source_span`. Used for
+/// elements whose children were modified after parsing.
+pub(crate) fn outer_html(element: &Element) -> String {
+    serialize_element(element)
+}
+
+/// The source of the content of `element`, its start and end tags left out.
+pub(crate) fn inner_html(element: &Element) -> String {
+    if let ElementVariant::Void = element.variant {
+        return String::new();
+    }
+    let source = source(element);
+    // the start tag ends at the first `>` outside of a quoted attribute value
+    let mut quote = None;
+    let start = source.char_indices().find_map(|(index, c)| {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '>') => return Some(index + 1),
+            _ => {}
+        }
+        None
+    });
+    let Some(start) = start else {
+        return String::new();
+    };
+    let content = &source[start..];
+    match content.rfind("</") {
+        Some(end) if content[end + 2..].trim_end_matches('>').trim().eq_ignore_ascii_case(&element.name) => content[..end].to_string(),
+        _ => content.to_string(),
+    }
+}
+
+/// Text and attribute values are already escaped in the tree, only the quotes of values that
+/// were written between single quotes need escaping.
+fn serialize_element(element: &Element) -> String {
+    let mut html = format!("<{}", element.name);
+    if let Some(ref id) = element.id {
+        html.push_str(&format!(r#" id="{}""#, escape_quotes(id)));
+    }
+    if !element.classes.is_empty() {
+        html.push_str(&format!(r#" class="{}""#, escape_quotes(&element.classes.join(" "))));
+    }
+    let mut attributes: Vec<(&String, &Option<String>)> = element.attributes.iter().collect();
+    attributes.sort();
+    for (key, value) in attributes {
+        match value {
+            Some(value) => html.push_str(&format!(r#" {}="{}""#, key, escape_quotes(value))),
+            None => html.push_str(&format!(" {}", key)),
+        }
+    }
+    html.push('>');
+    if let ElementVariant::Void = element.variant {
+        return html;
+    }
+    for child in element.children.iter() {
+        match child {
+            Node::Text(text) => html.push_str(text),
+            Node::Comment(comment) => html.push_str(&format!("<!--{}-->", comment)),
+            Node::Element(child) => html.push_str(&outer_html(child)),
+        }
+    }
+    html.push_str(&format!("</{}>", element.name));
+    html
+}
+
+/// Value of the attribute `name` of `element` with its character references decoded, `&amp;`
+/// becomes `&`. `id` and `class` are included and valueless attributes have an empty value. Every
+/// module reads attributes through it, the `Dom` keeps them as written in the source.
+pub(crate) fn attribute(element: &Element, name: &str) -> Option<String> {
+    let value = match name.to_lowercase().as_str() {
+        "id" => element.id.clone(),
+        "class" if !element.classes.is_empty() => Some(element.classes.join(" ")),
+        _ => element.attributes
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone().unwrap_or_default()),
+    };
+    value.map(|value| text::decode_entities(&value))
+}
+
+pub(crate) fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub(crate) fn escape_attribute(value: &str) -> String {
+    escape_quotes(&value.replace('&', "&amp;"))
+}
+
+fn escape_quotes(value: &str) -> String {
+    value.replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_position() {
+        let error = parse_html("<!-- never closed", &ParseMode::Strict).unwrap_err();
+        assert_eq!(error.line, Some(1));
+        assert_eq!(error.column, Some(2));
+        assert!(error.to_string().starts_with("parse error at 1:2"));
+    }
+
+    #[test]
+    fn test_parse_error_without_position() {
+        let error = parse_html("<p>x</p><body>y</body>", &ParseMode::Strict).unwrap_err();
+        assert_eq!(error.line, None);
+        assert_eq!(error.message, "A document fragment should not include body");
+    }
+
+    #[test]
+    fn test_parse_lenient_recovers() {
+        let dom = parse_html("<!DOCTYPE html>stray<div class='a b'>one<p>two<br></div><body>", &ParseMode::Lenient).unwrap();
+        let html = dom.children[0].element().unwrap();
+        assert_eq!(html.name, "html");
+        let body = html.children[1].element().unwrap();
+        assert_eq!(body.name, "body");
+        assert_eq!(body.children[0].text(), Some("stray"));
+        let div = body.children[1].element().unwrap();
+        assert_eq!(div.classes, vec!["a", "b"]);
+        assert_eq!(source(div), r#"<div class="a b">one<p>two<br></p></div>"#);
+    }
+
+    #[test]
+    fn test_parse_element() {
+        let element = parse_element("<span id='x'>text</span>").unwrap();
+        assert_eq!(element.id, Some("x".to_string()));
+        assert!(parse_element("only text").is_err());
+    }
+}
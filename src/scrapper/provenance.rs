@@ -0,0 +1,153 @@
+use html_parser::Element;
+
+use super::hash::fnv1a;
+use super::navigate::Document;
+use super::parse::{ParseError, ParseMode};
+use super::scrap::{parse_document, project, Output, ScrapeOptions};
+use crate::fetcher::fetch::FetchedPage;
+
+/// Where a scraped element comes from, written by `store_with_metadata` when
+/// `include_metadata` is set.
+///
+/// # Fields
+///
+/// * `source_url` - URL of the page, after redirects. `None` for pages that were not fetched.
+/// * `fetched_at` - RFC 3339 timestamp in UTC of the response.
+/// * `status` - HTTP status code of the response.
+/// * `path` - CSS path of the element in the document, see `ElementRef::css_path`.
+/// * `offset` - Byte offset of the element in the document. Always `None` with
+///   `ParseMode::Lenient`, whose element sources are re-serialized and have no position.
+/// * `hash` - 64 bit FNV-1a hash of the scraped value, in hexadecimal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    pub source_url: Option<String>,
+    pub fetched_at: Option<String>,
+    pub status: Option<u16>,
+    pub path: String,
+    pub offset: Option<usize>,
+    pub hash: String,
+}
+
+impl Provenance {
+    /// Names of the provenance fields, in the order they are written.
+    pub const FIELDS: [&'static str; 6] = ["source_url", "fetched_at", "status", "path", "offset", "hash"];
+
+    /// Value of every field of `FIELDS`, `None` when unknown.
+    pub fn values(&self) -> Vec<(&'static str, Option<String>)> {
+        let values = [
+            self.source_url.clone(),
+            self.fetched_at.clone(),
+            self.status.map(|status| status.to_string()),
+            Some(self.path.clone()),
+            self.offset.map(|offset| offset.to_string()),
+            Some(self.hash.clone()),
+        ];
+        Self::FIELDS.into_iter().zip(values).collect()
+    }
+
+    /// Provenance of a fetched element, shared by the tests of the storage formats.
+    #[cfg(test)]
+    pub(crate) fn sample() -> Provenance {
+        Provenance {
+            source_url: Some("https://example.com/".to_string()),
+            fetched_at: None,
+            status: Some(200),
+            path: "body > div".to_string(),
+            offset: Some(12),
+            hash: "00ff".to_string(),
+        }
+    }
+}
+
+/// Same as `scrape`, with the provenance of every scraped value at the same index. `page`
+/// gives the URL, time and status of the response when the document was fetched with
+/// `fetch_with_metadata`.
+pub fn scrape_with_provenance(
+    raw_html: &str,
+    options: &ScrapeOptions,
+    page: Option<&FetchedPage>,
+) -> Result<(Vec<String>, Vec<Provenance>), ParseError> {
+    let document = Document::from_dom(parse_document(raw_html, options)?);
+    let output = options.output.as_ref().unwrap_or(&Output::OuterHtml);
+    let strict = options.parse_mode.unwrap_or(ParseMode::Strict) == ParseMode::Strict;
+    let line_starts = if strict { line_starts(raw_html) } else { vec![] };
+    let mut values = vec![];
+    let mut provenance = vec![];
+    for element in document.select(options) {
+        let Some(value) = project(element.element(), output) else {
+            continue;
+        };
+        provenance.push(Provenance {
+            source_url: page.map(|page| page.url.clone()),
+            fetched_at: page.map(|page| page.fetched_at.clone()),
+            status: page.map(|page| page.status),
+            path: element.css_path(),
+            offset: if strict { byte_offset(raw_html, &line_starts, element.element()) } else { None },
+            hash: format!("{:016x}", fnv1a(&value)),
+        });
+        values.push(value);
+    }
+    Ok((values, provenance))
+}
+
+/// Byte offset of the start of every line of `raw_html`.
+fn line_starts(raw_html: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(raw_html.match_indices('\n').map(|(index, _)| index + 1))
+        .collect()
+}
+
+/// Byte offset of the 1-based line and character column where `element` starts.
+fn byte_offset(raw_html: &str, line_starts: &[usize], element: &Element) -> Option<usize> {
+    let span = &element.source_span;
+    let line_start = *line_starts.get(span.start_line.checked_sub(1)?)?;
+    let column: usize = raw_html[line_start..].chars().take(span.start_column.saturating_sub(1)).map(char::len_utf8).sum();
+    let offset = line_start + column;
+    // the span is only trusted when it points at the source of the element
+    raw_html[offset..].starts_with(&span.text).then_some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrapper::scrap::TagFilter;
+
+    fn items() -> ScrapeOptions {
+        ScrapeOptions::new(TagFilter {
+            filter: vec!["li".to_string()],
+            case_sensitive: None,
+        })
+    }
+
+    #[test]
+    fn test_scrape_with_provenance() {
+        let raw_html = "<div>\n  <p>Prix: 10 €</p>\n  <ul><li>é</li><li>b</li></ul>\n</div>";
+        let page = FetchedPage {
+            url: "https://example.com/items".to_string(),
+            status: 200,
+            fetched_at: "2024-05-01T10:00:00+00:00".to_string(),
+            body: raw_html.to_string(),
+        };
+        let (values, provenance) = scrape_with_provenance(raw_html, &items(), Some(&page)).unwrap();
+        assert_eq!(values, vec!["<li>é</li>", "<li>b</li>"]);
+        assert_eq!(provenance[1].path, "div > ul > li:nth-of-type(2)");
+        assert_eq!(provenance[0].source_url.as_deref(), Some("https://example.com/items"));
+        assert_eq!(provenance[0].status, Some(200));
+        for (value, provenance) in values.iter().zip(provenance.iter()) {
+            let offset = provenance.offset.unwrap();
+            assert_eq!(&raw_html[offset..offset + value.len()], value);
+        }
+        assert_eq!(provenance[0].hash, format!("{:016x}", fnv1a("<li>é</li>")));
+        assert_eq!(provenance[0].values()[4], ("offset", Some(provenance[0].offset.unwrap().to_string())));
+
+        let lenient = ScrapeOptions {
+            parse_mode: Some(ParseMode::Lenient),
+            output: Some(Output::Text),
+            ..items()
+        };
+        let (values, provenance) = scrape_with_provenance(raw_html, &lenient, None).unwrap();
+        assert_eq!(values, vec!["é", "b"]);
+        assert_eq!(provenance[0].path, "html > body > div > ul > li:nth-of-type(1)");
+        assert_eq!((provenance[0].offset, provenance[0].source_url.as_ref()), (None, None));
+    }
+}
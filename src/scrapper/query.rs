@@ -0,0 +1,1000 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use html_parser::{Dom, Element, Node};
+use regex::{Regex, RegexBuilder};
+
+use super::parse::{attribute, parse_html, source, ParseError, ParseMode};
+use super::scrap::{
+    element_text, is_excluded, project, tag_matches, AttributeFilter, Content, FilterType, MatchMode, Namespace, Output,
+    ScrapeOptions, TextFilter, TextSource,
+};
+use super::text;
+use crate::storage::records::{Record, Value};
+
+/// Error returned when a query can not be compiled. `position` is the byte offset in the
+/// selector where the error was found, when the query comes from a selector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    pub message: String,
+    pub position: Option<usize>,
+}
+
+impl QueryError {
+    fn new(message: &str, position: Option<usize>) -> Self {
+        Self {
+            message: message.to_string(),
+            position,
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "query error at {}: {}", position, self.message),
+            None => write!(f, "query error: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// A set of records to extract from every element matching `selector`.
+///
+/// # Fields
+///
+/// * `selector` - Selector of the elements that become records.
+/// * `fields` - The fields of every record, see `SchemaField`.
+pub struct Schema {
+    pub selector: String,
+    pub fields: Vec<SchemaField>,
+}
+
+/// A field of a `Schema`.
+///
+/// # Fields
+///
+/// * `name` - Name of the field in the record.
+/// * `selector` - Selector of the element holding the value, matched among the descendants of
+///   the record element. Defaults to the record element itself.
+/// * `attribute` - Attribute holding the value. Defaults to the visible text of the element.
+/// * `multiple` - Whether every matching element is used, as a `Value::List`, instead of the
+///   first one. Defaults to `false`.
+pub struct SchemaField {
+    pub name: String,
+    pub selector: Option<String>,
+    pub attribute: Option<String>,
+    pub multiple: Option<bool>,
+}
+
+impl SchemaField {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            selector: None,
+            attribute: None,
+            multiple: None,
+        }
+    }
+}
+
+/// A query compiled once and applied to any number of documents.
+///
+/// Tag names, attribute names and case insensitive values are lowercased, tag names are
+/// deduplicated in a set and regular expressions are built when the query is compiled, so
+/// applying it only compares the document against prepared values. A `CompiledQuery` is
+/// `Send + Sync` and can be shared between threads.
+///
+/// The public fields have the meaning of the `ScrapeOptions` fields of the same name.
+#[derive(Debug, Clone)]
+pub struct CompiledQuery {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub min_depth: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub skip_nested: Option<bool>,
+    pub parse_mode: Option<ParseMode>,
+    pub output: Option<Output>,
+    pub exclude: Option<Vec<Content>>,
+    // each alternative is a chain of filters, outermost ancestor first
+    alternatives: Vec<Vec<CompiledFilter>>,
+    fields: Vec<CompiledField>,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledField {
+    name: String,
+    query: Option<CompiledQuery>,
+    attribute: Option<String>,
+    multiple: bool,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledTags {
+    // plain names, lowercased unless case sensitive, looked up directly for unprefixed elements
+    names: HashSet<String>,
+    // every pattern of the filter, matched with `tag_matches` when the lookup is not enough
+    patterns: Vec<String>,
+    has_patterns: bool,
+    case_sensitive: bool,
+}
+
+impl CompiledTags {
+    fn new(filter: &[String], case_sensitive: bool) -> Self {
+        let is_plain = |tag: &&String| !tag.contains(':') && !tag.ends_with('*');
+        Self {
+            names: filter
+                .iter()
+                .filter(is_plain)
+                .map(|tag| if case_sensitive { tag.clone() } else { tag.to_lowercase() })
+                .collect(),
+            patterns: filter.to_vec(),
+            has_patterns: !filter.iter().all(|tag| is_plain(&tag)),
+            case_sensitive,
+        }
+    }
+
+    fn matches(&self, name: &str, namespace: Namespace) -> bool {
+        if !name.contains(':') {
+            let found = if !self.case_sensitive && name.bytes().any(|b| b.is_ascii_uppercase()) {
+                self.names.contains(&name.to_ascii_lowercase())
+            } else {
+                self.names.contains(name)
+            };
+            if found || !self.has_patterns {
+                return found;
+            }
+        }
+        self.patterns.iter().any(|pattern| tag_matches(pattern, name, namespace, self.case_sensitive))
+    }
+}
+
+/// The filters of a `ScrapeOptions` with their values prepared, shared by `scrape`, the
+/// stream scraper, navigation and `CompiledQuery`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CompiledFilter {
+    // `None` matches any tag
+    tags: Option<CompiledTags>,
+    ids: Option<Vec<String>>,
+    classes: Option<(Vec<String>, bool)>,
+    attributes_include: Option<Conditions<AttributeCondition>>,
+    attributes_exclude: Option<Conditions<AttributeCondition>>,
+    text_include: Option<TextConditions>,
+    text_exclude: Option<TextConditions>,
+}
+
+/// Conditions combined with `FilterType::And` when `all` is set, `FilterType::Or` otherwise.
+#[derive(Debug, Clone)]
+struct Conditions<T> {
+    conditions: Vec<T>,
+    all: bool,
+}
+
+#[derive(Debug, Clone)]
+struct AttributeCondition {
+    name: String,
+    matcher: ValueMatcher,
+}
+
+#[derive(Debug, Clone)]
+struct TextConditions {
+    conditions: Conditions<ValueMatcher>,
+    source: TextKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TextKind {
+    Source,
+    OwnText,
+    DescendantText,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Contains(String),
+    Exact(String),
+    Regex(Regex),
+    Exists,
+    Prefix(String),
+    Suffix(String),
+    Word(String),
+    LangPrefix(String),
+    // `None` when the pattern is not a number, nothing matches then
+    Compare(Option<f64>, fn(std::cmp::Ordering) -> bool),
+    // an invalid regular expression of a lenient filter
+    Never,
+}
+
+/// A value comparison with its pattern prepared, lowercased when `case_insensitive` is set.
+#[derive(Debug, Clone)]
+struct ValueMatcher {
+    matcher: Matcher,
+    case_insensitive: bool,
+}
+
+impl ValueMatcher {
+    /// Prepares `pattern`. With `lenient` set an invalid regular expression never matches
+    /// instead of being reported.
+    fn new(pattern: &str, mode: &MatchMode, case_insensitive: bool, lenient: bool) -> Result<Self, QueryError> {
+        let compile = |pattern: &str| match compile_regex(pattern, case_insensitive) {
+            Ok(regex) => Ok(Matcher::Regex(regex)),
+            Err(_) if lenient => Ok(Matcher::Never),
+            Err(error) => Err(error),
+        };
+        let prepared = if case_insensitive { pattern.to_lowercase() } else { pattern.to_string() };
+        let matcher = match mode {
+            MatchMode::Contains => Matcher::Contains(prepared),
+            MatchMode::Exact => Matcher::Exact(prepared),
+            MatchMode::Regex => compile(pattern)?,
+            MatchMode::WholeWord => compile(&format!(r"(?:^|\W){}(?:\W|$)", regex::escape(pattern)))?,
+            MatchMode::Exists => Matcher::Exists,
+            MatchMode::Prefix => Matcher::Prefix(prepared),
+            MatchMode::Suffix => Matcher::Suffix(prepared),
+            MatchMode::Word => Matcher::Word(prepared),
+            MatchMode::LangPrefix => Matcher::LangPrefix(prepared),
+            MatchMode::GreaterThan => Matcher::Compare(parse_number(pattern), |o| o.is_gt()),
+            MatchMode::GreaterOrEqual => Matcher::Compare(parse_number(pattern), |o| o.is_ge()),
+            MatchMode::LessThan => Matcher::Compare(parse_number(pattern), |o| o.is_lt()),
+            MatchMode::LessOrEqual => Matcher::Compare(parse_number(pattern), |o| o.is_le()),
+        };
+        Ok(Self { matcher, case_insensitive })
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        let lowercase;
+        let value = match self.matcher {
+            Matcher::Regex(_) | Matcher::Exists | Matcher::Compare(..) | Matcher::Never => value,
+            _ if self.case_insensitive => {
+                lowercase = value.to_lowercase();
+                lowercase.as_str()
+            }
+            _ => value,
+        };
+        match self.matcher {
+            Matcher::Contains(ref pattern) => value.contains(pattern.as_str()),
+            Matcher::Exact(ref pattern) => value == pattern,
+            Matcher::Regex(ref regex) => regex.is_match(value),
+            Matcher::Exists => true,
+            Matcher::Prefix(ref pattern) => value.starts_with(pattern.as_str()),
+            Matcher::Suffix(ref pattern) => value.ends_with(pattern.as_str()),
+            Matcher::Word(ref pattern) => value.split_whitespace().any(|word| word == pattern),
+            Matcher::LangPrefix(ref pattern) => value == pattern
+                || value.strip_prefix(pattern.as_str()).is_some_and(|rest| rest.starts_with('-')),
+            Matcher::Compare(pattern, accept) => pattern
+                .zip(parse_number(value))
+                .and_then(|(pattern, value)| value.partial_cmp(&pattern))
+                .is_some_and(accept),
+            Matcher::Never => false,
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    value.trim().parse::<f64>().ok()
+}
+
+fn compile_regex(pattern: &str, case_insensitive: bool) -> Result<Regex, QueryError> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|error| QueryError::new(&format!("invalid regex {:?}: {}", pattern, error), None))
+}
+
+impl CompiledFilter {
+    /// Compiles the element and text filters of `options`, an invalid regular expression never
+    /// matches like it does for `scrape`.
+    pub(crate) fn lenient(options: &ScrapeOptions) -> Self {
+        Self::compile(options, true).expect("lenient filters do not fail")
+    }
+
+    pub(crate) fn from_options(options: &ScrapeOptions) -> Result<Self, QueryError> {
+        Self::compile(options, false)
+    }
+
+    fn compile(options: &ScrapeOptions, lenient: bool) -> Result<Self, QueryError> {
+        Ok(Self {
+            tags: Some(CompiledTags::new(&options.tags.filter, options.tags.case_sensitive.unwrap_or(false))),
+            ids: options.id_filter.as_ref().map(|ids| ids.filter.clone()),
+            classes: options.class_filter
+                .as_ref()
+                .map(|classes| (classes.filter.clone(), matches!(classes.filter_type, FilterType::And))),
+            attributes_include: options.attributes_include.as_ref().map(|filter| compile_attributes(filter, lenient)).transpose()?,
+            attributes_exclude: options.attributes_exclude.as_ref().map(|filter| compile_attributes(filter, lenient)).transpose()?,
+            text_include: options.text_include.as_ref().map(|filter| compile_text(filter, lenient)).transpose()?,
+            text_exclude: options.text_exclude.as_ref().map(|filter| compile_text(filter, lenient)).transpose()?,
+        })
+    }
+
+    pub(crate) fn matches(&self, element: &Element, namespace: Namespace, exclude: &[Content]) -> bool {
+        self.matches_start_tag(element, namespace) && self.matches_text(element, exclude)
+    }
+
+    /// Checks the filters that only need the start tag of an element: tag name, id, classes and attributes.
+    pub(crate) fn matches_start_tag(&self, element: &Element, namespace: Namespace) -> bool {
+        if let Some(ref tags) = self.tags {
+            if !tags.matches(&element.name, namespace) {
+                return false;
+            }
+        }
+        if let Some(ref ids) = self.ids {
+            if !element.id.as_ref().is_some_and(|id| ids.contains(id)) {
+                return false;
+            }
+        }
+        if let Some((ref classes, all)) = self.classes {
+            let has = |class: &String| element.classes.contains(class);
+            let found = if all { classes.iter().all(has) } else { classes.iter().any(has) };
+            if !found {
+                return false;
+            }
+        }
+        if let Some(ref attributes) = self.attributes_include {
+            if !attributes.matches(|condition| condition.matches(element)) {
+                return false;
+            }
+        }
+        if let Some(ref attributes) = self.attributes_exclude {
+            if attributes.matches(|condition| condition.matches(element)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Checks the filters that need the content of an element.
+    pub(crate) fn matches_text(&self, element: &Element, exclude: &[Content]) -> bool {
+        if let Some(ref text) = self.text_include {
+            if !text.matches(element, exclude) {
+                return false;
+            }
+        }
+        if let Some(ref text) = self.text_exclude {
+            if text.matches(element, exclude) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl CompiledFilter {
+    /// The named groups captured in the text of `element` by the regular expressions of the
+    /// text include filter. Groups that did not participate in the match are left out.
+    pub(crate) fn captures(&self, element: &Element, exclude: &[Content]) -> HashMap<String, String> {
+        let mut captures = HashMap::new();
+        let Some(ref text) = self.text_include else {
+            return captures;
+        };
+        let node_text = text.text(element, exclude);
+        for matcher in text.conditions.conditions.iter() {
+            let Matcher::Regex(ref regex) = matcher.matcher else {
+                continue;
+            };
+            let Some(found) = regex.captures(&node_text) else {
+                continue;
+            };
+            for name in regex.capture_names().flatten() {
+                if let Some(value) = found.name(name) {
+                    captures.insert(name.to_string(), value.as_str().to_string());
+                }
+            }
+        }
+        captures
+    }
+}
+
+impl<T> Conditions<T> {
+    fn matches(&self, condition_matches: impl Fn(&T) -> bool) -> bool {
+        if self.all {
+            self.conditions.iter().all(condition_matches)
+        } else {
+            self.conditions.iter().any(condition_matches)
+        }
+    }
+}
+
+impl AttributeCondition {
+    fn matches(&self, element: &Element) -> bool {
+        // values are decoded like the outputs, so both parse modes match the same elements and
+        // valueless attributes such as `<input disabled>` are compared as an empty string
+        let Some(value) = attribute(element, &self.name) else {
+            return false;
+        };
+        if self.name.eq_ignore_ascii_case("class") {
+            value.split_whitespace().any(|class| self.matcher.matches(class))
+        } else {
+            self.matcher.matches(&value)
+        }
+    }
+}
+
+impl TextConditions {
+    fn matches(&self, element: &Element, exclude: &[Content]) -> bool {
+        let text = self.text(element, exclude);
+        self.conditions.matches(|matcher| matcher.matches(&text))
+    }
+
+    fn text(&self, element: &Element, exclude: &[Content]) -> String {
+        let source = match self.source {
+            TextKind::Source => TextSource::Source,
+            TextKind::OwnText => TextSource::OwnText,
+            TextKind::DescendantText => TextSource::DescendantText,
+        };
+        element_text(element, &source, exclude)
+    }
+}
+
+fn compile_attributes(filter: &AttributeFilter, lenient: bool) -> Result<Conditions<AttributeCondition>, QueryError> {
+    let mode = filter.match_mode.as_ref().unwrap_or(&MatchMode::Exact);
+    let case_insensitive = filter.case_insensitive.unwrap_or(false);
+    let conditions = filter.filter
+        .iter()
+        .map(|(name, value)| {
+            Ok(AttributeCondition {
+                name: name.to_lowercase(),
+                matcher: ValueMatcher::new(value, mode, case_insensitive, lenient)?,
+            })
+        })
+        .collect::<Result<Vec<AttributeCondition>, QueryError>>()?;
+    Ok(Conditions {
+        conditions,
+        all: matches!(filter.filter_type, FilterType::And),
+    })
+}
+
+fn compile_text(filter: &TextFilter, lenient: bool) -> Result<TextConditions, QueryError> {
+    let mode = filter.match_mode.as_ref().unwrap_or(&MatchMode::Contains);
+    let case_insensitive = filter.case_insensitive.unwrap_or(false);
+    let conditions = filter.filter
+        .iter()
+        .map(|pattern| ValueMatcher::new(pattern, mode, case_insensitive, lenient))
+        .collect::<Result<Vec<ValueMatcher>, QueryError>>()?;
+    Ok(TextConditions {
+        conditions: Conditions {
+            conditions,
+            all: matches!(filter.filter_type, FilterType::And),
+        },
+        source: match filter.text_source.as_ref().unwrap_or(&TextSource::Source) {
+            TextSource::Source => TextKind::Source,
+            TextSource::OwnText => TextKind::OwnText,
+            TextSource::DescendantText => TextKind::DescendantText,
+        },
+    })
+}
+
+impl CompiledQuery {
+    fn with_alternatives(alternatives: Vec<Vec<CompiledFilter>>) -> Self {
+        Self {
+            offset: None,
+            limit: None,
+            min_depth: None,
+            max_depth: None,
+            skip_nested: None,
+            parse_mode: None,
+            output: None,
+            exclude: None,
+            alternatives,
+            fields: vec![],
+        }
+    }
+
+    /// Compiles `options`, the query then returns the same elements as `scrape`. Unlike
+    /// `scrape`, where an invalid regular expression never matches, it is reported as an error.
+    pub fn from_options(options: &ScrapeOptions) -> Result<Self, QueryError> {
+        let mut chain = vec![];
+        if let Some(ref ancestor) = options.ancestor {
+            chain.push(CompiledFilter::from_options(ancestor)?);
+        }
+        chain.push(CompiledFilter::from_options(options)?);
+        Ok(Self {
+            offset: options.offset,
+            limit: options.limit,
+            min_depth: options.min_depth,
+            max_depth: options.max_depth,
+            skip_nested: options.skip_nested,
+            parse_mode: options.parse_mode,
+            output: options.output.clone(),
+            exclude: options.exclude.clone(),
+            ..Self::with_alternatives(vec![chain])
+        })
+    }
+
+    /// Compiles a CSS selector. Supported are type (`div`, `*`), id (`#main`), class
+    /// (`.item`) and attribute selectors (`[href]`, `[lang|=en]`, `[href$=".pdf" i]` with the
+    /// `=`, `~=`, `|=`, `^=`, `$=` and `*=` operators), the descendant combinator and selector
+    /// lists (`h1, h2`). Tag names are case insensitive.
+    pub fn from_selector(selector: &str) -> Result<Self, QueryError> {
+        Ok(Self::with_alternatives(SelectorParser::new(selector).parse()?))
+    }
+
+    /// Compiles a schema: the query matches the elements of `schema.selector` and `records`
+    /// extracts the schema fields from them.
+    pub fn from_schema(schema: &Schema) -> Result<Self, QueryError> {
+        let mut query = Self::from_selector(&schema.selector)?;
+        for field in schema.fields.iter() {
+            query.fields.push(CompiledField {
+                name: field.name.clone(),
+                query: field.selector.as_deref().map(Self::from_selector).transpose()?,
+                attribute: field.attribute.as_ref().map(|attribute| attribute.to_lowercase()),
+                multiple: field.multiple.unwrap_or(false),
+            });
+        }
+        Ok(query)
+    }
+
+    /// Same as `scrape` with the compiled query.
+    pub fn scrape(&self, raw_html: &str) -> Result<Vec<String>, ParseError> {
+        let html = self.parse(raw_html)?;
+        let output = self.output.as_ref().unwrap_or(&Output::OuterHtml);
+        Ok(self.matching_elements(&html)
+            .iter()
+            .filter_map(|element| project(element, output))
+            .collect())
+    }
+
+    /// Same as `first` with the compiled query.
+    pub fn first(&self, raw_html: &str) -> Result<Option<String>, ParseError> {
+        let html = self.parse(raw_html)?;
+        let query = Self {
+            limit: Some(1),
+            ..self.clone()
+        };
+        let output = self.output.as_ref().unwrap_or(&Output::OuterHtml);
+        Ok(query.matching_elements(&html).first().and_then(|element| project(element, output)))
+    }
+
+    /// Extracts a record from every match. With a schema the record has the schema fields,
+    /// otherwise it has the `html` source and visible `text` of the element.
+    pub fn records(&self, raw_html: &str) -> Result<Vec<Record>, ParseError> {
+        let html = self.parse(raw_html)?;
+        Ok(self.matching_elements(&html)
+            .into_iter()
+            .map(|element| self.record(element))
+            .collect())
+    }
+
+    /// Elements of a parsed document matching the query, in document order.
+    pub fn matching_elements<'a>(&self, html: &'a Dom) -> Vec<&'a Element> {
+        self.matches_in(&html.children)
+    }
+
+    fn parse(&self, raw_html: &str) -> Result<Dom, ParseError> {
+        parse_html(raw_html, self.parse_mode.as_ref().unwrap_or(&ParseMode::Strict))
+    }
+
+    fn record(&self, element: &Element) -> Record {
+        let mut record = Record::new();
+        if self.fields.is_empty() {
+            record.insert("html", Value::Text(source(element).into_owned()));
+            record.insert("text", Value::Text(text::visible_text(element, true)));
+            return record;
+        }
+        for field in self.fields.iter() {
+            let targets = match field.query {
+                Some(ref query) => query.matches_in(&element.children),
+                None => vec![element],
+            };
+            let values: Vec<String> = targets
+                .into_iter()
+                .filter_map(|target| match field.attribute {
+                    Some(ref name) => attribute(target, name),
+                    None => Some(text::visible_text(target, true)),
+                })
+                .collect();
+            if field.multiple {
+                record.insert(&field.name, Value::List(values));
+            } else if let Some(value) = values.into_iter().next() {
+                record.insert(&field.name, Value::Text(value));
+            }
+        }
+        record
+    }
+
+    /// Same traversal as `scrape`: document order, `offset`, `limit` and tree constraints.
+    fn matches_in<'a>(&self, nodes: &'a [Node]) -> Vec<&'a Element> {
+        let mut result = vec![];
+        if self.limit == Some(0) {
+            return result;
+        }
+        let offset = self.offset.unwrap_or(0);
+        let min_depth = self.min_depth.unwrap_or(0);
+        let max_depth = self.max_depth.unwrap_or(usize::MAX);
+        let skip_nested = self.skip_nested.unwrap_or(false);
+        // every pending node keeps its depth and, per alternative, how many filters of the
+        // chain are fulfilled by its ancestors
+        let initial = vec![0; self.alternatives.len()];
+        let mut pending: Vec<(&Element, usize, Namespace, Vec<usize>)> = nodes
+            .iter()
+            .rev()
+            .filter_map(|node| node.element())
+            .map(|element| (element, 0, Namespace::Html, initial.clone()))
+            .collect();
+        let exclude = self.exclude.as_deref().unwrap_or(&[]);
+        let mut index = 0;
+        while let Some((element, depth, parent_namespace, states)) = pending.pop() {
+            if is_excluded(&element.name, exclude) {
+                continue;
+            }
+            let namespace = parent_namespace.of(&element.name);
+            let is_match = depth >= min_depth
+                && depth <= max_depth
+                && self.alternatives.iter().zip(states.iter()).any(|(chain, state)| {
+                    *state == chain.len() - 1 && chain[*state].matches(element, namespace, exclude)
+                });
+            if depth < max_depth && !(is_match && skip_nested) {
+                let child_states: Vec<usize> = self.alternatives
+                    .iter()
+                    .zip(states.iter())
+                    .map(|(chain, state)| {
+                        if *state < chain.len() - 1 && chain[*state].matches(element, namespace, exclude) { state + 1 } else { *state }
+                    })
+                    .collect();
+                let children_namespace = namespace.for_children(&element.name);
+                for child in element.children.iter().rev().filter_map(|node| node.element()) {
+                    pending.push((child, depth + 1, children_namespace, child_states.clone()));
+                }
+            }
+            if !is_match {
+                continue;
+            }
+            if index >= offset {
+                result.push(element);
+                if self.limit.is_some_and(|limit| result.len() >= limit) {
+                    break;
+                }
+            }
+            index += 1;
+        }
+        result
+    }
+}
+
+struct SelectorParser<'a> {
+    selector: &'a str,
+    position: usize,
+}
+
+impl<'a> SelectorParser<'a> {
+    fn new(selector: &'a str) -> Self {
+        Self { selector, position: 0 }
+    }
+
+    fn error(&self, message: &str) -> QueryError {
+        QueryError::new(message, Some(self.position))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.selector[self.position..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+        self.position > start
+    }
+
+    fn parse(mut self) -> Result<Vec<Vec<CompiledFilter>>, QueryError> {
+        let mut alternatives = vec![];
+        loop {
+            alternatives.push(self.parse_complex()?);
+            match self.bump() {
+                Some(',') => continue,
+                None => return Ok(alternatives),
+                Some(_) => {
+                    self.position -= 1;
+                    return Err(self.error("unexpected character"));
+                }
+            }
+        }
+    }
+
+    fn parse_complex(&mut self) -> Result<Vec<CompiledFilter>, QueryError> {
+        let mut chain = vec![];
+        self.skip_whitespace();
+        loop {
+            chain.push(self.parse_compound()?);
+            let had_whitespace = self.skip_whitespace();
+            match self.peek() {
+                None | Some(',') => return Ok(chain),
+                Some('>') | Some('+') | Some('~') => return Err(self.error("only the descendant combinator is supported")),
+                Some(_) if had_whitespace => continue,
+                Some(_) => return Err(self.error("unexpected character")),
+            }
+        }
+    }
+
+    fn parse_compound(&mut self) -> Result<CompiledFilter, QueryError> {
+        let mut filter = CompiledFilter::default();
+        let mut attributes = vec![];
+        let mut empty = true;
+        if self.peek() == Some('*') {
+            self.bump();
+            empty = false;
+        } else if self.peek().is_some_and(is_name_char) {
+            let mut name = self.parse_name()?;
+            // `svg|path`, the CSS form of the `svg:path` tag pattern
+            if self.peek() == Some('|') {
+                self.bump();
+                let local_name = if self.peek() == Some('*') {
+                    self.bump();
+                    "*".to_string()
+                } else {
+                    self.parse_name()?
+                };
+                name = format!("{}:{}", name, local_name);
+            }
+            filter.tags = Some(CompiledTags::new(&[name], false));
+            empty = false;
+        }
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.bump();
+                    let id = self.parse_name()?;
+                    attributes.push(AttributeCondition {
+                        name: "id".to_string(),
+                        matcher: ValueMatcher::new(&id, &MatchMode::Exact, false, false)?,
+                    });
+                }
+                Some('.') => {
+                    self.bump();
+                    let class = self.parse_name()?;
+                    filter.classes.get_or_insert_with(|| (vec![], true)).0.push(class);
+                }
+                Some('[') => {
+                    self.bump();
+                    attributes.push(self.parse_attribute()?);
+                }
+                Some(':') => return Err(self.error("pseudo-classes are not supported")),
+                _ => break,
+            }
+            empty = false;
+        }
+        if empty {
+            return Err(self.error("expected a selector"));
+        }
+        if !attributes.is_empty() {
+            filter.attributes_include = Some(Conditions { conditions: attributes, all: true });
+        }
+        Ok(filter)
+    }
+
+    fn parse_name(&mut self) -> Result<String, QueryError> {
+        let start = self.position;
+        while self.peek().is_some_and(is_name_char) {
+            self.bump();
+        }
+        if self.position == start {
+            return Err(self.error("expected a name"));
+        }
+        Ok(self.selector[start..self.position].to_string())
+    }
+
+    fn parse_attribute(&mut self) -> Result<AttributeCondition, QueryError> {
+        self.skip_whitespace();
+        let name = self.parse_name()?.to_lowercase();
+        self.skip_whitespace();
+        let mode = match self.bump() {
+            Some(']') => {
+                return Ok(AttributeCondition {
+                    name,
+                    matcher: ValueMatcher::new("", &MatchMode::Exists, false, false)?,
+                });
+            }
+            Some('=') => MatchMode::Exact,
+            Some(operator @ ('~' | '|' | '^' | '$' | '*')) => {
+                if self.bump() != Some('=') {
+                    return Err(self.error("expected `=`"));
+                }
+                match operator {
+                    '~' => MatchMode::Word,
+                    '|' => MatchMode::LangPrefix,
+                    '^' => MatchMode::Prefix,
+                    '$' => MatchMode::Suffix,
+                    _ => MatchMode::Contains,
+                }
+            }
+            _ => return Err(self.error("expected an attribute operator")),
+        };
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.bump();
+                let start = self.position;
+                while self.peek().is_some_and(|c| c != quote) {
+                    self.bump();
+                }
+                let value = self.selector[start..self.position].to_string();
+                if self.bump().is_none() {
+                    return Err(self.error("unterminated string"));
+                }
+                value
+            }
+            _ => self.parse_name()?,
+        };
+        self.skip_whitespace();
+        let mut case_insensitive = false;
+        if matches!(self.peek(), Some('i') | Some('I')) {
+            self.bump();
+            case_insensitive = true;
+            self.skip_whitespace();
+        }
+        if self.bump() != Some(']') {
+            return Err(self.error("expected `]`"));
+        }
+        Ok(AttributeCondition {
+            name,
+            matcher: ValueMatcher::new(&value, &mode, case_insensitive, false)?,
+        })
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || !c.is_ascii()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrapper::scrap::{scrape, ClassFilter, TagFilter};
+
+    const PAGE: &str = r#"<div id="list">
+        <div class="item" data-price="10"><a href="/a.pdf">Alpha</a><span class="tag">x</span><span class="tag">y</span></div>
+        <div class="item sale" data-price="25"><a href="/b.html" lang="en-GB">Beta</a></div>
+        <p class="item">Not a div</p>
+    </div>"#;
+
+    #[test]
+    fn test_compiled_query_from_options_matches_scrape() {
+        let options = ScrapeOptions {
+            class_filter: Some(ClassFilter {
+                filter: vec!["item".to_string()],
+                filter_type: FilterType::And,
+            }),
+            attributes_include: Some(AttributeFilter {
+                filter: vec![("data-price".to_string(), "15".to_string())],
+                filter_type: FilterType::And,
+                match_mode: Some(MatchMode::GreaterThan),
+                case_insensitive: None,
+            }),
+            ancestor: Some(Box::new(ScrapeOptions::new(TagFilter {
+                filter: vec!["div".to_string()],
+                case_sensitive: None,
+            }))),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["div".to_string()],
+                case_sensitive: None,
+            })
+        };
+        let query = CompiledQuery::from_options(&options).unwrap();
+        let expected = scrape(PAGE, &options).unwrap();
+        assert_eq!(expected.len(), 1);
+        assert_eq!(query.scrape(PAGE).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_compiled_query_exclude() {
+        let raw_html = "<div><p>visible</p><script>var html = '<p>hidden</p>';</script><template><p>hidden</p></template></div>";
+        let options = ScrapeOptions {
+            exclude: Some(vec![Content::Scripts, Content::Templates]),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["p".to_string(), "script".to_string()],
+                case_sensitive: None,
+            })
+        };
+        let query = CompiledQuery::from_options(&options).unwrap();
+        assert_eq!(query.scrape(raw_html).unwrap(), vec!["<p>visible</p>"]);
+        assert_eq!(query.scrape(raw_html).unwrap(), scrape(raw_html, &options).unwrap());
+    }
+
+    #[test]
+    fn test_compiled_query_invalid_regex() {
+        let options = ScrapeOptions {
+            text_include: Some(TextFilter {
+                filter: vec!["(".to_string()],
+                filter_type: FilterType::And,
+                match_mode: Some(MatchMode::Regex),
+                case_insensitive: None,
+                text_source: None,
+            }),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["div".to_string()],
+                case_sensitive: None,
+            })
+        };
+        assert!(CompiledQuery::from_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_compiled_query_from_selector() {
+        let texts = |selector: &str| -> Vec<String> {
+            let query = CompiledQuery::from_selector(selector).unwrap();
+            let html = Dom::parse(PAGE).unwrap();
+            query.matching_elements(&html).into_iter().map(|element| text::visible_text(element, true)).collect()
+        };
+        assert_eq!(texts("#list .item a"), vec!["Alpha", "Beta"]);
+        assert_eq!(texts("DIV.item.sale"), vec!["Beta"]);
+        assert_eq!(texts("a[href$='.PDF' i], p"), vec!["Alpha", "Not a div"]);
+        assert_eq!(texts("[lang|=en]"), vec!["Beta"]);
+        assert_eq!(texts("*[data-price='25'] a"), vec!["Beta"]);
+
+        let error = CompiledQuery::from_selector("div > a").unwrap_err();
+        assert_eq!(error.position, Some(4));
+        assert!(CompiledQuery::from_selector("a:hover").is_err());
+        assert!(CompiledQuery::from_selector("a[href").is_err());
+    }
+
+    #[test]
+    fn test_compiled_query_tag_patterns() {
+        let raw_html = r##"<my-card><a href="/page">page</a></my-card><svg><a href="#icon"><path></path></a></svg>"##;
+        let options = ScrapeOptions::new(TagFilter {
+            filter: vec!["my-*".to_string(), "svg:a".to_string(), "svg:path".to_string()],
+            case_sensitive: None,
+        });
+        let query = CompiledQuery::from_options(&options).unwrap();
+        assert_eq!(query.scrape(raw_html).unwrap(), scrape(raw_html, &options).unwrap());
+        assert_eq!(query.scrape(raw_html).unwrap().len(), 3);
+
+        let selected = CompiledQuery::from_selector("svg|a, my-card a").unwrap().scrape(raw_html).unwrap();
+        assert_eq!(selected, vec![r#"<a href="/page">page</a>"#, r##"<a href="#icon"><path></path></a>"##]);
+    }
+
+    #[test]
+    fn test_compiled_query_from_schema() {
+        let schema = Schema {
+            selector: "div.item".to_string(),
+            fields: vec![
+                SchemaField {
+                    selector: Some("a".to_string()),
+                    ..SchemaField::new("name")
+                },
+                SchemaField {
+                    selector: Some("a".to_string()),
+                    attribute: Some("href".to_string()),
+                    ..SchemaField::new("url")
+                },
+                SchemaField {
+                    attribute: Some("data-price".to_string()),
+                    ..SchemaField::new("price")
+                },
+                SchemaField {
+                    selector: Some(".tag".to_string()),
+                    multiple: Some(true),
+                    ..SchemaField::new("tags")
+                },
+            ],
+        };
+        let records = CompiledQuery::from_schema(&schema).unwrap().records(PAGE).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].fields, vec![
+            ("name".to_string(), Value::Text("Alpha".to_string())),
+            ("url".to_string(), Value::Text("/a.pdf".to_string())),
+            ("price".to_string(), Value::Text("10".to_string())),
+            ("tags".to_string(), Value::List(vec!["x".to_string(), "y".to_string()])),
+        ]);
+        assert_eq!(records[1].get("tags"), Some(&Value::List(vec![])));
+    }
+
+    #[test]
+    fn test_compiled_query_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<CompiledQuery>();
+
+        let query = CompiledQuery::from_selector("a").unwrap();
+        let pages: Vec<String> = (0..8).map(|index| format!("<div><a href='/{0}'>{0}</a></div>", index)).collect();
+        let results: Vec<Vec<String>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = pages.iter().map(|page| scope.spawn(|| query.scrape(page).unwrap())).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        assert_eq!(results[3], vec!["<a href='/3'>3</a>"]);
+    }
+}
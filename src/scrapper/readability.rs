@@ -0,0 +1,559 @@
+use std::collections::HashMap;
+
+use html_parser::{Dom, Element, Node};
+use regex::Regex;
+
+use super::metadata::{metadata_in_dom, PageMetadata};
+use super::parse::{attribute, outer_html, parse_html, ParseError, ParseMode};
+use super::text;
+use crate::storage::records::{Record, Value};
+
+/// Elements that never hold article content, removed before scoring.
+const BOILERPLATE_ELEMENTS: [&str; 13] = [
+    "aside", "button", "footer", "form", "iframe", "input", "nav", "noscript", "object", "select",
+    "svg", "textarea", "embed",
+];
+
+/// Elements whose text is scored, divs and sections are scored too when they only hold inline content.
+const PARAGRAPH_ELEMENTS: [&str; 3] = ["p", "pre", "td"];
+
+/// Paragraphs shorter than this are ignored when scoring.
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+
+/// Number of ancestors that receive a share of a paragraph's score.
+const SCORED_ANCESTORS: usize = 5;
+
+const UNLIKELY_CANDIDATES: &str = r"(?i)-ad-|\bads?\b|advert|agegate|banner|breadcrumb|combx|comment|community|cookie|disqus|extra|footer|gdpr|header|legends|menu|modal|pager|pagination|popup|related|remark|replies|rss|share|shoutbox|sidebar|skyscraper|social|sponsor|subscribe|supplemental";
+const MAYBE_CANDIDATES: &str = r"(?i)and|article|body|column|content|main|shadow";
+const POSITIVE_NAMES: &str = r"(?i)article|body|content|entry|hentry|h-entry|main|page|post|text|blog|story";
+const NEGATIVE_NAMES: &str = r"(?i)-ad-|\bads?\b|advert|hidden|banner|combx|comment|com-|contact|foot|footnote|gdpr|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|tool|widget";
+const BYLINE_NAMES: &str = r"(?i)byline|author|writtenby";
+
+/// Main content of a page, as found by `extract_article`.
+///
+/// * `title` - OpenGraph title, else `<title>` without the site name, else the first `<h1>`.
+/// * `byline` - Author from the meta tags, JSON-LD, or a `rel="author"`/`byline` element.
+/// * `published` - Publish date as written in the page (meta tags, JSON-LD or `<time datetime>`).
+/// * `text` - Text of the content, one paragraph per line.
+/// * `html` - Cleaned HTML of the content: boilerplate, scripts, styles, comments, inline styles
+///   and event handler attributes removed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Article {
+    pub title: Option<String>,
+    pub byline: Option<String>,
+    pub published: Option<String>,
+    pub text: String,
+    pub html: String,
+}
+
+impl Article {
+    pub fn to_record(&self) -> Record {
+        let mut record = Record::new();
+        record.insert("title", Value::Text(self.title.clone().unwrap_or_default()));
+        record.insert("byline", Value::Text(self.byline.clone().unwrap_or_default()));
+        record.insert("published", Value::Text(self.published.clone().unwrap_or_default()));
+        record.insert("text", Value::Text(self.text.clone()));
+        record.insert("html", Value::Text(self.html.clone()));
+        record
+    }
+}
+
+/// Finds the main article body of a page, without per site filters.
+///
+/// Navigation, forms, hidden elements and blocks whose class or id look like ads, menus,
+/// comments or sidebars are dropped. The remaining paragraphs are scored on their length and
+/// number of commas, and their scores are given to their ancestors. The ancestor with the best
+/// score, reduced by its link density, is the content, along with siblings that score close to it.
+///
+/// Returns `None` when the page has no paragraph long enough to be an article.
+pub fn extract_article(raw_html: &str, mode: &ParseMode) -> Result<Option<Article>, ParseError> {
+    let html = parse_html(raw_html, mode)?;
+    Ok(article_in_dom(&html))
+}
+
+/// Same as `extract_article` for an already parsed document.
+pub fn article_in_dom(html: &Dom) -> Option<Article> {
+    let patterns = Patterns::new();
+    let mut pruned = html.clone();
+    prune(&mut pruned.children, &patterns);
+
+    let mut scores: HashMap<*const Element, (f64, &Element)> = HashMap::new();
+    for node in pruned.children.iter() {
+        if let Node::Element(element) = node {
+            score_paragraphs(element, &mut vec![], &mut scores, &patterns);
+        }
+    }
+    // candidates are compared in document order and the first of equal scores wins, the order
+    // of the map changes between runs
+    let mut elements = vec![];
+    collect_elements(&pruned.children, &mut elements);
+    let (top_score, top) = elements
+        .into_iter()
+        .filter_map(|element| scores.get(&(element as *const Element)))
+        .map(|(score, element)| (score * (1.0 - link_density(element)), *element))
+        .fold(None, |best: Option<(f64, &Element)>, candidate| match best {
+            Some(best) if best.0 >= candidate.0 => Some(best),
+            _ => Some(candidate),
+        })?;
+
+    let mut content = Element {
+        name: "div".to_string(),
+        ..Element::default()
+    };
+    match find_parent(&pruned.children, top) {
+        Some(parent) => {
+            let threshold = (top_score * 0.2).max(10.0);
+            for sibling in parent.children.iter().filter_map(|node| node.element()) {
+                let key = sibling as *const Element;
+                let include = std::ptr::eq(sibling, top)
+                    || scores.get(&key).is_some_and(|(score, _)| score * (1.0 - link_density(sibling)) >= threshold)
+                    || is_standalone_paragraph(sibling);
+                if include {
+                    content.children.push(Node::Element(sibling.clone()));
+                }
+            }
+        }
+        None => content.children.push(Node::Element(top.clone())),
+    }
+    clean(&mut content, &patterns);
+
+    let metadata = metadata_in_dom(html);
+    Some(Article {
+        title: article_title(html, &metadata),
+        byline: byline(html, &metadata, &patterns),
+        published: published(html, &metadata),
+        text: paragraphs(&content).join("\n"),
+        html: outer_html(&content),
+    })
+}
+
+struct Patterns {
+    unlikely: Regex,
+    maybe: Regex,
+    positive: Regex,
+    negative: Regex,
+    byline: Regex,
+}
+
+impl Patterns {
+    fn new() -> Self {
+        Self {
+            unlikely: Regex::new(UNLIKELY_CANDIDATES).unwrap(),
+            maybe: Regex::new(MAYBE_CANDIDATES).unwrap(),
+            positive: Regex::new(POSITIVE_NAMES).unwrap(),
+            negative: Regex::new(NEGATIVE_NAMES).unwrap(),
+            byline: Regex::new(BYLINE_NAMES).unwrap(),
+        }
+    }
+
+    /// +25 for content looking class or id names, -25 for boilerplate looking ones.
+    fn class_weight(&self, element: &Element) -> f64 {
+        let names = class_and_id(element);
+        let mut weight = 0.0;
+        if self.negative.is_match(&names) {
+            weight -= 25.0;
+        }
+        if self.positive.is_match(&names) {
+            weight += 25.0;
+        }
+        weight
+    }
+}
+
+fn class_and_id(element: &Element) -> String {
+    format!("{} {}", element.classes.join(" "), element.id.clone().unwrap_or_default())
+}
+
+fn is_named(element: &Element, names: &[&str]) -> bool {
+    names.iter().any(|name| element.name.eq_ignore_ascii_case(name))
+}
+
+fn is_hidden(element: &Element) -> bool {
+    attribute(element, "hidden").is_some()
+        || attribute(element, "aria-hidden").is_some_and(|value| value.eq_ignore_ascii_case("true"))
+        || attribute(element, "style").is_some_and(|style| style.replace(' ', "").to_lowercase().contains("display:none"))
+}
+
+/// Removes comments, scripts, styles, boilerplate elements and unlikely candidates.
+fn prune(nodes: &mut Vec<Node>, patterns: &Patterns) {
+    nodes.retain(|node| match node {
+        Node::Comment(_) => false,
+        Node::Element(element) => {
+            let names = class_and_id(element);
+            let unlikely = patterns.unlikely.is_match(&names)
+                && !patterns.maybe.is_match(&names)
+                && !is_named(element, &["html", "body", "article", "main"]);
+            !(text::is_hidden(element) || is_named(element, &BOILERPLATE_ELEMENTS) || is_hidden(element) || unlikely)
+        }
+        Node::Text(_) => true,
+    });
+    for node in nodes.iter_mut() {
+        if let Node::Element(element) = node {
+            prune(&mut element.children, patterns);
+        }
+    }
+}
+
+fn has_block_children(element: &Element) -> bool {
+    element.children.iter().filter_map(|node| node.element()).any(text::is_block)
+}
+
+fn is_paragraph(element: &Element) -> bool {
+    is_named(element, &PARAGRAPH_ELEMENTS)
+        || (is_named(element, &["div", "section", "article"]) && !has_block_children(element))
+}
+
+fn score_paragraphs<'a>(
+    element: &'a Element,
+    ancestors: &mut Vec<&'a Element>,
+    scores: &mut HashMap<*const Element, (f64, &'a Element)>,
+    patterns: &Patterns,
+) {
+    if is_paragraph(element) && !ancestors.is_empty() {
+        let paragraph = text::visible_text(element, true);
+        let length = paragraph.chars().count();
+        if length >= MIN_PARAGRAPH_LENGTH {
+            let commas = paragraph.matches([',', '，', '、']).count() as f64;
+            let score = 1.0 + commas + (length as f64 / 100.0).floor().min(3.0);
+            for (level, ancestor) in ancestors.iter().rev().take(SCORED_ANCESTORS).enumerate() {
+                let divider = match level {
+                    0 => 1.0,
+                    1 => 2.0,
+                    _ => level as f64 * 3.0,
+                };
+                let entry = scores
+                    .entry(*ancestor as *const Element)
+                    .or_insert_with(|| (initial_score(ancestor, patterns), ancestor));
+                entry.0 += score / divider;
+            }
+        }
+    }
+    ancestors.push(element);
+    for child in element.children.iter().filter_map(|node| node.element()) {
+        score_paragraphs(child, ancestors, scores, patterns);
+    }
+    ancestors.pop();
+}
+
+/// Every element of `nodes` and their descendants, in document order.
+fn collect_elements<'a>(nodes: &'a [Node], elements: &mut Vec<&'a Element>) {
+    for element in nodes.iter().filter_map(|node| node.element()) {
+        elements.push(element);
+        collect_elements(&element.children, elements);
+    }
+}
+
+fn initial_score(element: &Element, patterns: &Patterns) -> f64 {
+    let tag_score = match element.name.to_lowercase().as_str() {
+        "article" => 10.0,
+        "div" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    tag_score + patterns.class_weight(element)
+}
+
+/// Share of the text of `element` that is inside links.
+fn link_density(element: &Element) -> f64 {
+    let length = text::visible_text(element, true).chars().count();
+    if length == 0 {
+        return 0.0;
+    }
+    let mut link_length = 0;
+    let mut pending = vec![element];
+    while let Some(current) = pending.pop() {
+        for child in current.children.iter().filter_map(|node| node.element()) {
+            if child.name.eq_ignore_ascii_case("a") {
+                link_length += text::visible_text(child, true).chars().count();
+            } else {
+                pending.push(child);
+            }
+        }
+    }
+    link_length as f64 / length as f64
+}
+
+fn find_parent<'a>(nodes: &'a [Node], target: &Element) -> Option<&'a Element> {
+    for element in nodes.iter().filter_map(|node| node.element()) {
+        if element.children.iter().any(|child| child.element().is_some_and(|child| std::ptr::eq(child, target))) {
+            return Some(element);
+        }
+        if let Some(parent) = find_parent(&element.children, target) {
+            return Some(parent);
+        }
+    }
+    None
+}
+
+/// A paragraph next to the content with enough text and few links is part of the content too.
+fn is_standalone_paragraph(element: &Element) -> bool {
+    if !element.name.eq_ignore_ascii_case("p") {
+        return false;
+    }
+    let paragraph = text::visible_text(element, true);
+    let density = link_density(element);
+    let length = paragraph.chars().count();
+    (length > 80 && density < 0.25) || (length > 0 && density == 0.0 && paragraph.ends_with('.'))
+}
+
+/// Removes the blocks of the content that look like boilerplate and attributes that only matter
+/// for rendering.
+fn clean(element: &mut Element, patterns: &Patterns) {
+    element.attributes.retain(|key, _| {
+        let key = key.to_lowercase();
+        key != "style" && !key.starts_with("on")
+    });
+    element.children.retain(|node| match node {
+        Node::Element(child) => !is_boilerplate_block(child, patterns),
+        _ => true,
+    });
+    for child in element.children.iter_mut() {
+        if let Node::Element(child) = child {
+            clean(child, patterns);
+        }
+    }
+}
+
+fn is_boilerplate_block(element: &Element, patterns: &Patterns) -> bool {
+    if is_named(element, &["h1", "h2", "h3"]) {
+        return patterns.class_weight(element) < 0.0;
+    }
+    if !is_named(element, &["div", "section", "ul", "ol", "table", "header"]) {
+        return false;
+    }
+    if patterns.class_weight(element) < 0.0 {
+        return true;
+    }
+    let length = text::visible_text(element, true).chars().count();
+    let density = link_density(element);
+    let has = |name: &str| contains_element(element, name);
+    (density > 0.5 && length < 500)
+        || (length < MIN_PARAGRAPH_LENGTH && !has("img") && !has("p") && !has("pre") && !has("video") && !has("picture"))
+}
+
+fn contains_element(element: &Element, name: &str) -> bool {
+    element.children.iter().filter_map(|node| node.element()).any(|child| {
+        child.name.eq_ignore_ascii_case(name) || contains_element(child, name)
+    })
+}
+
+/// Text of the content split on block boundaries, one entry per non empty paragraph.
+fn paragraphs(element: &Element) -> Vec<String> {
+    let mut result = vec![];
+    let mut current = String::new();
+    collect_paragraphs(element, &mut current, &mut result);
+    flush(&mut current, &mut result);
+    result
+}
+
+fn collect_paragraphs(element: &Element, current: &mut String, result: &mut Vec<String>) {
+    for child in element.children.iter() {
+        match child {
+            Node::Text(text) => current.push_str(text),
+            Node::Element(child) if text::is_block(child) => {
+                flush(current, result);
+                collect_paragraphs(child, current, result);
+                flush(current, result);
+            }
+            Node::Element(child) => collect_paragraphs(child, current, result),
+            Node::Comment(_) => {}
+        }
+    }
+}
+
+fn flush(current: &mut String, result: &mut Vec<String>) {
+    let paragraph = text::collapse_whitespace(&text::decode_entities(current));
+    if !paragraph.is_empty() {
+        result.push(paragraph);
+    }
+    current.clear();
+}
+
+fn meta_value(metadata: &PageMetadata, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| {
+        metadata.meta
+            .iter()
+            .find(|(name, content)| name.eq_ignore_ascii_case(key) && !content.is_empty())
+            .map(|(_, content)| content.clone())
+    })
+}
+
+/// First value of `key` in the JSON-LD blocks, looking into `@graph` lists.
+fn json_ld_value(metadata: &PageMetadata, key: &str) -> Option<String> {
+    fn find(value: &serde_json::Value, key: &str) -> Option<String> {
+        match value {
+            serde_json::Value::Array(values) => values.iter().find_map(|value| find(value, key)),
+            serde_json::Value::Object(object) => match object.get(key) {
+                Some(serde_json::Value::String(text)) => Some(text.clone()),
+                Some(serde_json::Value::Object(inner)) => inner.get("name").and_then(|name| name.as_str()).map(String::from),
+                Some(serde_json::Value::Array(values)) => {
+                    let names: Vec<String> = values
+                        .iter()
+                        .filter_map(|value| value.as_str().or_else(|| value.get("name").and_then(|name| name.as_str())))
+                        .map(String::from)
+                        .collect();
+                    if names.is_empty() { None } else { Some(names.join(", ")) }
+                }
+                _ => object.get("@graph").and_then(|graph| find(graph, key)),
+            },
+            _ => None,
+        }
+    }
+    metadata.json_ld.iter().find_map(|value| find(value, key))
+}
+
+fn article_title(html: &Dom, metadata: &PageMetadata) -> Option<String> {
+    if let Some(title) = meta_value(metadata, &["og:title", "twitter:title"]) {
+        return Some(title);
+    }
+    if let Some(title) = metadata.title.as_ref().filter(|title| !title.is_empty()) {
+        // "Headline | Site name", the headline is kept when it is not too short to stand on its own
+        for separator in [" | ", " - ", " – ", " — ", " :: ", " » "] {
+            if let Some((headline, _)) = title.rsplit_once(separator) {
+                if headline.split_whitespace().count() >= 3 {
+                    return Some(headline.to_string());
+                }
+            }
+        }
+        return Some(title.clone());
+    }
+    find_element(&html.children, &|element| element.name.eq_ignore_ascii_case("h1"))
+        .map(|heading| text::visible_text(heading, true))
+}
+
+fn byline(html: &Dom, metadata: &PageMetadata, patterns: &Patterns) -> Option<String> {
+    if let Some(author) = meta_value(metadata, &["author", "article:author", "twitter:creator"]) {
+        return Some(author);
+    }
+    if let Some(author) = json_ld_value(metadata, "author") {
+        return Some(author);
+    }
+    let is_byline = |element: &Element| {
+        attribute(element, "rel").is_some_and(|rel| rel.eq_ignore_ascii_case("author"))
+            || attribute(element, "itemprop").is_some_and(|itemprop| itemprop.contains("author"))
+            || patterns.byline.is_match(&class_and_id(element))
+    };
+    find_element(&html.children, &|element| {
+        let length = text::visible_text(element, true).chars().count();
+        is_byline(element) && length > 0 && length < 100
+    })
+    .map(|element| text::visible_text(element, true))
+}
+
+fn published(html: &Dom, metadata: &PageMetadata) -> Option<String> {
+    let keys = [
+        "article:published_time", "og:published_time", "datePublished", "date", "pubdate",
+        "publishdate", "dc.date", "dcterms.date", "dc.date.issued",
+    ];
+    if let Some(date) = meta_value(metadata, &keys) {
+        return Some(date);
+    }
+    if let Some(date) = json_ld_value(metadata, "datePublished") {
+        return Some(date);
+    }
+    let date_published = find_element(&html.children, &|element| {
+        attribute(element, "itemprop").is_some_and(|itemprop| itemprop == "datePublished")
+    });
+    if let Some(element) = date_published {
+        return attribute(element, "datetime")
+            .or_else(|| attribute(element, "content"))
+            .or_else(|| Some(text::visible_text(element, true)));
+    }
+    find_element(&html.children, &|element| element.name.eq_ignore_ascii_case("time") && attribute(element, "datetime").is_some())
+        .and_then(|element| attribute(element, "datetime"))
+}
+
+fn find_element<'a>(nodes: &'a [Node], predicate: &dyn Fn(&Element) -> bool) -> Option<&'a Element> {
+    for element in nodes.iter().filter_map(|node| node.element()) {
+        if predicate(element) {
+            return Some(element);
+        }
+        if let Some(found) = find_element(&element.children, predicate) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Rivers rise after a week of rain | Daily Planet</title>
+    <meta name="author" content="Lois Lane">
+    <meta property="article:published_time" content="2024-03-01T08:00:00Z">
+</head>
+<body>
+    <nav><a href="/">Home</a><a href="/news">News</a><a href="/sport">Sport</a></nav>
+    <div class="sidebar"><p>Most read: something else entirely, and a lot of other links.</p></div>
+    <div id="main">
+        <article class="post">
+            <h1>Rivers rise after a week of rain</h1>
+            <p>Rivers across the region rose sharply on Thursday, after a week of heavy rain, flooding fields and closing several roads.</p>
+            <div class="ad-slot"><a href="/ads">Buy now</a></div>
+            <p>Forecasters expect the water to peak on Saturday, and residents near the banks have been asked to prepare.</p>
+            <script>track("article");</script>
+            <p style="color: red" onclick="share()">Emergency services said no injuries had been reported so far, but warned drivers to stay away.</p>
+        </article>
+        <div class="comments"><p>First! This is a comment, with commas, that should not be kept at all.</p></div>
+    </div>
+    <footer><p>Copyright Daily Planet, all rights reserved, since forever.</p></footer>
+</body>
+</html>"#;
+
+    #[test]
+    fn test_extract_article() {
+        let article = extract_article(PAGE, &ParseMode::Lenient).unwrap().unwrap();
+        assert_eq!(article.title, Some("Rivers rise after a week of rain".to_string()));
+        assert_eq!(article.byline, Some("Lois Lane".to_string()));
+        assert_eq!(article.published, Some("2024-03-01T08:00:00Z".to_string()));
+        let lines: Vec<&str> = article.text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "Rivers rise after a week of rain");
+        assert!(lines[1].starts_with("Rivers across the region"));
+        assert!(lines[3].starts_with("Emergency services"));
+        for boilerplate in ["Home", "Most read", "Buy now", "First!", "Copyright", "track("] {
+            assert!(!article.text.contains(boilerplate), "{} should be removed", boilerplate);
+            assert!(!article.html.contains(boilerplate), "{} should be removed", boilerplate);
+        }
+        assert!(!article.html.contains("onclick") && !article.html.contains("style="));
+    }
+
+    #[test]
+    fn test_extract_article_json_ld_and_time() {
+        let raw_html = r#"<html><head>
+            <title>Short</title>
+            <script type="application/ld+json">{"@graph": [{"@type": "NewsArticle", "author": [{"name": "Clark Kent"}]}]}</script>
+        </head><body><div>
+            <p><time datetime="2024-05-06">May 6</time></p>
+            <p>A paragraph long enough to be scored as article content, with a comma.</p>
+        </div></body></html>"#;
+        let article = extract_article(raw_html, &ParseMode::Lenient).unwrap().unwrap();
+        assert_eq!(article.title, Some("Short".to_string()));
+        assert_eq!(article.byline, Some("Clark Kent".to_string()));
+        assert_eq!(article.published, Some("2024-05-06".to_string()));
+    }
+
+    #[test]
+    fn test_extract_article_equal_scores() {
+        let raw_html = r#"<html><body><div>
+            <section><div><p>The first candidate paragraph, long enough to be scored.</p></div></section>
+            <section><div><p>The other candidate paragraph, long enough to be scored.</p></div></section>
+        </div></body></html>"#;
+        for _ in 0..10 {
+            let article = extract_article(raw_html, &ParseMode::Lenient).unwrap().unwrap();
+            assert_eq!(article.text, "The first candidate paragraph, long enough to be scored.");
+        }
+    }
+
+    #[test]
+    fn test_extract_article_without_content() {
+        let raw_html = "<html><body><nav><a href='/'>Home</a></nav><p>Too short.</p></body></html>";
+        assert_eq!(extract_article(raw_html, &ParseMode::Lenient).unwrap(), None);
+    }
+}
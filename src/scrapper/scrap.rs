@@ -1,19 +1,136 @@
+use std::collections::HashMap;
+
 use html_parser;
 use html_parser::Dom;
 use html_parser::Element;
+use html_parser::Node;
+
+use super::parse::{self, attribute, inner_html, outer_html, parse_html, ParseError, ParseMode};
+use super::query::CompiledFilter;
+use super::text;
+use crate::storage::records::{json_object, Record, Value};
 
 pub enum FilterType {
     And,
     Or,
 }
 
+/// How the values of a `TextFilter` or `AttributeFilter` are compared.
+///
+/// * `Contains` - The value appears anywhere in the text. Default for `TextFilter`.
+/// * `Exact` - The whole text is equal to the value. Default for `AttributeFilter`.
+/// * `Regex` - The value is a regular expression that must match the text.
+/// * `WholeWord` - The value appears in the text delimited by non-word characters.
+/// * `Exists` - Only the presence of the attribute is checked, the value is ignored (`[attr]`).
+/// * `Prefix` - The text starts with the value (`[attr^=value]`).
+/// * `Suffix` - The text ends with the value (`[attr$=value]`).
+/// * `Word` - The value is one of the whitespace separated words of the text (`[attr~=value]`).
+/// * `LangPrefix` - The text is the value or starts with the value followed by `-` (`[attr|=value]`).
+/// * `GreaterThan`, `GreaterOrEqual`, `LessThan`, `LessOrEqual` - Both the text and the value
+///   are parsed as numbers and compared. Text that is not a number never matches.
+///
+/// `Contains` is the equivalent of `[attr*=value]` and `Exact` of `[attr=value]`.
+pub enum MatchMode {
+    Contains,
+    Exact,
+    Regex,
+    WholeWord,
+    Exists,
+    Prefix,
+    Suffix,
+    Word,
+    LangPrefix,
+    GreaterThan,
+    GreaterOrEqual,
+    LessThan,
+    LessOrEqual,
+}
+
+/// Tag names an element must have, one of `filter` must match.
+///
+/// * `div` - Tag name, compared case insensitively unless `case_sensitive` is set.
+/// * `*` - Any element.
+/// * `my-*` - Tag names starting with `my-`, for custom elements.
+/// * `svg:path`, `math:*`, `html:a` - Elements of the SVG, MathML or HTML namespace. Elements
+///   inside `<svg>` and `<math>` are in those namespaces, except the content of `<foreignObject>`.
+///   Names without a prefix match elements of any namespace.
+///
+/// `case_sensitive` defaults to `false`.
 pub struct TagFilter {
     pub filter: Vec<String>,
+    pub case_sensitive: Option<bool>,
+}
+
+/// Namespace of an element, derived from its `<svg>` or `<math>` ancestor or from its prefix
+/// (`<svg:path>` in XHTML style markup).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Namespace {
+    Html,
+    Svg,
+    MathMl,
+}
+
+impl Namespace {
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        match prefix.to_lowercase().as_str() {
+            "html" => Some(Namespace::Html),
+            "svg" => Some(Namespace::Svg),
+            "math" => Some(Namespace::MathMl),
+            _ => None,
+        }
+    }
+
+    /// Namespace of an element named `name` whose parent is in `self`.
+    pub(crate) fn of(self, name: &str) -> Namespace {
+        if let Some((namespace, _)) = split_prefix(name) {
+            return namespace;
+        }
+        if name.eq_ignore_ascii_case("svg") {
+            Namespace::Svg
+        } else if name.eq_ignore_ascii_case("math") {
+            Namespace::MathMl
+        } else {
+            self
+        }
+    }
+
+    /// Namespace of the children of an element named `name` in `self`.
+    pub(crate) fn for_children(self, name: &str) -> Namespace {
+        let local_name = split_prefix(name).map_or(name, |(_, local_name)| local_name);
+        if self == Namespace::Svg && local_name.eq_ignore_ascii_case("foreignObject") {
+            return Namespace::Html;
+        }
+        self
+    }
+}
+
+fn split_prefix(name: &str) -> Option<(Namespace, &str)> {
+    let (prefix, local_name) = name.split_once(':')?;
+    Namespace::from_prefix(prefix).map(|namespace| (namespace, local_name))
+}
+
+/// Whether one tag `pattern` of a `TagFilter` matches an element named `name` in `namespace`.
+pub(crate) fn tag_matches(pattern: &str, name: &str, namespace: Namespace, case_sensitive: bool) -> bool {
+    let (pattern_namespace, pattern) = match split_prefix(pattern) {
+        Some((namespace, local_name)) => (Some(namespace), local_name),
+        None => (None, pattern),
+    };
+    let name = split_prefix(name).map_or(name, |(_, local_name)| local_name);
+    if pattern_namespace.is_some_and(|pattern_namespace| pattern_namespace != namespace) {
+        return false;
+    }
+    let equal = |a: &str, b: &str| if case_sensitive { a == b } else { a.eq_ignore_ascii_case(b) };
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.len() >= prefix.len() && name.is_char_boundary(prefix.len()) && equal(&name[..prefix.len()], prefix),
+        None => equal(name, pattern),
+    }
 }
 
 pub struct AttributeFilter {
     pub filter: Vec<(String, String)>,
     pub filter_type: FilterType,
+    pub match_mode: Option<MatchMode>, // Defaults to MatchMode::Exact
+    pub case_insensitive: Option<bool>, // Defaults to false
 }
 
 pub struct IdFilter {
@@ -26,11 +143,105 @@ pub struct ClassFilter {
 }
 
 
+/// Which text of an element a `TextFilter` is matched against.
+///
+/// * `Source` - The raw source of the element, markup and attributes included. Default.
+/// * `OwnText` - The visible text of the direct text children of the element.
+/// * `DescendantText` - The visible text of the element and all its descendants.
+///
+/// Visible text has entities decoded, whitespace collapsed and excludes comments,
+/// `<script>` and `<style>` content.
+pub enum TextSource {
+    Source,
+    OwnText,
+    DescendantText,
+}
+
 pub struct TextFilter {
     pub filter: Vec<String>,
     pub filter_type: FilterType,
+    pub match_mode: Option<MatchMode>, // Defaults to MatchMode::Contains
+    pub case_insensitive: Option<bool>, // Defaults to false
+    pub text_source: Option<TextSource>, // Defaults to TextSource::Source
+}
+
+/// What `scrape` returns for every match.
+///
+/// * `OuterHtml` - The source of the element, its own tags included. Default.
+/// * `InnerHtml` - The source of the content of the element, without its own tags.
+/// * `Text` - The visible text of the element and its descendants.
+/// * `Attribute` - The value of one attribute, `id` and `class` included. Matches without it
+///   are left out, after `offset` and `limit` were applied.
+/// * `Attributes` - The given attributes of the element as a JSON object, those it does not
+///   have are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    OuterHtml,
+    InnerHtml,
+    Text,
+    Attribute(String),
+    Attributes(Vec<String>),
+}
+
+/// Content of the document that can be left out of `scrape`.
+///
+/// * `Scripts` - `<script>` elements.
+/// * `Styles` - `<style>` elements.
+/// * `Noscript` - `<noscript>` elements.
+/// * `Templates` - `<template>` elements.
+/// * `Comments` - Comment nodes.
+///
+/// Excluded elements are neither matched nor traversed, and excluded content is removed from
+/// the text checked by text filters, `TextSource::Source` included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Content {
+    Scripts,
+    Styles,
+    Noscript,
+    Templates,
+    Comments,
+}
+
+impl Content {
+    fn tag_name(self) -> Option<&'static str> {
+        match self {
+            Content::Scripts => Some("script"),
+            Content::Styles => Some("style"),
+            Content::Noscript => Some("noscript"),
+            Content::Templates => Some("template"),
+            Content::Comments => None,
+        }
+    }
+}
+
+/// Whether elements named `name` are left out by `exclude`.
+pub(crate) fn is_excluded(name: &str, exclude: &[Content]) -> bool {
+    exclude.iter().any(|content| content.tag_name().is_some_and(|tag_name| name.eq_ignore_ascii_case(tag_name)))
 }
 
+/// Options for selecting the elements returned by `scrape`.
+///
+/// Matches are always returned in document order.
+///
+/// # Fields
+///
+/// * `tags` - The tag names an element must have.
+/// * `id_filter`, `class_filter` - Restrict matches by id or class.
+/// * `attributes_include`, `attributes_exclude` - Attributes a match must or must not fulfill.
+/// * `text_include`, `text_exclude` - Text a match must or must not contain.
+/// * `offset` - Number of matches to skip before returning results. Defaults to `0`.
+/// * `limit` - Maximum number of matches to return. Defaults to no limit. Traversal of the
+///   document stops as soon as `offset + limit` matches were found.
+/// * `ancestor` - Only elements nested inside an element matching these options are returned.
+///   Only the element filters of the ancestor options are used, its `offset`, `limit` and
+///   tree constraints are ignored.
+/// * `min_depth`, `max_depth` - Depth range of the returned elements, top level nodes of the
+///   document have depth `0`.
+/// * `skip_nested` - Whether elements nested inside another match are left out. Defaults to `false`.
+/// * `parse_mode` - How the document is parsed. Defaults to `ParseMode::Strict`.
+/// * `output` - What is returned for every match, see `Output`. Defaults to `Output::OuterHtml`.
+/// * `exclude` - Content left out of traversal and text filters, see `Content`. Defaults to
+///   none, scripts, styles, templates and comments are searched like any other content.
 pub struct ScrapeOptions {
     pub tags: TagFilter,
     pub id_filter: Option<IdFilter>,
@@ -39,123 +250,227 @@ pub struct ScrapeOptions {
     pub attributes_exclude: Option<AttributeFilter>,
     pub text_include: Option<TextFilter>,
     pub text_exclude: Option<TextFilter>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub ancestor: Option<Box<ScrapeOptions>>,
+    pub min_depth: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub skip_nested: Option<bool>,
+    pub parse_mode: Option<ParseMode>,
+    pub output: Option<Output>,
+    pub exclude: Option<Vec<Content>>,
 }
 
-pub fn scrape(raw_html: &str, options:&ScrapeOptions) -> Vec<String> {
-    let html = Dom::parse(raw_html).unwrap();
-    let mut current:Vec<html_parser::Node> = Vec::new();
-    let mut result:Vec<String> = Vec::new();
-    for node in html.children {
-        current.push(node);
-        while !current.is_empty(){
-            let node = current.pop().unwrap();
-            if let Some(element) = node.element(){
-                element.children.iter().for_each(|x| current.push(x.clone()));
-            }
-            if let Some(ref id_filter) = options.id_filter{
-                if !has_id(node.element(), id_filter){
-                    continue;
-                }
-            }
-            if let Some(ref class_filter) = options.class_filter{
-                if !has_class(node.element(), class_filter){
-                    continue;
-                }
-            }
-            if !has_tagname(node.element(), &options.tags){
-                continue;
-            }
-            if let Some(ref attributes) = options.attributes_include{
-                if !fulfill_attribute_filter(node.element(), attributes){
-                    continue;
-                }
-            }
-            if let Some(ref attributes) = options.attributes_exclude{
-                if fulfill_attribute_filter(node.element(), attributes){
-                    continue;
-                }
-            }
-            if let Some(ref text_filters) = options.text_include{
-                if !filter_by_text(node.element(), text_filters){
-                    continue;
+impl ScrapeOptions {
+    pub fn new(tags: TagFilter) -> Self {
+        Self {
+            tags,
+            id_filter: None,
+            class_filter: None,
+            attributes_include: None,
+            attributes_exclude: None,
+            text_include: None,
+            text_exclude: None,
+            offset: None,
+            limit: None,
+            ancestor: None,
+            min_depth: None,
+            max_depth: None,
+            skip_nested: None,
+            parse_mode: None,
+            output: None,
+            exclude: None,
+        }
+    }
+}
+
+pub fn scrape(raw_html: &str, options:&ScrapeOptions) -> Result<Vec<String>, ParseError> {
+    let html = parse_document(raw_html, options)?;
+    let output = options.output.as_ref().unwrap_or(&Output::OuterHtml);
+    Ok(matching_elements(&html, options)
+        .iter()
+        .filter_map(|element| project(element, output))
+        .collect())
+}
+
+/// Same as `scrape` with every match returned as a record, ready for `store_records` without
+/// parsing the elements again. The record has an `html` field for `Output::OuterHtml` and
+/// `Output::InnerHtml`, a `text` field for `Output::Text` and one field per attribute found
+/// for `Output::Attribute` and `Output::Attributes`.
+pub fn scrape_records(raw_html: &str, options:&ScrapeOptions) -> Result<Vec<Record>, ParseError> {
+    let html = parse_document(raw_html, options)?;
+    let output = options.output.as_ref().unwrap_or(&Output::OuterHtml);
+    Ok(matching_elements(&html, options)
+        .iter()
+        .filter_map(|element| output_record(element, output))
+        .collect())
+}
+
+/// The value `scrape` returns for a match, `None` when the attribute of `Output::Attribute`
+/// is missing.
+pub(crate) fn project(element: &Element, output: &Output) -> Option<String> {
+    match output {
+        Output::OuterHtml => Some(parse::source(element).into_owned()),
+        Output::InnerHtml => Some(inner_html(element)),
+        Output::Text => Some(text::visible_text(element, true)),
+        Output::Attribute(name) => attribute(element, name),
+        Output::Attributes(_) => output_record(element, output).map(|record| json_object(&record, false, 0)),
+    }
+}
+
+fn output_record(element: &Element, output: &Output) -> Option<Record> {
+    let mut record = Record::new();
+    match output {
+        Output::OuterHtml | Output::InnerHtml => record.insert("html", Value::Text(project(element, output)?)),
+        Output::Text => record.insert("text", Value::Text(text::visible_text(element, true))),
+        Output::Attribute(name) => record.insert(name, Value::Text(attribute(element, name)?)),
+        Output::Attributes(names) => {
+            for name in names {
+                if let Some(value) = attribute(element, name) {
+                    record.insert(name, Value::Text(value));
                 }
             }
-            if let Some(ref text_filters) = options.text_exclude{
-                if filter_by_text(node.element(), text_filters){
-                    continue;
-                }
-            }            
-            result.push(node.element().unwrap().source_span.text.clone());
         }
     }
-    return result;
+    Some(record)
 }
 
-fn has_tagname(element: Option<&Element>, tags: &TagFilter) -> bool {
-    let element = match element {
-        Some(e) => return tags.filter.iter().any(|x| x.eq(&e.name)),
-        None => return false,
-    };
+/// Scrapes the elements matching `options` and returns, for every match, the named
+/// capture groups of the `MatchMode::Regex` patterns in `options.text_include`.
+///
+/// Results are in the same order as `scrape`. Groups that did not participate in the
+/// match are left out of the map.
+pub fn extract_captures(raw_html: &str, options:&ScrapeOptions) -> Result<Vec<HashMap<String, String>>, ParseError> {
+    let html = parse_document(raw_html, options)?;
+    let filter = CompiledFilter::lenient(options);
+    Ok(matching_elements(&html, options)
+        .iter()
+        .map(|element| filter.captures(element, exclusions(options)))
+        .collect())
 }
 
-fn has_id(element: Option<&Element>, id_filter: &IdFilter) -> bool {
-    let element = match element {
-        Some(e) => e,
-        None => return false,
-    };
-    id_filter.filter.iter().any(|id| {
-        element.id.clone().map_or(false, |v| v.eq(id))
-    })
+/// Returns the first element matching `options`, skipping `options.offset` matches, as
+/// configured by `options.output`. Traversal stops at the first match.
+pub fn first(raw_html: &str, options:&ScrapeOptions) -> Result<Option<String>, ParseError> {
+    let html = parse_document(raw_html, options)?;
+    let offset = options.offset.unwrap_or(0);
+    let mut found = None;
+    walk_matches(&html, options, |index, element| {
+        if index < offset {
+            return true;
+        }
+        found = project(element, options.output.as_ref().unwrap_or(&Output::OuterHtml));
+        false
+    });
+    Ok(found)
 }
 
-fn has_class(element: Option<&Element>, class_filter: &ClassFilter) -> bool {
-    let element = match element {
-        Some(e) => e,
-        None => return false,
-    };
-    match class_filter.filter_type {
-        FilterType::And => class_filter.filter.iter().all(|class| {
-            element.classes.iter().any(|c| c.eq(class))
-        }),
-        FilterType::Or => class_filter.filter.iter().any(|class| {
-            element.classes.iter().any(|c| c.eq(class))
-        }),
+pub(crate) fn parse_document(raw_html: &str, options:&ScrapeOptions) -> Result<Dom, ParseError> {
+    parse_html(raw_html, options.parse_mode.as_ref().unwrap_or(&ParseMode::Strict))
+}
+
+pub(crate) fn matching_elements<'a>(html: &'a Dom, options:&ScrapeOptions) -> Vec<&'a Element> {
+    let offset = options.offset.unwrap_or(0);
+    let mut result:Vec<&Element> = Vec::new();
+    walk_matches(html, options, |index, element| {
+        if index >= offset {
+            result.push(element);
+        }
+        options.limit.is_none_or(|limit| result.len() < limit)
+    });
+    result
+}
+
+/// Visits the elements matching `options` in document order, calling `on_match` with the
+/// index of the match. Traversal stops as soon as `on_match` returns `false`.
+fn walk_matches<'a, F>(html: &'a Dom, options:&ScrapeOptions, mut on_match: F)
+where
+    F: FnMut(usize, &'a Element) -> bool,
+{
+    if options.limit == Some(0) {
+        return;
+    }
+    let min_depth = options.min_depth.unwrap_or(0);
+    let max_depth = options.max_depth.unwrap_or(usize::MAX);
+    let skip_nested = options.skip_nested.unwrap_or(false);
+    let filter = CompiledFilter::lenient(options);
+    let ancestor = options.ancestor.as_deref().map(CompiledFilter::lenient);
+    let exclude = exclusions(options);
+    // every pending node keeps its depth, the namespace of its parent and whether it is
+    // inside a matching ancestor
+    let mut current:Vec<(&html_parser::Node, usize, Namespace, bool)> = html.children
+        .iter()
+        .rev()
+        .map(|node| (node, 0, Namespace::Html, options.ancestor.is_none()))
+        .collect();
+    let mut index = 0;
+    while let Some((node, depth, parent_namespace, inside_ancestor)) = current.pop() {
+        let Some(element) = node.element() else {
+            continue;
+        };
+        if is_excluded(&element.name, exclude) {
+            continue;
+        }
+        let namespace = parent_namespace.of(&element.name);
+        let is_match = inside_ancestor
+            && depth >= min_depth
+            && depth <= max_depth
+            && filter.matches(element, namespace, exclude);
+        let descend = depth < max_depth && !(is_match && skip_nested);
+        if descend {
+            let child_inside_ancestor = inside_ancestor || ancestor
+                .as_ref()
+                .is_some_and(|ancestor| ancestor.matches(element, namespace, exclude));
+            let child_namespace = namespace.for_children(&element.name);
+            // children are pushed in reverse so they are popped in document order
+            element.children
+                .iter()
+                .rev()
+                .for_each(|x| current.push((x, depth + 1, child_namespace, child_inside_ancestor)));
+        }
+        if !is_match {
+            continue;
+        }
+        if !on_match(index, element) {
+            return;
+        }
+        index += 1;
     }
 }
 
-fn fulfill_attribute_filter(element: Option<&Element>, attributes: &AttributeFilter) -> bool {
-    let element = match element {
-        Some(e) => e,
-        None => return false,
-    };
-    match attributes.filter_type {
-        FilterType::And => attributes.filter.iter().all(|(key, value)| {
-            match key.to_lowercase().as_str() {
-                "class" => return element.classes.iter().any(|c| c.eq(value)),
-                "id" => return element.id == Some(value.clone()),
-                _ => element.attributes.get(key).map_or(false, |v| v.clone().unwrap().eq(value)),
-            }
-        }),
-        FilterType::Or => attributes.filter.iter().any(|(key, value)| {
-            element.attributes.get(key).map_or(false, |v| v.clone().unwrap().eq(value))
-        }),
+fn exclusions(options: &ScrapeOptions) -> &[Content] {
+    options.exclude.as_deref().unwrap_or(&[])
+}
+
+/// The text of `element` checked by text filters, without the `exclude`d content.
+pub(crate) fn element_text(element: &Element, source: &TextSource, exclude: &[Content]) -> String {
+    if exclude.is_empty() {
+        return match source {
+            TextSource::Source => parse::source(element).into_owned(),
+            TextSource::OwnText => text::visible_text(element, false),
+            TextSource::DescendantText => text::visible_text(element, true),
+        };
+    }
+    match source {
+        TextSource::Source => outer_html(&without_excluded(element, exclude)),
+        TextSource::OwnText => text::visible_text(&without_excluded(element, exclude), false),
+        TextSource::DescendantText => text::visible_text(&without_excluded(element, exclude), true),
     }
 }
 
-fn filter_by_text(element: Option<&Element>, text_filters: &TextFilter) -> bool {
-    let element = match element {
-        Some(t) => t,
-        None => return false,
-    };
-    let node_text = &element.source_span.text;
-    match text_filters.filter_type{
-        FilterType::And => text_filters.filter.iter().all(|fragment|{
-            node_text.contains(fragment)
-        }),
-        FilterType::Or => text_filters.filter.iter().any(|fragment|{
-            node_text.contains(fragment)
+/// A copy of `element` without its excluded descendants.
+fn without_excluded(element: &Element, exclude: &[Content]) -> Element {
+    let mut copy = element.clone();
+    copy.children = element.children
+        .iter()
+        .filter_map(|child| match child {
+            Node::Comment(_) if exclude.contains(&Content::Comments) => None,
+            Node::Element(child) if is_excluded(&child.name, exclude) => None,
+            Node::Element(child) => Some(Node::Element(without_excluded(child, exclude))),
+            other => Some(other.clone()),
         })
-    }
+        .collect();
+    copy
 }
 
 #[cfg(test)]
@@ -166,26 +481,197 @@ mod tests {
         Dom::parse(raw_html).unwrap()
     }
 
+    // the filters are checked one at a time through the compiled filter `scrape` uses
+    fn matches(element: Option<&Element>, namespace: Namespace, options: ScrapeOptions, exclude: &[Content]) -> bool {
+        element.is_some_and(|element| CompiledFilter::lenient(&options).matches(element, namespace, exclude))
+    }
+
+    fn any_tag() -> TagFilter {
+        TagFilter {
+            filter: vec!["*".to_string()],
+            case_sensitive: None,
+        }
+    }
+
+    fn has_tagname(element: Option<&Element>, namespace: Namespace, tags: TagFilter) -> bool {
+        matches(element, namespace, ScrapeOptions::new(tags), &[])
+    }
+
+    fn has_id(element: Option<&Element>, id_filter: IdFilter) -> bool {
+        let options = ScrapeOptions {
+            id_filter: Some(id_filter),
+            ..ScrapeOptions::new(any_tag())
+        };
+        matches(element, Namespace::Html, options, &[])
+    }
+
+    fn has_class(element: Option<&Element>, class_filter: ClassFilter) -> bool {
+        let options = ScrapeOptions {
+            class_filter: Some(class_filter),
+            ..ScrapeOptions::new(any_tag())
+        };
+        matches(element, Namespace::Html, options, &[])
+    }
+
+    fn fulfill_attribute_filter(element: Option<&Element>, attributes: AttributeFilter) -> bool {
+        let options = ScrapeOptions {
+            attributes_include: Some(attributes),
+            ..ScrapeOptions::new(any_tag())
+        };
+        matches(element, Namespace::Html, options, &[])
+    }
+
+    fn filter_by_text(element: Option<&Element>, text_filters: TextFilter, exclude: &[Content]) -> bool {
+        let options = ScrapeOptions {
+            text_include: Some(text_filters),
+            ..ScrapeOptions::new(any_tag())
+        };
+        matches(element, Namespace::Html, options, exclude)
+    }
+
     #[test]
     fn test_has_tagname() {
         let dom = init_dom("<div></div>");
-        let element = dom.children.get(0).unwrap().element();
+        let element = dom.children.first().unwrap().element();
         let tags = TagFilter {
             filter: vec!["div".to_string()],
+            case_sensitive: None,
         };
         let tags_fail = TagFilter {
             filter: vec!["fail".to_string()],
+            case_sensitive: None,
         };
         // should return true
-        assert!(has_tagname(element, &tags));
+        assert!(has_tagname(element, Namespace::Html, tags));
         // should return false
-        assert!(!has_tagname(element, &tags_fail));
+        assert!(!has_tagname(element, Namespace::Html, tags_fail));
+    }
+
+    #[test]
+    fn test_has_tagname_patterns() {
+        let dom = init_dom("<DIV></DIV><my-card></my-card>");
+        let upper = dom.children[0].element();
+        let custom = dom.children[1].element();
+        let tags = |filter: &[&str], case_sensitive| TagFilter {
+            filter: filter.iter().map(|tag| tag.to_string()).collect(),
+            case_sensitive,
+        };
+        assert!(has_tagname(upper, Namespace::Html, tags(&["div"], None)));
+        assert!(!has_tagname(upper, Namespace::Html, tags(&["div"], Some(true))));
+        assert!(has_tagname(upper, Namespace::Html, tags(&["DIV"], Some(true))));
+        assert!(has_tagname(upper, Namespace::Html, tags(&["*"], None)));
+        assert!(has_tagname(custom, Namespace::Html, tags(&["my-*"], None)));
+        assert!(!has_tagname(upper, Namespace::Html, tags(&["my-*"], None)));
+        assert!(has_tagname(custom, Namespace::Html, tags(&["html:my-card"], None)));
+        assert!(!has_tagname(custom, Namespace::Svg, tags(&["html:my-card"], None)));
+    }
+
+    #[test]
+    fn test_scrape_output() {
+        let raw_html = r#"<ul><li><a href="/a" data-id="1" class="x">Alpha &amp; <b>co</b></a></li><li><a data-id="2">Beta</a></li><li><a href='/c>'>C</a></li></ul>"#;
+        let output = |output: Output| ScrapeOptions {
+            output: Some(output),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["a".to_string()],
+                case_sensitive: None,
+            })
+        };
+        assert_eq!(scrape(raw_html, &output(Output::InnerHtml)).unwrap(), vec!["Alpha &amp; <b>co</b>", "Beta", "C"]);
+        assert_eq!(scrape(raw_html, &output(Output::Text)).unwrap(), vec!["Alpha & co", "Beta", "C"]);
+        assert_eq!(scrape(raw_html, &output(Output::Attribute("href".to_string()))).unwrap(), vec!["/a", "/c>"]);
+        let attributes = output(Output::Attributes(vec!["data-id".to_string(), "class".to_string()]));
+        assert_eq!(scrape(raw_html, &attributes).unwrap(), vec![
+            r#"{"data-id":"1","class":"x"}"#,
+            r#"{"data-id":"2"}"#,
+            "{}",
+        ]);
+        let records = scrape_records(raw_html, &attributes).unwrap();
+        assert_eq!(records[1].get("data-id"), Some(&Value::Text("2".to_string())));
+        assert_eq!(records[1].get("class"), None);
+        let found = first(raw_html, &ScrapeOptions {
+            offset: Some(1),
+            ..output(Output::Attribute("data-id".to_string()))
+        });
+        assert_eq!(found.unwrap(), Some("2".to_string()));
+    }
+
+    #[test]
+    fn test_scrape_namespaces() {
+        let raw_html = r##"<div><a href="/page">page</a><svg><a href="#icon"><title>icon</title></a><foreignObject><a href="/inner">inner</a></foreignObject></svg><math><mi>x</mi></math></div>"##;
+        let options = |filter: &[&str]| ScrapeOptions {
+            parse_mode: Some(ParseMode::Strict),
+            ..ScrapeOptions::new(TagFilter {
+                filter: filter.iter().map(|tag| tag.to_string()).collect(),
+                case_sensitive: None,
+            })
+        };
+        assert_eq!(scrape(raw_html, &options(&["html:a"])).unwrap(), vec![
+            r#"<a href="/page">page</a>"#,
+            r#"<a href="/inner">inner</a>"#,
+        ]);
+        assert_eq!(scrape(raw_html, &options(&["svg:a", "svg:title"])).unwrap(), vec![
+            r##"<a href="#icon"><title>icon</title></a>"##,
+            r#"<title>icon</title>"#,
+        ]);
+        assert_eq!(scrape(raw_html, &options(&["math:*"])).unwrap(), vec![
+            "<math><mi>x</mi></math>",
+            "<mi>x</mi>",
+        ]);
+        // names without a prefix match in every namespace
+        assert_eq!(scrape(raw_html, &options(&["a"])).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_scrape_exclude() {
+        let raw_html = r#"<div><p>Price <!-- price: old -->10</p><script>var price = "<p>js price</p>";</script><template><p>price</p></template><noscript><p>Enable JavaScript</p></noscript></div>"#;
+        let options = |exclude: Option<Vec<Content>>| ScrapeOptions {
+            text_include: Some(TextFilter {
+                filter: vec!["price".to_string()],
+                filter_type: FilterType::And,
+                match_mode: None,
+                case_insensitive: None,
+                text_source: None,
+            }),
+            exclude,
+            parse_mode: Some(ParseMode::Strict),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["*".to_string()],
+                case_sensitive: None,
+            })
+        };
+        // by default the comment, the script and the template match
+        assert_eq!(scrape(raw_html, &options(None)).unwrap().len(), 5);
+        let everything = vec![Content::Scripts, Content::Styles, Content::Noscript, Content::Templates, Content::Comments];
+        assert!(scrape(raw_html, &options(Some(everything.clone()))).unwrap().is_empty());
+        assert_eq!(scrape(raw_html, &options(Some(vec![Content::Scripts, Content::Templates]))).unwrap(), vec![
+            raw_html,
+            "<p>Price <!-- price: old -->10</p>",
+        ]);
+        let paragraphs = ScrapeOptions {
+            exclude: Some(everything),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["p".to_string()],
+                case_sensitive: None,
+            })
+        };
+        assert_eq!(scrape(raw_html, &paragraphs).unwrap(), vec!["<p>Price <!-- price: old -->10</p>"]);
+    }
+
+    #[test]
+    fn test_element_text_source_exclude() {
+        // the excluded script also appears in an attribute value, which is kept
+        let dom = init_dom(r#"<div data-x="<script>a</script>"><script>a</script><!-- note -->b</div>"#);
+        let element = dom.children[0].element().unwrap();
+        assert_eq!(
+            element_text(element, &TextSource::Source, &[Content::Scripts, Content::Comments]),
+            r#"<div data-x="<script>a</script>">b</div>"#
+        );
     }
 
     #[test]
     fn test_has_id() {
         let dom = init_dom("<div id='test'></div>");
-        let element = dom.children.get(0).unwrap().element();
+        let element = dom.children.first().unwrap().element();
         let id_filter_success = IdFilter {
             filter: vec!["test".to_string()],
         };
@@ -193,15 +679,15 @@ mod tests {
             filter: vec!["fail".to_string()],
         };
         // should return true
-        assert!(has_id(element, &id_filter_success));
+        assert!(has_id(element, id_filter_success));
         // should return false
-        assert!(!has_id(element, &id_filter_fail));
+        assert!(!has_id(element, id_filter_fail));
     }
     
     #[test]
     fn test_has_class_and() {
         let dom = init_dom("<div class='test city note logo animal fruit'></div>");
-        let element = dom.children.get(0).unwrap().element();
+        let element = dom.children.first().unwrap().element();
         let class_filter_success = ClassFilter {
             filter: vec!["test".to_string(), "city".to_string(), "animal".to_string()],
             filter_type: FilterType::And,
@@ -211,15 +697,15 @@ mod tests {
             filter_type: FilterType::And,
         };
         // should return true
-        assert!(has_class(element, &class_filter_success));
+        assert!(has_class(element, class_filter_success));
         // should return false
-        assert!(!has_class(element, &class_filter_fail));
+        assert!(!has_class(element, class_filter_fail));
     }
 
     #[test]
     fn test_has_class_or() {
         let dom = init_dom("<div class='test city note logo animal fruit'></div>");
-        let element = dom.children.get(0).unwrap().element();
+        let element = dom.children.first().unwrap().element();
         let class_filter_success = ClassFilter {
             filter: vec!["test".to_string(), "fail".to_string(), "animal".to_string()],
             filter_type: FilterType::Or,
@@ -229,108 +715,497 @@ mod tests {
             filter_type: FilterType::Or,
         };
         // should return true
-        assert!(has_class(element, &class_filter_success), "should return true as at least one class matches");
+        assert!(has_class(element, class_filter_success), "should return true as at least one class matches");
         // should return false
-        assert!(!has_class(element, &class_filter_fail), "should return false as no class matches");
+        assert!(!has_class(element, class_filter_fail), "should return false as no class matches");
     }
 
     #[test]
     fn test_fulfill_attribute_filter_and() {
         let dom = init_dom("<div height='test' width='test'></div>");
-        let element = dom.children.get(0).unwrap().element();
+        let element = dom.children.first().unwrap().element();
         let attribute_filter = AttributeFilter {
             filter: vec![("height".to_string(), "test".to_string()), ("width".to_string(), "test".to_string())],
             filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
         };
         let attribute_filter_fail = AttributeFilter {
             filter: vec![("height".to_string(), "fail".to_string()), ("width".to_string(), "test".to_string())],
             filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
         };
-        assert!(fulfill_attribute_filter(element, &attribute_filter), "should return true as all attributes match");
-        assert!(!fulfill_attribute_filter(element, &attribute_filter_fail), "should return false as not all attributes match");
+        assert!(fulfill_attribute_filter(element, attribute_filter), "should return true as all attributes match");
+        assert!(!fulfill_attribute_filter(element, attribute_filter_fail), "should return false as not all attributes match");
     }
 
     #[test]
     fn test_fulfill_attribute_filter_or() {
         let dom = init_dom("<div height='test' width='test'></div>");
-        let element = dom.children.get(0).unwrap().element();
+        let element = dom.children.first().unwrap().element();
         let attribute_filter = AttributeFilter {
             filter: vec![("height".to_string(), "fail".to_string()), ("width".to_string(), "test".to_string())],
             filter_type: FilterType::Or,
+            match_mode: None,
+            case_insensitive: None,
         };
         let attribute_filter_fail = AttributeFilter {
             filter: vec![("height".to_string(), "null".to_string()), ("width".to_string(), "void".to_string())],
             filter_type: FilterType::Or,
+            match_mode: None,
+            case_insensitive: None,
         };
-        assert!(fulfill_attribute_filter(element, &attribute_filter), "should return true as at least one attribute match");
-        assert!(!fulfill_attribute_filter(element, &attribute_filter_fail), "should return false as no attribute match");
+        assert!(fulfill_attribute_filter(element, attribute_filter), "should return true as at least one attribute match");
+        assert!(!fulfill_attribute_filter(element, attribute_filter_fail), "should return false as no attribute match");
     }
 
     #[test]
     fn test_fulfill_attribute_filter_with_id_and_class() {
         let dom = init_dom("<div id='test_id' class='test_class'></div>");
-        let element = dom.children.get(0).unwrap().element();
+        let element = dom.children.first().unwrap().element();
 
         // Test for id attribute
         let attribute_filter_id = AttributeFilter {
             filter: vec![("id".to_string(), "test_id".to_string())],
             filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
         };
-        assert!(fulfill_attribute_filter(element, &attribute_filter_id), "should return true as id attribute matches");
+        assert!(fulfill_attribute_filter(element, attribute_filter_id), "should return true as id attribute matches");
 
         // Test for class attribute
         let attribute_filter_class = AttributeFilter {
             filter: vec![("class".to_string(), "test_class".to_string())],
             filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
         };
-        assert!(fulfill_attribute_filter(element, &attribute_filter_class), "should return true as class attribute matches");
+        assert!(fulfill_attribute_filter(element, attribute_filter_class), "should return true as class attribute matches");
 
         // Test for non-matching id attribute
         let attribute_filter_id_fail = AttributeFilter {
             filter: vec![("id".to_string(), "wrong_id".to_string())],
             filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
         };
-        assert!(!fulfill_attribute_filter(element, &attribute_filter_id_fail), "should return false as id attribute does not match");
+        assert!(!fulfill_attribute_filter(element, attribute_filter_id_fail), "should return false as id attribute does not match");
 
         // Test for non-matching class attribute
         let attribute_filter_class_fail = AttributeFilter {
             filter: vec![("class".to_string(), "wrong_class".to_string())],
             filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
         };
-        assert!(!fulfill_attribute_filter(element, &attribute_filter_class_fail), "should return false as class attribute does not match");
+        assert!(!fulfill_attribute_filter(element, attribute_filter_class_fail), "should return false as class attribute does not match");
     }
 
     #[test]
     fn test_filter_by_text_and() {
         let dom = init_dom("<div>Occaecat ex minim tempor fugiat. Laborum consectetur ut et qui anim nostrud cupidatat tempor id sint eu cupidatat.</div>");
-        let text = dom.children.get(0).unwrap().element();
+        let text = dom.children.first().unwrap().element();
         
         let text_filter = TextFilter {
             filter: vec!["qui anim".to_string(), "id sint eu cupidatat".to_string()],
             filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
+            text_source: None,
         };
         let text_filter_fail = TextFilter {
             filter: vec!["minim".to_string(), "consetur".to_string()],
             filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
+            text_source: None,
         };
-        assert!(filter_by_text(text, &text_filter), "should return true as all text fragments are present");
-        assert!(!filter_by_text(text, &text_filter_fail), "should return false as not all text fragments are present");
+        assert!(filter_by_text(text, text_filter, &[]), "should return true as all text fragments are present");
+        assert!(!filter_by_text(text, text_filter_fail, &[]), "should return false as not all text fragments are present");
     }
 
     #[test]
     fn test_filter_by_text_or() {
         let dom = init_dom("<div>Occaecat ex minim tempor fugiat. Laborum consectetur ut et qui anim nostrud cupidatat tempor id sint eu cupidatat.</div>");
-        let text = dom.children.get(0).unwrap().element();
+        let text = dom.children.first().unwrap().element();
         let text_filter = TextFilter {
             filter: vec!["adsfasdfasd".to_string(), "cupidatat tempor".to_string()],
             filter_type: FilterType::Or,
+            match_mode: None,
+            case_insensitive: None,
+            text_source: None,
         };
         let text_filter_fail = TextFilter {
             filter: vec!["burip".to_string(), "consetur".to_string()],
             filter_type: FilterType::Or,
+            match_mode: None,
+            case_insensitive: None,
+            text_source: None,
         };
-        assert!(filter_by_text(text, &text_filter), "should return true as at least one text fragment is present");
-        assert!(!filter_by_text(text, &text_filter_fail), "should return false as no text fragment is present");
+        assert!(filter_by_text(text, text_filter, &[]), "should return true as at least one text fragment is present");
+        assert!(!filter_by_text(text, text_filter_fail, &[]), "should return false as no text fragment is present");
+    }
+
+    #[test]
+    fn test_filter_by_text_regex() {
+        let dom = init_dom("<div>Published in 2019, price $12.50</div>");
+        let text = dom.children.first().unwrap().element();
+        let text_filter = TextFilter {
+            filter: vec![r"\b(19|20)\d{2}\b".to_string()],
+            filter_type: FilterType::And,
+            match_mode: Some(MatchMode::Regex),
+            case_insensitive: None,
+            text_source: None,
+        };
+        let text_filter_fail = TextFilter {
+            filter: vec![r"^\d+$".to_string()],
+            filter_type: FilterType::And,
+            match_mode: Some(MatchMode::Regex),
+            case_insensitive: None,
+            text_source: None,
+        };
+        let text_filter_invalid = TextFilter {
+            filter: vec![r"(unclosed".to_string()],
+            filter_type: FilterType::Or,
+            match_mode: Some(MatchMode::Regex),
+            case_insensitive: None,
+            text_source: None,
+        };
+        assert!(filter_by_text(text, text_filter, &[]), "should return true as the regex matches");
+        assert!(!filter_by_text(text, text_filter_fail, &[]), "should return false as the regex does not match");
+        assert!(!filter_by_text(text, text_filter_invalid, &[]), "should return false as the regex is invalid");
+    }
+
+    #[test]
+    fn test_filter_by_text_case_insensitive_and_whole_word() {
+        let dom = init_dom("<div>Hello Rustaceans, welcome to RUST</div>");
+        let text = dom.children.first().unwrap().element();
+        let case_insensitive = TextFilter {
+            filter: vec!["hello".to_string()],
+            filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: Some(true),
+            text_source: None,
+        };
+        let case_sensitive = TextFilter {
+            filter: vec!["hello".to_string()],
+            filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
+            text_source: None,
+        };
+        let whole_word = TextFilter {
+            filter: vec!["rust".to_string()],
+            filter_type: FilterType::And,
+            match_mode: Some(MatchMode::WholeWord),
+            case_insensitive: Some(true),
+            text_source: None,
+        };
+        let whole_word_fail = TextFilter {
+            filter: vec!["Rustacean".to_string()],
+            filter_type: FilterType::And,
+            match_mode: Some(MatchMode::WholeWord),
+            case_insensitive: None,
+            text_source: None,
+        };
+        assert!(filter_by_text(text, case_insensitive, &[]), "should return true ignoring case");
+        assert!(!filter_by_text(text, case_sensitive, &[]), "should return false as case differs");
+        assert!(filter_by_text(text, whole_word, &[]), "should return true as RUST is a whole word");
+        assert!(!filter_by_text(text, whole_word_fail, &[]), "should return false as Rustacean is only part of a word");
+    }
+
+    #[test]
+    fn test_filter_by_text_visible_text() {
+        let dom = init_dom("<div data-role='main'>Fish &amp; <b>chips</b><script>var main = 1;</script></div>");
+        let element = dom.children.first().unwrap().element();
+        let text_filter = |fragment: &str, text_source: TextSource| TextFilter {
+            filter: vec![fragment.to_string()],
+            filter_type: FilterType::And,
+            match_mode: None,
+            case_insensitive: None,
+            text_source: Some(text_source),
+        };
+        assert!(filter_by_text(element, text_filter("main", TextSource::Source), &[]), "should return true as the source contains the attribute value");
+        assert!(!filter_by_text(element, text_filter("main", TextSource::DescendantText), &[]), "should return false as main is only in markup and script");
+        assert!(filter_by_text(element, text_filter("Fish & chips", TextSource::DescendantText), &[]), "should return true as the decoded descendant text matches");
+        assert!(!filter_by_text(element, text_filter("chips", TextSource::OwnText), &[]), "should return false as chips is in a child element");
+        assert!(filter_by_text(element, text_filter("Fish &", TextSource::OwnText), &[]), "should return true as the own text matches");
+    }
+
+    #[test]
+    fn test_fulfill_attribute_filter_regex() {
+        let dom = init_dom("<a href='https://example.com/page' class='Nav-Link' data-id='item-42'></a>");
+        let element = dom.children.first().unwrap().element();
+        let attribute_filter = AttributeFilter {
+            filter: vec![("href".to_string(), r"^https://".to_string()), ("data-id".to_string(), r"item-\d+".to_string())],
+            filter_type: FilterType::And,
+            match_mode: Some(MatchMode::Regex),
+            case_insensitive: None,
+        };
+        let attribute_filter_class = AttributeFilter {
+            filter: vec![("class".to_string(), "nav-link".to_string())],
+            filter_type: FilterType::Or,
+            match_mode: None,
+            case_insensitive: Some(true),
+        };
+        let attribute_filter_fail = AttributeFilter {
+            filter: vec![("href".to_string(), r"^http://".to_string())],
+            filter_type: FilterType::And,
+            match_mode: Some(MatchMode::Regex),
+            case_insensitive: None,
+        };
+        assert!(fulfill_attribute_filter(element, attribute_filter), "should return true as all attribute regexes match");
+        assert!(fulfill_attribute_filter(element, attribute_filter_class), "should return true as class matches ignoring case");
+        assert!(!fulfill_attribute_filter(element, attribute_filter_fail), "should return false as the regex does not match");
+    }
+
+    #[test]
+    fn test_fulfill_attribute_filter_operators() {
+        let dom = init_dom("<a href='https://example.com/docs.pdf' rel='nofollow noopener' lang='en-US' data-price='12.5' title='Download manual'></a>");
+        let element = dom.children.first().unwrap().element();
+        let check = |key: &str, value: &str, mode: MatchMode| {
+            fulfill_attribute_filter(element, AttributeFilter {
+                filter: vec![(key.to_string(), value.to_string())],
+                filter_type: FilterType::And,
+                match_mode: Some(mode),
+                case_insensitive: None,
+            })
+        };
+        assert!(check("href", "", MatchMode::Exists), "should return true as href is present");
+        assert!(!check("target", "", MatchMode::Exists), "should return false as target is missing");
+        assert!(check("href", "https://", MatchMode::Prefix), "should return true as href starts with https://");
+        assert!(!check("href", "http://", MatchMode::Prefix), "should return false as href does not start with http://");
+        assert!(check("href", ".pdf", MatchMode::Suffix), "should return true as href ends with .pdf");
+        assert!(check("title", "manual", MatchMode::Contains), "should return true as title contains manual");
+        assert!(check("rel", "noopener", MatchMode::Word), "should return true as noopener is a word of rel");
+        assert!(!check("rel", "noop", MatchMode::Word), "should return false as noop is not a whole word of rel");
+        assert!(check("lang", "en", MatchMode::LangPrefix), "should return true as lang starts with en-");
+        assert!(!check("lang", "e", MatchMode::LangPrefix), "should return false as e is not followed by -");
+        assert!(check("data-price", "10", MatchMode::GreaterThan), "should return true as 12.5 > 10");
+        assert!(check("data-price", "12.5", MatchMode::GreaterOrEqual), "should return true as 12.5 >= 12.5");
+        assert!(check("data-price", "20", MatchMode::LessThan), "should return true as 12.5 < 20");
+        assert!(!check("data-price", "12", MatchMode::LessOrEqual), "should return false as 12.5 > 12");
+        assert!(!check("title", "5", MatchMode::GreaterThan), "should return false as title is not a number");
+    }
+
+    #[test]
+    fn test_fulfill_attribute_filter_valueless_attribute() {
+        let dom = init_dom("<input type='checkbox' disabled>");
+        let element = dom.children.first().unwrap().element();
+        let exists = AttributeFilter {
+            filter: vec![("disabled".to_string(), "".to_string())],
+            filter_type: FilterType::And,
+            match_mode: Some(MatchMode::Exists),
+            case_insensitive: None,
+        };
+        let exact = AttributeFilter {
+            filter: vec![("disabled".to_string(), "true".to_string())],
+            filter_type: FilterType::Or,
+            match_mode: None,
+            case_insensitive: None,
+        };
+        assert!(fulfill_attribute_filter(element, exists), "should return true as disabled is present");
+        assert!(!fulfill_attribute_filter(element, exact), "should return false as disabled has no value");
+    }
+
+    #[test]
+    fn test_extract_captures() {
+        let raw_html = r#"
+            <span class='book'>Dune (1965) - $9.99</span>
+            <span class='book'>Neuromancer (1984) - $12.50</span>
+            <span class='book'>Untitled</span>
+        "#;
+        let options = ScrapeOptions {
+            text_include: Some(TextFilter {
+                filter: vec![r"\((?P<year>\d{4})\)".to_string(), r"\$(?P<price>\d+\.\d{2})".to_string()],
+                filter_type: FilterType::And,
+                match_mode: Some(MatchMode::Regex),
+                case_insensitive: None,
+                text_source: None,
+            }),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["span".to_string()],
+                case_sensitive: None,
+            })
+        };
+        let result = extract_captures(raw_html, &options).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].get("year"), Some(&"1965".to_string()));
+        assert_eq!(result[0].get("price"), Some(&"9.99".to_string()));
+        assert_eq!(result[1].get("year"), Some(&"1984".to_string()));
+        assert_eq!(result[1].get("price"), Some(&"12.50".to_string()));
+    }
+
+    #[test]
+    fn test_extract_captures_skips_invalid_regex() {
+        let raw_html = "<span>Dune (1965)</span><span>Emma (1815)</span>";
+        let options = ScrapeOptions {
+            text_include: Some(TextFilter {
+                filter: vec!["(unclosed".to_string(), r"\((?P<year>\d{4})\)".to_string()],
+                filter_type: FilterType::Or,
+                match_mode: Some(MatchMode::Regex),
+                case_insensitive: Some(true),
+                text_source: None,
+            }),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["span".to_string()],
+                case_sensitive: None,
+            })
+        };
+        let result = extract_captures(raw_html, &options).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].get("year"), Some(&"1965".to_string()));
+        assert_eq!(result[1].get("year"), Some(&"1815".to_string()));
+    }
+
+    #[test]
+    fn test_scrape_document_order() {
+        let raw_html = "<div id='a'><div id='b'><div id='c'></div></div><div id='d'></div></div><div id='e'></div>";
+        let options = ScrapeOptions::new(TagFilter {
+            filter: vec!["div".to_string()],
+            case_sensitive: None,
+        });
+        let ids: Vec<String> = scrape(raw_html, &options).unwrap()
+            .iter()
+            .map(|html| html.split('\'').nth(1).unwrap().to_string())
+            .collect();
+        assert_eq!(ids, vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_scrape_limit_offset_and_first() {
+        let raw_html = "<ul><li>one</li><li>two</li><li>three</li><li>four</li></ul>";
+        let tags = || TagFilter {
+            filter: vec!["li".to_string()],
+            case_sensitive: None,
+        };
+        let limited = ScrapeOptions {
+            limit: Some(2),
+            ..ScrapeOptions::new(tags())
+        };
+        assert_eq!(scrape(raw_html, &limited).unwrap(), vec!["<li>one</li>", "<li>two</li>"]);
+
+        let windowed = ScrapeOptions {
+            offset: Some(1),
+            limit: Some(2),
+            ..ScrapeOptions::new(tags())
+        };
+        assert_eq!(scrape(raw_html, &windowed).unwrap(), vec!["<li>two</li>", "<li>three</li>"]);
+
+        let past_end = ScrapeOptions {
+            offset: Some(10),
+            ..ScrapeOptions::new(tags())
+        };
+        assert!(scrape(raw_html, &past_end).unwrap().is_empty());
+
+        assert_eq!(first(raw_html, &ScrapeOptions::new(tags())).unwrap(), Some("<li>one</li>".to_string()));
+        assert_eq!(first(raw_html, &ScrapeOptions { offset: Some(3), ..ScrapeOptions::new(tags()) }).unwrap(), Some("<li>four</li>".to_string()));
+        assert_eq!(first(raw_html, &ScrapeOptions { offset: Some(4), ..ScrapeOptions::new(tags()) }).unwrap(), None);
+    }
+
+    #[test]
+    fn test_scrape_ancestor_depth_and_nesting() {
+        let raw_html = r#"
+            <nav><div class='card'>menu</div></nav>
+            <main>
+                <div class='card'>first<div class='card'>inner</div></div>
+                <section><div class='card'>second</div></section>
+            </main>
+        "#;
+        let cards = || TagFilter {
+            filter: vec!["div".to_string()],
+            case_sensitive: None,
+        };
+
+        let inside_main = ScrapeOptions {
+            ancestor: Some(Box::new(ScrapeOptions::new(TagFilter {
+                filter: vec!["main".to_string()],
+                case_sensitive: None,
+            }))),
+            ..ScrapeOptions::new(cards())
+        };
+        assert_eq!(scrape(raw_html, &inside_main).unwrap(), vec![
+            "<div class='card'>first<div class='card'>inner</div></div>",
+            "<div class='card'>inner</div>",
+            "<div class='card'>second</div>",
+        ]);
+
+        let not_nested = ScrapeOptions {
+            skip_nested: Some(true),
+            ..ScrapeOptions::new(cards())
+        };
+        assert_eq!(scrape(raw_html, &not_nested).unwrap(), vec![
+            "<div class='card'>menu</div>",
+            "<div class='card'>first<div class='card'>inner</div></div>",
+            "<div class='card'>second</div>",
+        ]);
+
+        let depth_one = ScrapeOptions {
+            min_depth: Some(1),
+            max_depth: Some(1),
+            ..ScrapeOptions::new(cards())
+        };
+        assert_eq!(scrape(raw_html, &depth_one).unwrap(), vec![
+            "<div class='card'>menu</div>",
+            "<div class='card'>first<div class='card'>inner</div></div>",
+        ]);
+
+        let deep_only = ScrapeOptions {
+            min_depth: Some(2),
+            ..ScrapeOptions::new(cards())
+        };
+        assert_eq!(scrape(raw_html, &deep_only).unwrap(), vec![
+            "<div class='card'>inner</div>",
+            "<div class='card'>second</div>",
+        ]);
+    }
+
+    #[test]
+    fn test_scrape_malformed_html() {
+        let raw_html = "<p>intro</p><body><div class='item'>one<div class='item'>two</body>";
+        let options = ScrapeOptions {
+            class_filter: Some(ClassFilter {
+                filter: vec!["item".to_string()],
+                filter_type: FilterType::And,
+            }),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["div".to_string()],
+                case_sensitive: None,
+            })
+        };
+        assert!(scrape(raw_html, &options).is_err(), "should return an error as the strict parser rejects the document");
+
+        let lenient = ScrapeOptions {
+            parse_mode: Some(ParseMode::Lenient),
+            ..options
+        };
+        assert_eq!(scrape(raw_html, &lenient).unwrap(), vec![
+            r#"<div class="item">one<div class="item">two</div></div>"#,
+            r#"<div class="item">two</div>"#,
+        ]);
+    }
+
+    #[test]
+    fn test_scrape_entities_in_both_modes() {
+        let raw_html = r#"<div><p title="a &amp;lt; b">&amp;lt;b&amp;gt; &lt;i&gt;</p></div>"#;
+        let options = |parse_mode: ParseMode, output: Output| ScrapeOptions {
+            parse_mode: Some(parse_mode),
+            output: Some(output),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["p".to_string()],
+                case_sensitive: None,
+            })
+        };
+        for output in [Output::Text, Output::OuterHtml, Output::InnerHtml, Output::Attribute("title".to_string())] {
+            let strict = scrape(raw_html, &options(ParseMode::Strict, output.clone())).unwrap();
+            let lenient = scrape(raw_html, &options(ParseMode::Lenient, output.clone())).unwrap();
+            assert_eq!(strict, lenient, "should give the same {:?} in both modes", output);
+        }
+        let text = scrape(raw_html, &options(ParseMode::Lenient, Output::Text)).unwrap();
+        assert_eq!(text, vec!["&lt;b&gt; <i>"]);
+        let title = scrape(raw_html, &options(ParseMode::Strict, Output::Attribute("title".to_string()))).unwrap();
+        assert_eq!(title, vec!["a &lt; b"]);
     }
 
     // TODO ADD CASES FOR DIFFERENT FILTER OPTIONS
@@ -346,55 +1221,46 @@ mod tests {
 
         // Case 1: Filter by tag and class
         let options1 = ScrapeOptions {
-            tags: TagFilter {
-                filter: vec!["div".to_string()],
-            },
-            id_filter: None,
             class_filter: Some(ClassFilter {
                 filter: vec!["test".to_string()],
                 filter_type: FilterType::And,
             }),
-            attributes_include: None,
-            attributes_exclude: None,
-            text_include: None,
-            text_exclude: None,
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["div".to_string()],
+                case_sensitive: None,
+            })
         };
-        let result1 = scrape(raw_html, &options1);
+        let result1 = scrape(raw_html, &options1).unwrap();
         assert_eq!(result1, vec!["<div class='test' id='div1' data-role='main'>hello world</div>", "<div class='test' id='div2' data-role='main'>goodbye world</div>"]);
 
         // Case 2: Filter by tag and id
         let options2 = ScrapeOptions {
-            tags: TagFilter {
-                filter: vec!["span".to_string()],
-            },
             id_filter: Some(IdFilter {
                 filter: vec!["span1".to_string()],
             }),
-            class_filter: None,
-            attributes_include: None,
-            attributes_exclude: None,
-            text_include: None,
-            text_exclude: None,
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["span".to_string()],
+                case_sensitive: None,
+            })
         };
-        let result2 = scrape(raw_html, &options2);
+        let result2 = scrape(raw_html, &options2).unwrap();
         assert_eq!(result2, vec!["<span class='test' id='span1' data-role='secondary'>hello rust</span>"]);
 
         // Case 3: Filter by text include
         let options3 = ScrapeOptions {
-            tags: TagFilter {
-                filter: vec!["div".to_string(), "span".to_string()],
-            },
-            id_filter: None,
-            class_filter: None,
-            attributes_include: None,
-            attributes_exclude: None,
             text_include: Some(TextFilter {
                 filter: vec!["hello".to_string()],
                 filter_type: FilterType::Or,
+                match_mode: None,
+                case_insensitive: None,
+                text_source: None,
             }),
-            text_exclude: None,
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["div".to_string(), "span".to_string()],
+                case_sensitive: None,
+            })
         };
-        let result3 = scrape(raw_html, &options3);
+        let result3 = scrape(raw_html, &options3).unwrap();
         assert_eq!(result3, vec![
             "<div class='test' id='div1' data-role='main'>hello world</div>",
             "<span class='test' id='span1' data-role='secondary'>hello rust</span>",
@@ -403,20 +1269,18 @@ mod tests {
 
         // Case 4: Filter by attribute include
         let options4 = ScrapeOptions {
-            tags: TagFilter {
-                filter: vec!["div".to_string()],
-            },
-            id_filter: None,
-            class_filter: None,
             attributes_include: Some(AttributeFilter {
                 filter: vec![("data-role".to_string(), "main".to_string())],
                 filter_type: FilterType::And,
+                match_mode: None,
+                case_insensitive: None,
             }),
-            attributes_exclude: None,
-            text_include: None,
-            text_exclude: None,
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["div".to_string()],
+                case_sensitive: None,
+            })
         };
-        let result4 = scrape(raw_html, &options4);
+        let result4 = scrape(raw_html, &options4).unwrap();
         assert_eq!(result4, vec![
             "<div class='test' id='div1' data-role='main'>hello world</div>",
             "<div class='test' id='div2' data-role='main'>goodbye world</div>",
@@ -425,9 +1289,6 @@ mod tests {
 
         // Case 5: Filter by multiple criteria
         let options5 = ScrapeOptions {
-            tags: TagFilter {
-                filter: vec!["div".to_string()],
-            },
             id_filter: Some(IdFilter {
                 filter: vec!["div1".to_string(), "div2".to_string()],
             }),
@@ -435,15 +1296,19 @@ mod tests {
                 filter: vec!["test".to_string()],
                 filter_type: FilterType::And,
             }),
-            attributes_include: None,
-            attributes_exclude: None,
             text_include: Some(TextFilter {
                 filter: vec!["world".to_string()],
                 filter_type: FilterType::Or,
+                match_mode: None,
+                case_insensitive: None,
+                text_source: None,
             }),
-            text_exclude: None,
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["div".to_string()],
+                case_sensitive: None,
+            })
         };
-        let result5 = scrape(raw_html, &options5);
+        let result5 = scrape(raw_html, &options5).unwrap();
         assert_eq!(result5, vec![
             "<div class='test' id='div1' data-role='main'>hello world</div>",
             "<div class='test' id='div2' data-role='main'>goodbye world</div>"
@@ -0,0 +1,595 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::states::RawKind;
+use html5ever::tokenizer::{
+    BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+};
+use html_parser::Element;
+
+use super::parse::{escape_attribute, escape_text, parse_element, ParseError, VOID_ELEMENTS};
+use super::query::CompiledFilter;
+use super::scrap::{is_excluded, project, Namespace, Output, ScrapeOptions};
+
+/// Size of the chunks read from the input.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Elements whose start closes an open `<p>`.
+const CLOSE_PARAGRAPH: [&str; 28] = [
+    "address", "article", "aside", "blockquote", "details", "div", "dl", "fieldset", "figcaption",
+    "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "main", "menu",
+    "nav", "ol", "p", "pre", "section", "ul",
+];
+
+/// Streaming alternative to `scrape` for documents too large to be held in memory.
+///
+/// The input is tokenized chunk by chunk and elements are matched as their start tag is read,
+/// so only the open elements and the source of the matches being recorded are kept in memory.
+/// Matches are yielded in document order, each as soon as it and the matches starting before
+/// it are complete. Reading stops once `offset + limit` matches were yielded.
+///
+/// Every filter of `options` is supported with these differences to `scrape`:
+/// * Text filters are checked when the element ends, so an element is recorded until then.
+///   With `skip_nested` the matches nested in it are recorded too, and dropped if it passes.
+///   When the source of the element can not be parsed to check them, an `InvalidData` error is
+///   yielded in its place.
+/// * Only the element filters of `ancestor` are used, its text filters are ignored.
+/// * `parse_mode` is ignored. Matches are re-serialized from the tokens: attributes are double
+///   quoted, entities in text are normalized and end tags that the source leaves implicit
+///   (`<p>`, `<li>`, `<td>`...) are added.
+/// * Misnested markup is not repaired the way an HTML5 tree builder does, an end tag closes
+///   every element opened after the matching start tag and stray end tags are ignored.
+pub fn scrape_stream<R: Read>(reader: R, options: &ScrapeOptions) -> StreamScraper<'_, R> {
+    StreamScraper {
+        reader,
+        tokenizer: Tokenizer::new(StreamSink::new(options), TokenizerOpts::default()),
+        queue: BufferQueue::default(),
+        carry: vec![],
+        index: 0,
+        done: options.limit == Some(0),
+        eof: false,
+    }
+}
+
+/// Iterator returned by `scrape_stream`.
+pub struct StreamScraper<'a, R: Read> {
+    reader: R,
+    tokenizer: Tokenizer<StreamSink<'a>>,
+    queue: BufferQueue,
+    // bytes of a UTF-8 sequence split between two chunks
+    carry: Vec<u8>,
+    index: usize,
+    done: bool,
+    eof: bool,
+}
+
+impl<'a, R: Read> Iterator for StreamScraper<'a, R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if let Some(complete) = self.tokenizer.sink.take_complete() {
+                let html = match complete {
+                    Ok(html) => html,
+                    Err(error) => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, error))),
+                };
+                let options = self.tokenizer.sink.options;
+                self.index += 1;
+                if self.index <= options.offset.unwrap_or(0) {
+                    continue;
+                }
+                if options.limit.is_some_and(|limit| self.index >= options.offset.unwrap_or(0) + limit) {
+                    self.done = true;
+                }
+                match options.output {
+                    None | Some(Output::OuterHtml) => return Some(Ok(html)),
+                    Some(ref output) => match parse_element(&html) {
+                        Ok(element) => match project(&element, output) {
+                            Some(value) => return Some(Ok(value)),
+                            None => continue,
+                        },
+                        Err(error) => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, error))),
+                    },
+                }
+            }
+            if self.eof {
+                self.done = true;
+                break;
+            }
+            if let Err(error) = self.read_chunk() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, R: Read> StreamScraper<'a, R> {
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = loop {
+            match self.reader.read(&mut chunk) {
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        if read == 0 {
+            if !self.carry.is_empty() {
+                let rest = String::from_utf8_lossy(&self.carry).into_owned();
+                self.carry.clear();
+                self.feed(&rest);
+            }
+            self.tokenizer.end();
+            self.eof = true;
+            return Ok(());
+        }
+        let mut bytes = std::mem::take(&mut self.carry);
+        bytes.extend_from_slice(&chunk[..read]);
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) => text.to_string(),
+            // an incomplete sequence at the end is completed by the next chunk
+            Err(error) if error.error_len().is_none() => {
+                self.carry = bytes[error.valid_up_to()..].to_vec();
+                String::from_utf8_lossy(&bytes[..error.valid_up_to()]).into_owned()
+            }
+            Err(_) => String::from_utf8_lossy(&bytes).into_owned(),
+        };
+        self.feed(&text);
+        Ok(())
+    }
+
+    fn feed(&mut self, text: &str) {
+        self.queue.push_back(StrTendril::from_slice(text));
+        let _ = self.tokenizer.feed(&mut self.queue);
+    }
+}
+
+struct OpenElement {
+    name: String,
+    is_match: bool,
+    children_inside_ancestor: bool,
+    children_namespace: Namespace,
+    // the element or one of its ancestors is left out by `exclude`
+    excluded: bool,
+}
+
+/// A match being recorded, `start` and `end` are offsets in the stream of serialized tokens.
+struct PendingMatch {
+    start: usize,
+    end: Option<usize>,
+    // set when the text filters could not be checked
+    error: Option<ParseError>,
+}
+
+struct StreamSink<'a> {
+    options: &'a ScrapeOptions,
+    filter: CompiledFilter,
+    ancestor: Option<CompiledFilter>,
+    open: Vec<OpenElement>,
+    // open matches that passed every filter, with text filters none is known to pass before
+    // its end tag and nested matches are dropped once it does
+    open_matches: usize,
+    pending: VecDeque<PendingMatch>,
+    // serialized tokens from the start of the oldest pending match, `offset` is the stream
+    // position of its first byte
+    buffer: String,
+    offset: usize,
+}
+
+impl<'a> StreamSink<'a> {
+    fn new(options: &'a ScrapeOptions) -> Self {
+        Self {
+            options,
+            filter: CompiledFilter::lenient(options),
+            ancestor: options.ancestor.as_deref().map(CompiledFilter::lenient),
+            open: vec![],
+            open_matches: 0,
+            pending: VecDeque::new(),
+            buffer: String::new(),
+            offset: 0,
+        }
+    }
+
+    fn checks_text(&self) -> bool {
+        self.options.text_include.is_some() || self.options.text_exclude.is_some()
+    }
+
+    fn position(&self) -> usize {
+        self.offset + self.buffer.len()
+    }
+
+    fn append(&mut self, html: &str) {
+        if !self.pending.is_empty() {
+            self.buffer.push_str(html);
+        }
+    }
+
+    /// Removes the oldest match if it is complete and returns its source, or the error that kept
+    /// its text filters from being checked.
+    fn take_complete(&mut self) -> Option<Result<String, ParseError>> {
+        let end = self.pending.front()?.end?;
+        let PendingMatch { start, error, .. } = self.pending.pop_front()?;
+        let html = match error {
+            Some(error) => Err(error),
+            None => Ok(self.buffer[start - self.offset..end - self.offset].to_string()),
+        };
+        let keep_from = match self.pending.front() {
+            Some(next) => next.start,
+            None => self.position(),
+        };
+        self.buffer.drain(..keep_from - self.offset);
+        self.offset = keep_from;
+        Some(html)
+    }
+
+    fn start_tag(&mut self, tag: Tag) -> TokenSinkResult<()> {
+        let name = tag.name.to_string();
+        while self.open.last().is_some_and(|open| closes_implicitly(&open.name, &name)) {
+            self.close_top();
+        }
+        let depth = self.open.len();
+        let (inside_ancestor, parent_namespace, parent_excluded) = match self.open.last() {
+            Some(parent) => (parent.children_inside_ancestor, parent.children_namespace, parent.excluded),
+            None => (self.options.ancestor.is_none(), Namespace::Html, false),
+        };
+        let excluded = parent_excluded || is_excluded(&name, self.options.exclude.as_deref().unwrap_or(&[]));
+        let namespace = parent_namespace.of(&name);
+        let element = element_from_tag(&tag);
+        let is_match = !excluded
+            && inside_ancestor
+            && depth >= self.options.min_depth.unwrap_or(0)
+            && depth <= self.options.max_depth.unwrap_or(usize::MAX)
+            && !(self.options.skip_nested.unwrap_or(false) && self.open_matches > 0)
+            && self.filter.matches_start_tag(&element, namespace);
+        if is_match {
+            let start = self.position();
+            self.pending.push_back(PendingMatch { start, end: None, error: None });
+        }
+        self.append(&serialize_start_tag(&tag));
+
+        if tag.self_closing || VOID_ELEMENTS.contains(&name.as_str()) {
+            if is_match {
+                self.end_match();
+            }
+            return TokenSinkResult::Continue;
+        }
+        let children_inside_ancestor = inside_ancestor || (!excluded && self.ancestor
+            .as_ref()
+            .is_some_and(|ancestor| ancestor.matches_start_tag(&element, namespace)));
+        if is_match && !self.checks_text() {
+            self.open_matches += 1;
+        }
+        self.open.push(OpenElement {
+            name: name.clone(),
+            is_match,
+            children_inside_ancestor,
+            children_namespace: namespace.for_children(&name),
+            excluded,
+        });
+        match name.as_str() {
+            "script" => TokenSinkResult::RawData(RawKind::ScriptData),
+            "style" | "xmp" | "iframe" | "noembed" | "noframes" => TokenSinkResult::RawData(RawKind::Rawtext),
+            "title" | "textarea" => TokenSinkResult::RawData(RawKind::Rcdata),
+            "plaintext" => TokenSinkResult::Plaintext,
+            _ => TokenSinkResult::Continue,
+        }
+    }
+
+    fn end_tag(&mut self, tag: Tag) {
+        let name = tag.name.to_string();
+        if !self.open.iter().any(|open| open.name == name) {
+            return;
+        }
+        while let Some(open) = self.close_top() {
+            if open == name {
+                break;
+            }
+        }
+    }
+
+    fn close_top(&mut self) -> Option<String> {
+        let open = self.open.pop()?;
+        self.append(&format!("</{}>", open.name));
+        if open.is_match {
+            if !self.checks_text() {
+                self.open_matches -= 1;
+            }
+            self.end_match();
+        }
+        Some(open.name)
+    }
+
+    /// Ends the most recent pending match that is still open. With text filters the match is
+    /// dropped when it fails them, and the matches nested in it when it passes and
+    /// `skip_nested` is set.
+    fn end_match(&mut self) {
+        let position = self.position();
+        let Some(index) = self.pending.iter().rposition(|pending| pending.end.is_none()) else {
+            return;
+        };
+        self.pending[index].end = Some(position);
+        if !self.checks_text() {
+            return;
+        }
+        let start = self.pending[index].start - self.offset;
+        let passes = match parse_element(&self.buffer[start..]) {
+            Ok(element) => self.filter.matches_text(&element, self.options.exclude.as_deref().unwrap_or(&[])),
+            Err(error) => {
+                // reported in place of the match like `scrape` reports it, the nested matches are kept
+                self.pending[index].error = Some(error);
+                return;
+            }
+        };
+        if !passes {
+            self.pending.remove(index);
+        } else if self.options.skip_nested.unwrap_or(false) {
+            // every later match started inside this one and is complete
+            self.pending.truncate(index + 1);
+        }
+        if self.pending.is_empty() {
+            self.offset = self.position();
+            self.buffer.clear();
+        }
+    }
+
+    fn in_raw_text(&self) -> bool {
+        self.open.last().is_some_and(|open| {
+            matches!(open.name.as_str(), "script" | "style" | "xmp" | "iframe" | "noembed" | "noframes" | "plaintext")
+        })
+    }
+}
+
+impl<'a> TokenSink for StreamSink<'a> {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => match tag.kind {
+                TagKind::StartTag => return self.start_tag(tag),
+                TagKind::EndTag => self.end_tag(tag),
+            },
+            Token::CharacterTokens(text) => {
+                if self.in_raw_text() {
+                    self.append(&text);
+                } else {
+                    self.append(&escape_text(&text));
+                }
+            }
+            Token::CommentToken(comment) => self.append(&format!("<!--{}-->", comment)),
+            Token::EOFToken => {
+                while self.close_top().is_some() {}
+            }
+            Token::NullCharacterToken | Token::DoctypeToken(_) | Token::ParseError(_) => {}
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+fn closes_implicitly(open: &str, new: &str) -> bool {
+    match open {
+        "p" => CLOSE_PARAGRAPH.contains(&new) || new == "table",
+        "li" => new == "li",
+        "dt" | "dd" => matches!(new, "dt" | "dd"),
+        "td" | "th" => matches!(new, "td" | "th" | "tr" | "thead" | "tbody" | "tfoot"),
+        "tr" => matches!(new, "tr" | "thead" | "tbody" | "tfoot"),
+        "thead" | "tbody" => matches!(new, "tbody" | "tfoot"),
+        "option" => matches!(new, "option" | "optgroup"),
+        _ => false,
+    }
+}
+
+/// The element of a start tag, with its attribute values escaped again like those of a parsed
+/// `Dom` so the filters see the same values as with `scrape`.
+fn element_from_tag(tag: &Tag) -> Element {
+    let mut element = Element {
+        name: tag.name.to_string(),
+        ..Element::default()
+    };
+    for attribute in tag.attrs.iter() {
+        let value = escape_attribute(&attribute.value);
+        match &*attribute.name.local {
+            "id" => element.id = Some(value),
+            "class" => element.classes = value.split_whitespace().map(String::from).collect(),
+            key => {
+                element.attributes.insert(key.to_string(), Some(value));
+            }
+        }
+    }
+    element
+}
+
+fn serialize_start_tag(tag: &Tag) -> String {
+    let mut html = format!("<{}", tag.name);
+    for attribute in tag.attrs.iter() {
+        html.push_str(&format!(
+            r#" {}="{}""#,
+            attribute.name.local,
+            escape_attribute(&attribute.value)
+        ));
+    }
+    if tag.self_closing {
+        html.push_str(" /");
+    }
+    html.push('>');
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrapper::parse::ParseMode;
+    use crate::scrapper::scrap::{scrape, AttributeFilter, ClassFilter, Content, FilterType, TagFilter, TextFilter, TextSource};
+
+    fn stream(raw_html: &str, options: &ScrapeOptions) -> Vec<String> {
+        scrape_stream(raw_html.as_bytes(), options).collect::<io::Result<Vec<String>>>().unwrap()
+    }
+
+    fn divs() -> ScrapeOptions {
+        ScrapeOptions::new(TagFilter {
+            filter: vec!["div".to_string()],
+            case_sensitive: None,
+        })
+    }
+
+    #[test]
+    fn test_scrape_stream_matches_scrape() {
+        let raw_html = r#"<section><div class="item" id="a">one <b>bold</b></div><!-- note --><div class="item"><div>inner</div></div><span>x</span></section>"#;
+        let options = ScrapeOptions {
+            class_filter: Some(ClassFilter {
+                filter: vec!["item".to_string()],
+                filter_type: FilterType::And,
+            }),
+            ..divs()
+        };
+        assert_eq!(stream(raw_html, &options), scrape(raw_html, &options).unwrap());
+        // nested matches are yielded in document order
+        assert_eq!(stream(raw_html, &divs()), scrape(raw_html, &divs()).unwrap());
+    }
+
+    #[test]
+    fn test_scrape_stream_attribute_entities() {
+        let raw_html = r#"<div data-q="a&amp;b">one</div><div data-q="a&amp;amp;b">two</div><div data-q="a&#38;b">three</div>"#;
+        let options = ScrapeOptions {
+            attributes_include: Some(AttributeFilter {
+                filter: vec![("data-q".to_string(), "a&b".to_string())],
+                filter_type: FilterType::And,
+                match_mode: None,
+                case_insensitive: None,
+            }),
+            ..divs()
+        };
+        assert_eq!(stream(raw_html, &options), vec![
+            r#"<div data-q="a&amp;b">one</div>"#,
+            r#"<div data-q="a&amp;b">three</div>"#,
+        ]);
+        assert_eq!(scrape(raw_html, &options).unwrap().len(), 2);
+        let lenient = ScrapeOptions {
+            parse_mode: Some(ParseMode::Lenient),
+            ..options
+        };
+        assert_eq!(stream(raw_html, &lenient), scrape(raw_html, &lenient).unwrap());
+    }
+
+    #[test]
+    fn test_scrape_stream_skip_nested_with_text_filter() {
+        let raw_html = r#"<div class="a">x<div class="a">needle</div></div><div class="a">needle<div class="a">needle</div></div>"#;
+        let options = ScrapeOptions {
+            skip_nested: Some(true),
+            text_include: Some(TextFilter {
+                filter: vec!["needle".to_string()],
+                filter_type: FilterType::And,
+                match_mode: None,
+                case_insensitive: None,
+                text_source: Some(TextSource::OwnText),
+            }),
+            ..divs()
+        };
+        assert_eq!(stream(raw_html, &options), scrape(raw_html, &options).unwrap());
+        assert_eq!(stream(raw_html, &options), vec![
+            r#"<div class="a">needle</div>"#,
+            r#"<div class="a">needle<div class="a">needle</div></div>"#,
+        ]);
+    }
+
+    #[test]
+    fn test_scrape_stream_text_filter_parse_error() {
+        // the name of the attribute of `<a` can not be read back by the strict parser
+        let raw_html = "<div>y<a <!--></a></div><div>y</div>";
+        let options = ScrapeOptions {
+            text_include: Some(TextFilter {
+                filter: vec!["y".to_string()],
+                filter_type: FilterType::And,
+                match_mode: None,
+                case_insensitive: None,
+                text_source: None,
+            }),
+            ..divs()
+        };
+        assert!(scrape(raw_html, &options).is_err());
+        let results: Vec<io::Result<String>> = scrape_stream(raw_html.as_bytes(), &options).collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(results[1].as_ref().unwrap(), "<div>y</div>");
+    }
+
+    #[test]
+    fn test_scrape_stream_implied_end_tags_and_raw_text() {
+        let raw_html = "<ul><li>one<li>two &amp; <script>if (a < b) {}</script></ul><p>para<div>block</div>";
+        let options = ScrapeOptions::new(TagFilter {
+            filter: vec!["li".to_string(), "p".to_string()],
+            case_sensitive: None,
+        });
+        assert_eq!(stream(raw_html, &options), vec![
+            "<li>one</li>",
+            "<li>two &amp; <script>if (a < b) {}</script></li>",
+            "<p>para</p>",
+        ]);
+    }
+
+    #[test]
+    fn test_scrape_stream_limits_and_chunks() {
+        // a multi byte character is split between two chunks
+        let mut raw_html = "x".repeat(CHUNK_SIZE - 1);
+        raw_html.push_str("<div>é</div><div>2</div><div>3</div>");
+        let options = ScrapeOptions {
+            offset: Some(1),
+            limit: Some(1),
+            ..divs()
+        };
+        assert_eq!(stream(&raw_html, &options), vec!["<div>2</div>"]);
+        assert_eq!(stream(&raw_html, &divs())[0], "<div>é</div>");
+    }
+
+    #[test]
+    fn test_scrape_stream_output() {
+        let raw_html = r#"<div><a href="/a">A</a><a>B</a><a href="/c">C</a></div>"#;
+        let options = ScrapeOptions {
+            output: Some(Output::Attribute("href".to_string())),
+            ..ScrapeOptions::new(TagFilter {
+                filter: vec!["a".to_string()],
+                case_sensitive: None,
+            })
+        };
+        assert_eq!(stream(raw_html, &options), vec!["/a", "/c"]);
+        assert_eq!(stream(raw_html, &options), scrape(raw_html, &options).unwrap());
+    }
+
+    #[test]
+    fn test_scrape_stream_namespaces() {
+        let raw_html = r##"<svg><a href="#icon"><path d="M0"/></a><foreignObject><a href="/inner">inner</a></foreignObject></svg><a href="/page">page</a>"##;
+        let options = |filter: &str| ScrapeOptions::new(TagFilter {
+            filter: vec![filter.to_string()],
+            case_sensitive: None,
+        });
+        assert_eq!(stream(raw_html, &options("svg:*")).len(), 4);
+        assert_eq!(stream(raw_html, &options("html:a")), vec![
+            r#"<a href="/inner">inner</a>"#,
+            r#"<a href="/page">page</a>"#,
+        ]);
+    }
+
+    #[test]
+    fn test_scrape_stream_exclude() {
+        let raw_html = "<div><noscript><div>no js</div></noscript><div>one <!-- note --></div></div>";
+        let options = ScrapeOptions {
+            exclude: Some(vec![Content::Noscript, Content::Comments]),
+            text_include: Some(TextFilter {
+                filter: vec!["note".to_string()],
+                filter_type: FilterType::And,
+                match_mode: None,
+                case_insensitive: None,
+                text_source: None,
+            }),
+            ..divs()
+        };
+        assert_eq!(stream(raw_html, &options), Vec::<String>::new());
+        let options = ScrapeOptions {
+            text_include: None,
+            ..options
+        };
+        assert_eq!(stream(raw_html, &options), scrape(raw_html, &options).unwrap());
+        assert_eq!(stream(raw_html, &options).len(), 2);
+    }
+}
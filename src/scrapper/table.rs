@@ -0,0 +1,363 @@
+use html_parser::{Element, Node};
+
+use super::parse::{attribute, ParseError};
+use super::scrap::{matching_elements, parse_document, ScrapeOptions};
+use super::text;
+use crate::storage::records::{Record, Value};
+
+/// Upper bound for `colspan`, browsers clamp it in the same way.
+const MAX_COLSPAN: usize = 1000;
+/// Upper bound for `rowspan`, browsers clamp it in the same way.
+const MAX_ROWSPAN: usize = 65534;
+
+/// A `<table>` expanded into a rectangular grid of cell texts.
+///
+/// Cells spanning several columns or rows are repeated in every position they cover. The
+/// header comes from the `<thead>` rows or, without `<thead>`, from a first row made only of
+/// `<th>` cells. Columns without a header are named `column_<n>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Converts every data row into a `Record` keyed by the header, ready for `store_records`.
+    /// Repeated header names get a `_<n>` suffix so no column is lost.
+    pub fn to_records(&self) -> Vec<Record> {
+        let mut keys: Vec<String> = vec![];
+        for name in self.header.iter() {
+            let mut key = name.clone();
+            let mut count = 1;
+            while keys.contains(&key) {
+                count += 1;
+                key = format!("{}_{}", name, count);
+            }
+            keys.push(key);
+        }
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut record = Record::new();
+                for (key, cell) in keys.iter().zip(row.iter()) {
+                    record.insert(key, Value::Text(cell.clone()));
+                }
+                record
+            })
+            .collect()
+    }
+}
+
+/// Extracts every `<table>` element matching `options`, in document order.
+///
+/// Nested tables are returned as tables of their own and their text is left out of the cells
+/// of the outer table. Use `skip_nested` in `options` to only return the outermost tables.
+pub fn extract_tables(raw_html: &str, options: &ScrapeOptions) -> Result<Vec<Table>, ParseError> {
+    let html = parse_document(raw_html, options)?;
+    Ok(matching_elements(&html, options)
+        .into_iter()
+        .filter(|element| element.name.eq_ignore_ascii_case("table"))
+        .map(table_from_element)
+        .collect())
+}
+
+/// Builds a `Table` from a parsed `<table>` element.
+pub fn table_from_element(table: &Element) -> Table {
+    let mut header_rows: Vec<Vec<String>> = vec![];
+    let mut rows: Vec<Vec<String>> = vec![];
+    let mut loose_rows: Vec<&Element> = vec![];
+    for child in child_elements(table) {
+        match child.name.to_lowercase().as_str() {
+            "tr" => loose_rows.push(child),
+            "thead" => header_rows.extend(expand_rows(&rows_of(child))),
+            "tbody" | "tfoot" => {
+                rows.extend(expand_rows(&loose_rows));
+                loose_rows.clear();
+                rows.extend(expand_rows(&rows_of(child)));
+            }
+            _ => {}
+        }
+    }
+    rows.extend(expand_rows(&loose_rows));
+
+    if header_rows.is_empty() && starts_with_header_row(table) && !rows.is_empty() {
+        header_rows.push(rows.remove(0));
+    }
+    let width = header_rows
+        .iter()
+        .chain(rows.iter())
+        .map(|row| row.len())
+        .max()
+        .unwrap_or(0);
+    for row in rows.iter_mut() {
+        row.resize(width, String::new());
+    }
+    Table {
+        header: combine_header_rows(&header_rows, width),
+        rows,
+    }
+}
+
+fn child_elements(element: &Element) -> impl Iterator<Item = &Element> {
+    element.children.iter().filter_map(|node| node.element())
+}
+
+fn rows_of(section: &Element) -> Vec<&Element> {
+    child_elements(section)
+        .filter(|child| child.name.eq_ignore_ascii_case("tr"))
+        .collect()
+}
+
+fn cells_of(row: &Element) -> impl Iterator<Item = &Element> {
+    child_elements(row).filter(|child| child.name.eq_ignore_ascii_case("td") || child.name.eq_ignore_ascii_case("th"))
+}
+
+/// Whether the first row of the table, outside of any `<thead>`, only has `<th>` cells.
+fn starts_with_header_row(table: &Element) -> bool {
+    let first_row = child_elements(table)
+        .flat_map(|child| match child.name.to_lowercase().as_str() {
+            "tr" => vec![child],
+            "tbody" | "tfoot" => rows_of(child),
+            _ => vec![],
+        })
+        .next();
+    first_row.is_some_and(|row| {
+        let mut cells = cells_of(row).peekable();
+        cells.peek().is_some() && cells.all(|cell| cell.name.eq_ignore_ascii_case("th"))
+    })
+}
+
+/// Lays the rows of one row group out on a grid, copying spanning cells into every slot they cover.
+/// A `rowspan` of `0` covers the rest of the row group.
+fn expand_rows(rows: &[&Element]) -> Vec<Vec<String>> {
+    // remaining rows and text of a rowspan, per column
+    let mut pending: Vec<Option<(usize, String)>> = vec![];
+    let mut grid = vec![];
+    for (index, row) in rows.iter().enumerate() {
+        let mut cells: Vec<Option<String>> = vec![None; pending.len()];
+        for (column, slot) in pending.iter_mut().enumerate() {
+            if let Some((remaining, text)) = slot {
+                cells[column] = Some(text.clone());
+                *remaining -= 1;
+                if *remaining == 0 {
+                    *slot = None;
+                }
+            }
+        }
+        let mut column = 0;
+        for cell in cells_of(row) {
+            while cells.get(column).is_some_and(|slot| slot.is_some()) {
+                column += 1;
+            }
+            let text = cell_text(cell);
+            let rowspan = match span(cell, "rowspan") {
+                Some(0) => rows.len() - index,
+                Some(rowspan) => rowspan.min(MAX_ROWSPAN),
+                None => 1,
+            };
+            let colspan = span(cell, "colspan").filter(|colspan| *colspan > 0).unwrap_or(1);
+            for _ in 0..colspan.min(MAX_COLSPAN) {
+                // slots reserved by a rowspan of an earlier row are skipped
+                while cells.get(column).is_some_and(|slot| slot.is_some()) {
+                    column += 1;
+                }
+                if cells.len() <= column {
+                    cells.resize(column + 1, None);
+                }
+                cells[column] = Some(text.clone());
+                if rowspan > 1 {
+                    if pending.len() <= column {
+                        pending.resize(column + 1, None);
+                    }
+                    pending[column] = Some((rowspan - 1, text.clone()));
+                }
+                column += 1;
+            }
+        }
+        grid.push(cells.into_iter().map(|cell| cell.unwrap_or_default()).collect());
+    }
+    grid
+}
+
+fn span(cell: &Element, name: &str) -> Option<usize> {
+    attribute(cell, name).and_then(|value| value.trim().parse::<usize>().ok())
+}
+
+/// Visible text of a cell without the text of tables nested inside of it.
+fn cell_text(cell: &Element) -> String {
+    let mut cell = cell.clone();
+    remove_tables(&mut cell);
+    text::visible_text(&cell, true)
+}
+
+fn remove_tables(element: &mut Element) {
+    element.children.retain(|node| !matches!(node, Node::Element(child) if child.name.eq_ignore_ascii_case("table")));
+    for child in element.children.iter_mut() {
+        if let Node::Element(child) = child {
+            remove_tables(child);
+        }
+    }
+}
+
+fn combine_header_rows(header_rows: &[Vec<String>], width: usize) -> Vec<String> {
+    (0..width)
+        .map(|column| {
+            let mut parts: Vec<&str> = vec![];
+            for row in header_rows {
+                if let Some(part) = row.get(column) {
+                    if !part.is_empty() && !parts.contains(&part.as_str()) {
+                        parts.push(part);
+                    }
+                }
+            }
+            if parts.is_empty() {
+                return format!("column_{}", column + 1);
+            }
+            parts.join(" ")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrapper::scrap::TagFilter;
+
+    fn tables(raw_html: &str) -> Vec<Table> {
+        extract_tables(raw_html, &ScrapeOptions::new(TagFilter {
+            filter: vec!["table".to_string()],
+            case_sensitive: None,
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_extract_tables_thead() {
+        let raw_html = r#"
+            <table>
+                <thead><tr><th>Name</th><th>Price</th></tr></thead>
+                <tbody>
+                    <tr><td>Apple</td><td>1.00 &euro;</td></tr>
+                    <tr><td>Pear</td><td>2.50 &euro;</td></tr>
+                </tbody>
+            </table>
+        "#;
+        assert_eq!(tables(raw_html), vec![Table {
+            header: vec!["Name".to_string(), "Price".to_string()],
+            rows: vec![
+                vec!["Apple".to_string(), "1.00 €".to_string()],
+                vec!["Pear".to_string(), "2.50 €".to_string()],
+            ],
+        }]);
+    }
+
+    #[test]
+    fn test_extract_tables_th_row_and_missing_header() {
+        let with_th = tables("<table><tr><th>A</th><th>B</th></tr><tr><td>1</td><td>2</td></tr></table>");
+        assert_eq!(with_th[0].header, vec!["A", "B"]);
+        assert_eq!(with_th[0].rows, vec![vec!["1", "2"]]);
+
+        let without_header = tables("<table><tr><td>1</td><td>2</td></tr></table>");
+        assert_eq!(without_header[0].header, vec!["column_1", "column_2"]);
+        assert_eq!(without_header[0].rows, vec![vec!["1", "2"]]);
+    }
+
+    #[test]
+    fn test_extract_tables_spans() {
+        let raw_html = r#"
+            <table>
+                <tr><th rowspan='2'>Region</th><th colspan='2'>Sales</th></tr>
+                <tr><th>2023</th><th>2024</th></tr>
+                <tr><td rowspan='2'>North</td><td>10</td><td>12</td></tr>
+                <tr><td colspan='2'>n/a</td></tr>
+            </table>
+        "#;
+        let table = &tables(raw_html)[0];
+        // only the first th row is detected as header without a thead
+        assert_eq!(table.header, vec!["Region", "Sales", "Sales"]);
+        assert_eq!(table.rows, vec![
+            vec!["Region", "2023", "2024"],
+            vec!["North", "10", "12"],
+            vec!["North", "n/a", "n/a"],
+        ]);
+
+        let with_thead = tables(r#"
+            <table>
+                <thead>
+                    <tr><th rowspan='2'>Region</th><th colspan='2'>Sales</th></tr>
+                    <tr><th>2023</th><th>2024</th></tr>
+                </thead>
+                <tr><td>North</td><td>10</td><td>12</td></tr>
+            </table>
+        "#);
+        assert_eq!(with_thead[0].header, vec!["Region", "Sales 2023", "Sales 2024"]);
+
+        // a colspan running into a rowspan of the row above continues after it
+        let overlapping = tables(r#"
+            <table>
+                <tr><td>a</td><td rowspan='2'>b</td><td>c</td></tr>
+                <tr><td colspan='2'>d</td></tr>
+            </table>
+        "#);
+        assert_eq!(overlapping[0].rows, vec![
+            vec!["a", "b", "c"],
+            vec!["d", "b", "d"],
+        ]);
+
+        // attribute names are case insensitive
+        let upper_case = tables(r#"
+            <table>
+                <tr><td ROWSPAN='2'>a</td><td ColSpan='2'>b</td></tr>
+                <tr><td>c</td><td>d</td></tr>
+            </table>
+        "#);
+        assert_eq!(upper_case[0].rows, vec![
+            vec!["a", "b", "b"],
+            vec!["a", "c", "d"],
+        ]);
+
+        // a rowspan of 0 runs to the end of its row group and a colspan of 0 counts as 1
+        let to_group_end = tables(r#"
+            <table>
+                <tbody>
+                    <tr><td rowspan='0'>a</td><td colspan='0'>b</td></tr>
+                    <tr><td>c</td></tr>
+                    <tr><td>d</td></tr>
+                </tbody>
+                <tbody>
+                    <tr><td>e</td><td>f</td></tr>
+                </tbody>
+            </table>
+        "#);
+        assert_eq!(to_group_end[0].rows, vec![
+            vec!["a", "b"],
+            vec!["a", "c"],
+            vec!["a", "d"],
+            vec!["e", "f"],
+        ]);
+    }
+
+    #[test]
+    fn test_extract_tables_nested() {
+        let raw_html = r#"
+            <table>
+                <tr><th>Item</th><th>Details</th></tr>
+                <tr><td>Box</td><td>Sizes<table><tr><td>S</td><td>M</td></tr></table></td></tr>
+            </table>
+        "#;
+        let result = tables(raw_html);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].rows, vec![vec!["Box", "Sizes"]]);
+        assert_eq!(result[1].rows, vec![vec!["S", "M"]]);
+    }
+
+    #[test]
+    fn test_table_to_records() {
+        let table = Table {
+            header: vec!["Name".to_string(), "Name".to_string()],
+            rows: vec![vec!["a".to_string(), "b".to_string()]],
+        };
+        let records = table.to_records();
+        assert_eq!(records[0].get("Name"), Some(&Value::Text("a".to_string())));
+        assert_eq!(records[0].get("Name_2"), Some(&Value::Text("b".to_string())));
+    }
+}
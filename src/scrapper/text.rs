@@ -0,0 +1,203 @@
+use html_parser::{Element, Node};
+use unicode_normalization::UnicodeNormalization;
+
+/// Elements whose content is never rendered as text.
+const HIDDEN_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// Elements that start a new line when rendered, their boundaries are treated as whitespace.
+const BLOCK_ELEMENTS: [&str; 38] = [
+    "address", "article", "aside", "blockquote", "br", "caption", "dd", "div", "dl", "dt",
+    "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6",
+    "header", "hr", "li", "main", "nav", "ol", "p", "pre", "section", "table", "tbody", "td",
+    "tfoot", "th", "thead", "tr", "ul",
+];
+
+/// Unicode normalization form applied to extracted text.
+///
+/// * `Nfc` - Canonical composition, `e` followed by a combining accent becomes `é`.
+/// * `Nfkc` - Compatibility composition, also folds ligatures, full width and super/subscript
+///   characters into their plain equivalent (`ﬁ` becomes `fi`, `²` becomes `2`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnicodeForm {
+    Nfc,
+    Nfkc,
+}
+
+/// Options of `extract_text`, the routine used for every text field written by the storage module.
+///
+/// # Fields
+///
+/// * `recursive` - Whether the text of descendants is included, otherwise only the element's own text nodes. Defaults to `true`.
+/// * `decode_entities` - Whether HTML character references are decoded (`&amp;` becomes `&`). Defaults to `true`.
+/// * `collapse_whitespace` - Whether runs of whitespace become a single space and the text is trimmed. Defaults to `true`.
+/// * `normalization` - Unicode normalization form applied last. Defaults to none.
+/// * `block_newlines` - Whether block elements and `<br>` start a new line instead of a space. With
+///   `collapse_whitespace` empty lines are removed. Defaults to `false`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextOptions {
+    pub recursive: Option<bool>,
+    pub decode_entities: Option<bool>,
+    pub collapse_whitespace: Option<bool>,
+    pub normalization: Option<UnicodeForm>,
+    pub block_newlines: Option<bool>,
+}
+
+impl TextOptions {
+    pub fn new() -> Self {
+        Self {
+            recursive: None,
+            decode_entities: None,
+            collapse_whitespace: None,
+            normalization: None,
+            block_newlines: None,
+        }
+    }
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the visible text of `element`: entities decoded, whitespace collapsed and the
+/// content of comments, `<script>` and `<style>` left out.
+///
+/// When `recursive` is `false` only the text nodes that are direct children of `element`
+/// (its own text) are used, otherwise the text of every descendant is included.
+pub fn visible_text(element: &Element, recursive: bool) -> String {
+    extract_text(element, &TextOptions {
+        recursive: Some(recursive),
+        ..TextOptions::new()
+    })
+}
+
+/// Returns the text of `element` as configured by `options`. Comments, `<script>` and `<style>`
+/// are always left out.
+pub fn extract_text(element: &Element, options: &TextOptions) -> String {
+    let separator = if options.block_newlines.unwrap_or(false) { '\n' } else { ' ' };
+    let mut raw = String::new();
+    collect_text(element, options.recursive.unwrap_or(true), separator, &mut raw);
+    let mut text = if options.decode_entities.unwrap_or(true) {
+        decode_entities(&raw)
+    } else {
+        raw
+    };
+    if options.collapse_whitespace.unwrap_or(true) {
+        text = if separator == '\n' {
+            // the new lines come from the markup, those of the source are plain whitespace
+            text.split('\n')
+                .map(collapse_whitespace)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<String>>()
+                .join("\n")
+        } else {
+            collapse_whitespace(&text)
+        };
+    }
+    match options.normalization {
+        Some(UnicodeForm::Nfc) => text.nfc().collect(),
+        Some(UnicodeForm::Nfkc) => text.nfkc().collect(),
+        None => text,
+    }
+}
+
+/// Decodes named and numeric HTML character references, `&amp;` becomes `&`.
+pub fn decode_entities(text: &str) -> String {
+    html_escape::decode_html_entities(text).into_owned()
+}
+
+/// Replaces every run of whitespace with a single space and trims both ends.
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+pub(crate) fn is_hidden(element: &Element) -> bool {
+    HIDDEN_ELEMENTS.iter().any(|name| element.name.eq_ignore_ascii_case(name))
+}
+
+pub(crate) fn is_block(element: &Element) -> bool {
+    BLOCK_ELEMENTS.iter().any(|name| element.name.eq_ignore_ascii_case(name))
+}
+
+fn collect_text(element: &Element, recursive: bool, separator: char, raw: &mut String) {
+    for child in element.children.iter() {
+        match child {
+            // with new lines, source line breaks are whitespace like any other
+            Node::Text(text) if separator == '\n' => raw.push_str(&text.replace(['\n', '\r'], " ")),
+            Node::Text(text) => raw.push_str(text),
+            Node::Element(child) if recursive && !is_hidden(child) => {
+                if is_block(child) {
+                    raw.push(separator);
+                }
+                collect_text(child, recursive, separator, raw);
+                if is_block(child) {
+                    raw.push(separator);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html_parser::Dom;
+
+    fn first_element(raw_html: &str) -> Element {
+        Dom::parse(raw_html).unwrap().children[0].element().unwrap().clone()
+    }
+
+    #[test]
+    fn test_visible_text_recursive() {
+        let element = first_element("<div>Fish &amp; <b>chips</b>\n   <!-- hidden --><script>var x = 1;</script><style>p {}</style>&nbsp;now</div>");
+        assert_eq!(visible_text(&element, true), "Fish & chips now");
+    }
+
+    #[test]
+    fn test_visible_text_own_text() {
+        let element = first_element("<div>Price: <span>10</span> EUR</div>");
+        assert_eq!(visible_text(&element, false), "Price: EUR");
+    }
+
+    #[test]
+    fn test_visible_text_block_boundaries() {
+        let element = first_element("<ul><li>one</li><li>two</li></ul>");
+        assert_eq!(visible_text(&element, true), "one two");
+        let element = first_element("<table><caption>Prices</caption><thead><tr><th>Name</th><th>Price</th></tr></thead></table>");
+        assert_eq!(visible_text(&element, true), "Prices Name Price");
+    }
+
+    #[test]
+    fn test_extract_text_block_newlines() {
+        let element = first_element("<div>Title<p>First\n  line<br>second</p><ul><li>a</li><li></li><li>b</li></ul></div>");
+        let options = TextOptions {
+            block_newlines: Some(true),
+            ..TextOptions::new()
+        };
+        assert_eq!(extract_text(&element, &options), "Title\nFirst line\nsecond\na\nb");
+    }
+
+    #[test]
+    fn test_extract_text_raw_and_normalized() {
+        let element = first_element("<p>  Caf\u{0065}\u{0301} &amp; <b>ﬁsh</b>  </p>");
+        let raw = TextOptions {
+            recursive: Some(false),
+            decode_entities: Some(false),
+            collapse_whitespace: Some(false),
+            ..TextOptions::new()
+        };
+        assert_eq!(extract_text(&element, &raw), "  Caf\u{0065}\u{0301} &amp; ");
+        let nfc = TextOptions {
+            normalization: Some(UnicodeForm::Nfc),
+            ..TextOptions::new()
+        };
+        assert_eq!(extract_text(&element, &nfc), "Café & ﬁsh");
+        let nfkc = TextOptions {
+            normalization: Some(UnicodeForm::Nfkc),
+            ..TextOptions::new()
+        };
+        assert_eq!(extract_text(&element, &nfkc), "Café & fish");
+    }
+}
@@ -0,0 +1,412 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use regex::Regex;
+use url::Url;
+
+use super::links::resolve_url;
+use crate::storage::records::{Record, Value};
+
+/// Date formats tried by `Transform::ParseDate` when it is given no format. Day first formats
+/// win over month first ones, pass explicit formats for `03/04/2024` style US dates.
+const DEFAULT_DATE_FORMATS: [&str; 14] = [
+    "%Y-%m-%d",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d",
+    "%d/%m/%Y",
+    "%d.%m.%Y",
+    "%d-%m-%Y",
+    "%B %d, %Y",
+    "%b %d, %Y",
+    "%B %d %Y",
+    "%d %B %Y",
+    "%d %b %Y",
+    "%A, %B %d, %Y",
+    "%a, %d %b %Y",
+];
+
+/// Languages writing `1.234,56`, the others write `1,234.56`.
+const DECIMAL_COMMA_LANGUAGES: [&str; 29] = [
+    "bg", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "lt", "lv", "nb",
+    "nl", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+/// Characters used to group thousands, whichever is not the decimal separator is ignored.
+const GROUP_SEPARATORS: [char; 7] = [',', '.', ' ', '\u{a0}', '\u{202f}', '\'', '’'];
+
+/// How numbers are written in the source page.
+///
+/// * `DecimalPoint` - `1,234.56`, `1 234.56` or `1'234.56`.
+/// * `DecimalComma` - `1.234,56` or `1 234,56`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NumberLocale {
+    DecimalPoint,
+    DecimalComma,
+}
+
+impl NumberLocale {
+    /// Picks the convention of a BCP 47 language tag such as `en-US`, `de` or `fr-CH`.
+    /// Swiss German, French and Italian use a decimal point.
+    pub fn from_language(tag: &str) -> Self {
+        let tag = tag.to_lowercase().replace('_', "-");
+        let mut parts = tag.split('-');
+        let language = parts.next().unwrap_or_default();
+        if parts.any(|region| region == "ch") {
+            return NumberLocale::DecimalPoint;
+        }
+        if DECIMAL_COMMA_LANGUAGES.contains(&language) {
+            return NumberLocale::DecimalComma;
+        }
+        NumberLocale::DecimalPoint
+    }
+
+    fn decimal_separator(&self) -> char {
+        match self {
+            NumberLocale::DecimalPoint => '.',
+            NumberLocale::DecimalComma => ',',
+        }
+    }
+}
+
+/// A step of a post-processing chain, applied to extracted values before they are stored.
+///
+/// Text transforms (`Trim`, `Lowercase`, `Replace`, `ResolveUrl`, `ParseNumber`, `ParseDate`)
+/// are applied to every item of a list.
+///
+/// * `Trim` - Removes leading and trailing whitespace.
+/// * `Lowercase` - Converts to lowercase.
+/// * `Replace` - Replaces every match of the regex `pattern` with `replacement`, which can use `$1` or `$name`.
+///   Built with `Transform::replace`, which reports an invalid pattern.
+/// * `ParseNumber` - Reads the first number of the text, ignoring currency symbols, units and
+///   thousands separators, and turns the value into a `Value::Number`. A separator which is not
+///   the locale's decimal separator must be followed by exactly three digits, so `1,5` read with
+///   a decimal point is an error instead of `15`.
+/// * `ParseDate` - Parses a date with the first matching `chrono` format (`DEFAULT_DATE_FORMATS`
+///   when empty, RFC 3339 and RFC 2822 are always tried) and writes it as ISO-8601: `2024-03-01`,
+///   `2024-03-01T08:30:00` or `2024-03-01T08:30:00+01:00`.
+/// * `Split` - Splits the text on `separator` into a `Value::List` of trimmed, non empty items.
+/// * `Default` - Replaces an empty text or list, or a missing field, with the given text.
+/// * `ResolveUrl` - Resolves a relative URL against the given base URL.
+#[derive(Debug, Clone)]
+pub enum Transform {
+    Trim,
+    Lowercase,
+    Replace { pattern: Regex, replacement: String },
+    ParseNumber(NumberLocale),
+    ParseDate { formats: Vec<String> },
+    Split { separator: String },
+    Default(String),
+    ResolveUrl(Url),
+}
+
+impl Transform {
+    /// A `Replace` transform, the regex `pattern` is compiled once here.
+    pub fn replace(pattern: &str, replacement: &str) -> Result<Self, TransformError> {
+        Ok(Transform::Replace {
+            pattern: Regex::new(pattern).map_err(|_| TransformError::new(pattern, "invalid regex"))?,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+/// Error returned when a transform can not be applied to a value. `field` is set when the
+/// value comes from a `Pipeline`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransformError {
+    pub field: Option<String>,
+    pub value: String,
+    pub message: String,
+}
+
+impl TransformError {
+    fn new(value: &str, message: &str) -> Self {
+        Self {
+            field: None,
+            value: value.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.field {
+            Some(ref field) => write!(f, "field {}: {}: {:?}", field, self.message, self.value),
+            None => write!(f, "{}: {:?}", self.message, self.value),
+        }
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+/// Transform chains attached to record fields, run in insertion order of the fields.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    pub fields: Vec<(String, Vec<Transform>)>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self { fields: vec![] }
+    }
+
+    /// Sets the transforms of `field`, replacing the previous chain of the field if any.
+    pub fn insert(&mut self, field: &str, transforms: Vec<Transform>) {
+        match self.fields.iter_mut().find(|(name, _)| name == field) {
+            Some((_, old)) => *old = transforms,
+            None => self.fields.push((field.to_string(), transforms)),
+        }
+    }
+
+    /// Runs the chains on every record. A missing field is treated as an empty text, so it is
+    /// only added when its chain has a `Default`. When a chain fails the records are left unchanged.
+    pub fn apply(&self, records: &mut [Record]) -> Result<(), TransformError> {
+        let mut transformed = records.to_vec();
+        for record in transformed.iter_mut() {
+            for (field, transforms) in self.fields.iter() {
+                let value = record.get(field).cloned();
+                let missing = value.is_none();
+                let value = apply_transforms(value.unwrap_or(Value::Text(String::new())), transforms).map_err(|error| {
+                    TransformError {
+                        field: Some(field.clone()),
+                        ..error
+                    }
+                })?;
+                if !missing || !is_empty(&value) {
+                    record.insert(field, value);
+                }
+            }
+        }
+        records.clone_from_slice(&transformed);
+        Ok(())
+    }
+}
+
+/// Runs `transforms` on every scraped value, typically the text of the elements returned by `scrape`.
+pub fn transform_values(values: &[String], transforms: &[Transform]) -> Result<Vec<Value>, TransformError> {
+    values
+        .iter()
+        .map(|value| apply_transforms(Value::Text(value.clone()), transforms))
+        .collect()
+}
+
+/// Runs `transforms` on `value`, in order.
+pub fn apply_transforms(value: Value, transforms: &[Transform]) -> Result<Value, TransformError> {
+    transforms.iter().try_fold(value, apply_transform)
+}
+
+fn apply_transform(value: Value, transform: &Transform) -> Result<Value, TransformError> {
+    match transform {
+        Transform::Split { separator } => Ok(Value::List(
+            value.as_text()
+                .split(separator.as_str())
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect(),
+        )),
+        Transform::Default(default) if is_empty(&value) => Ok(Value::Text(default.clone())),
+        Transform::Default(_) => Ok(value),
+        Transform::ParseNumber(locale) => match value {
+            Value::Number(_) => Ok(value),
+            Value::List(items) => items
+                .iter()
+                .map(|item| parse_number(item, locale).map(|number| number.to_string()))
+                .collect::<Result<Vec<String>, TransformError>>()
+                .map(Value::List),
+            Value::Text(text) => parse_number(&text, locale).map(Value::Number),
+        },
+        Transform::Trim => map_text(value, |text| Ok(text.trim().to_string())),
+        Transform::Lowercase => map_text(value, |text| Ok(text.to_lowercase())),
+        Transform::Replace { pattern, replacement } => {
+            map_text(value, |text| Ok(pattern.replace_all(text, replacement.as_str()).into_owned()))
+        }
+        Transform::ParseDate { formats } => map_text(value, |text| parse_date(text, formats)),
+        Transform::ResolveUrl(base_url) => map_text(value, |text| {
+            resolve_url(base_url, text).ok_or_else(|| TransformError::new(text, "invalid URL"))
+        }),
+    }
+}
+
+/// Applies a text transform to a text, or to every item of a list.
+fn map_text<F>(value: Value, map: F) -> Result<Value, TransformError>
+where
+    F: Fn(&str) -> Result<String, TransformError>,
+{
+    match value {
+        Value::List(items) => items
+            .iter()
+            .map(|item| map(item))
+            .collect::<Result<Vec<String>, TransformError>>()
+            .map(Value::List),
+        _ => map(&value.as_text()).map(Value::Text),
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Text(text) => text.trim().is_empty(),
+        Value::List(items) => items.is_empty(),
+        Value::Number(_) => false,
+    }
+}
+
+fn parse_number(text: &str, locale: &NumberLocale) -> Result<f64, TransformError> {
+    // separators are only part of the number between groups of three digits or before the
+    // decimals, so `1 2` is two numbers
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    let decimal_separator = locale.decimal_separator();
+    let found = NUMBER
+        .get_or_init(|| Regex::new(r"[-+−]?[0-9]+(?:[.,' \u{a0}\u{202f}’][0-9]{3})*(?:[.,][0-9]+)?").unwrap())
+        .find(text)
+        .ok_or_else(|| TransformError::new(text, "no number found"))?;
+    let mut normalized = String::new();
+    for (index, c) in found.as_str().char_indices() {
+        match c {
+            '−' => normalized.push('-'),
+            c if c == decimal_separator => normalized.push('.'),
+            c if GROUP_SEPARATORS.contains(&c) => {
+                // a group separator is always followed by exactly three digits, `1,5` with a
+                // decimal point or `1,2345` can not be read safely
+                let rest = &text[found.start() + index + c.len_utf8()..];
+                if rest.chars().take_while(char::is_ascii_digit).count() != 3 {
+                    return Err(TransformError::new(text, "ambiguous number"));
+                }
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+        .parse::<f64>()
+        .map_err(|_| TransformError::new(text, "invalid number"))
+}
+
+fn parse_date(text: &str, formats: &[String]) -> Result<String, TransformError> {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if let Ok(date) = DateTime::parse_from_rfc3339(&text) {
+        return Ok(date.to_rfc3339());
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(&text) {
+        return Ok(date.to_rfc3339());
+    }
+    let formats: Vec<&str> = if formats.is_empty() {
+        DEFAULT_DATE_FORMATS.to_vec()
+    } else {
+        formats.iter().map(String::as_str).collect()
+    };
+    for format in formats {
+        if let Ok(date) = DateTime::parse_from_str(&text, format) {
+            return Ok(date.to_rfc3339());
+        }
+        if let Ok(date) = NaiveDateTime::parse_from_str(&text, format) {
+            return Ok(date.format("%Y-%m-%dT%H:%M:%S").to_string());
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(&text, format) {
+            return Ok(date.format("%Y-%m-%d").to_string());
+        }
+    }
+    Err(TransformError::new(&text, "unrecognized date"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_string())
+    }
+
+    fn apply_one(value: &str, transform: &Transform) -> Result<Value, TransformError> {
+        apply_transforms(text(value), std::slice::from_ref(transform))
+    }
+
+    #[test]
+    fn test_text_transforms() {
+        let transforms = vec![
+            Transform::Trim,
+            Transform::Lowercase,
+            Transform::replace(r"\s+", "-").unwrap(),
+        ];
+        assert_eq!(apply_transforms(text("  Hello   Big World "), &transforms), Ok(text("hello-big-world")));
+        let error = Transform::replace("(unclosed", "").unwrap_err();
+        assert_eq!(error.message, "invalid regex");
+    }
+
+    #[test]
+    fn test_parse_number() {
+        let en = Transform::ParseNumber(NumberLocale::from_language("en-US"));
+        let de = Transform::ParseNumber(NumberLocale::from_language("de"));
+        let ch = Transform::ParseNumber(NumberLocale::from_language("de-CH"));
+        assert_eq!(apply_one("Price: $1,234.50 USD", &en), Ok(Value::Number(1234.5)));
+        assert_eq!(apply_one("1.234,50 €", &de), Ok(Value::Number(1234.5)));
+        assert_eq!(apply_one("-3 000,25", &de), Ok(Value::Number(-3000.25)));
+        assert_eq!(apply_one("CHF 1'250.75", &ch), Ok(Value::Number(1250.75)));
+        assert_eq!(apply_one("4.5 out of 5", &en), Ok(Value::Number(4.5)));
+        assert_eq!(apply_one("1 2", &en), Ok(Value::Number(1.0)));
+        assert_eq!(apply_one("Page 2 of 100", &en), Ok(Value::Number(2.0)));
+        assert_eq!(apply_one("12 345 678,9", &de), Ok(Value::Number(12345678.9)));
+        let error = apply_one("free", &en).unwrap_err();
+        assert_eq!(error.message, "no number found");
+        let error = apply_one("1,5", &en).unwrap_err();
+        assert_eq!(error.message, "ambiguous number");
+        let error = apply_one("1,2345", &en).unwrap_err();
+        assert_eq!(error.message, "ambiguous number");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let default_formats = Transform::ParseDate { formats: vec![] };
+        assert_eq!(apply_one("March 1, 2024", &default_formats), Ok(text("2024-03-01")));
+        assert_eq!(apply_one("01/03/2024", &default_formats), Ok(text("2024-03-01")));
+        assert_eq!(
+            apply_one("Fri, 01 Mar 2024 08:30:00 +0100", &default_formats),
+            Ok(text("2024-03-01T08:30:00+01:00"))
+        );
+        let us = Transform::ParseDate { formats: vec!["%m/%d/%Y %H:%M".to_string()] };
+        assert_eq!(apply_one("03/01/2024 17:05", &us), Ok(text("2024-03-01T17:05:00")));
+        assert!(apply_one("yesterday", &default_formats).is_err());
+    }
+
+    #[test]
+    fn test_split_and_resolve_url() {
+        let base_url = Url::parse("https://example.com/shop/").unwrap();
+        let transforms = vec![
+            Transform::Split { separator: ",".to_string() },
+            Transform::ResolveUrl(base_url),
+        ];
+        assert_eq!(
+            apply_transforms(text("a.png, /b.png,"), &transforms),
+            Ok(Value::List(vec!["https://example.com/shop/a.png".to_string(), "https://example.com/b.png".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut first = Record::new();
+        first.insert("name", text(" Widget "));
+        first.insert("price", text("9,99 €"));
+        let mut second = Record::new();
+        second.insert("name", text("Gadget"));
+        second.insert("price", text("n/a"));
+        let mut pipeline = Pipeline::new();
+        pipeline.insert("name", vec![Transform::Trim]);
+        pipeline.insert("stock", vec![Transform::Default("unknown".to_string())]);
+        pipeline.insert("price", vec![Transform::ParseNumber(NumberLocale::DecimalComma)]);
+
+        let mut records = vec![first.clone()];
+        pipeline.apply(&mut records).unwrap();
+        assert_eq!(records[0].fields, vec![
+            ("name".to_string(), text("Widget")),
+            ("price".to_string(), Value::Number(9.99)),
+            ("stock".to_string(), text("unknown")),
+        ]);
+
+        let mut records = vec![first.clone(), second];
+        let error = pipeline.apply(&mut records).unwrap_err();
+        assert_eq!(error.field, Some("price".to_string()));
+        assert_eq!(error.value, "n/a");
+        assert_eq!(records[0], first, "should leave the records unchanged on error");
+    }
+}
@@ -1,8 +1,11 @@
 use std::collections::HashSet;
 
 use super::storage::ScraperGenerator;
+use super::storage::StorageError;
 use super::storage::StorageOptions;
-use html_parser::Dom;
+use super::records::csv_cell;
+use crate::scrapper::parse::parse_element;
+use crate::scrapper::provenance::Provenance;
 
 pub struct ScraperCSVGenerator<'a>{
     tags: ScraperGenerator<'a>, 
@@ -18,13 +21,26 @@ impl<'a> ScraperCSVGenerator<'a> {
             order: vec![]
         }
     }
+
+    pub fn with_metadata(mut self, metadata: &'a [Provenance]) -> Self {
+        self.tags.metadata = metadata;
+        self
+    }
+
+    /// Provenance columns, written after the others.
+    fn metadata_columns(&self, delimeter: &str) -> String {
+        if !self.tags.options.include_metadata.unwrap_or(false){
+            return "".to_string();
+        }
+        format!("{}{}", delimeter, Provenance::FIELDS.join(delimeter))
+    }
     
     fn first_gen(&mut self) -> Option<String> {
         let mut csv_order: HashSet<String> = HashSet::new();
         let delimeter = self.tags.options.delimiter.clone().unwrap_or(",".to_string());
         if !self.tags.options.include_tag_content.unwrap_or(false){
             self.order.push("text".to_string());
-            return Some("text\n".to_string());
+            return Some(format!("text{}\n", self.metadata_columns(&delimeter)));
         }
         if self.tags.options.include_tag_names.unwrap_or(true){
             self.order.push("tag".to_string());
@@ -36,11 +52,13 @@ impl<'a> ScraperCSVGenerator<'a> {
         }
         else {
             for tag in self.tags.data{
-                let tag = Dom::parse(&tag).unwrap().children[0].element().unwrap().clone();
-                if !tag.id.is_none(){
+                let Ok(tag) = parse_element(tag) else {
+                    continue;
+                };
+                if tag.id.is_some(){
                     csv_order.insert("id".to_string());
                 }
-                if tag.classes.len() > 0{
+                if !tag.classes.is_empty(){
                     csv_order.insert("class".to_string());
                 }
                 for (key, _) in tag.attributes.iter(){
@@ -53,20 +71,27 @@ impl<'a> ScraperCSVGenerator<'a> {
         }
         self.order.push("text".to_string());
         let mut header = self.order.join(&delimeter).to_owned();
-        header.push_str("\n");
-        return Some(header);
+        header.push_str(&self.metadata_columns(&delimeter));
+        header.push('\n');
+        Some(header)
     }
 }
 
 impl<'a> Iterator for ScraperCSVGenerator<'a> {
-    type Item = String;
+    type Item = Result<String, StorageError>;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.tags.failed {
+            return None;
+        }
         if self.first{
             self.first = false;
-            return self.first_gen();
+            return self.first_gen().map(Ok);
         }
         if self.tags.index < self.tags.data.len() {
-            let tag = Dom::parse(&self.tags.data[self.tags.index]).unwrap().children[0].element().unwrap().clone();
+            let tag = match self.tags.element(self.tags.index) {
+                Ok(tag) => tag,
+                Err(error) => return Some(Err(error)),
+            };
             let delimeter = self.tags.options.delimiter.clone().unwrap_or(",".to_string());
             let mut csv_line: Vec<String> = vec![];
             for header in self.order.iter(){
@@ -74,15 +99,24 @@ impl<'a> Iterator for ScraperCSVGenerator<'a> {
                     "tag" => csv_line.push(tag.name.clone()),
                     "class" => csv_line.push(tag.classes.join(" ")),
                     "id" => csv_line.push(tag.id.clone().unwrap_or("".to_string())),
-                    "text" => csv_line.push(tag.children.iter().map(|node|node.text().unwrap_or("")).collect::<Vec<&str>>().join(" ")),
+                    "text" => csv_line.push(csv_cell(&self.tags.text(&tag), &delimeter)),
                     default => csv_line.push(tag.attributes.get(default).unwrap_or(&Some("".to_string())).clone().unwrap_or("".to_string()))
                 }
             }
-            let a = 
+            if self.tags.options.include_metadata.unwrap_or(false){
+                match self.tags.provenance(self.tags.index){
+                    Some(provenance) => {
+                        for (_, value) in provenance.values(){
+                            csv_line.push(csv_cell(&value.unwrap_or_default(), &delimeter));
+                        }
+                    }
+                    None => csv_line.extend(Provenance::FIELDS.iter().map(|_| "".to_string())),
+                }
+            }
             self.tags.index += 1;
             let mut row = csv_line.join(&delimeter).to_owned();
-            row.push_str("\n");
-            return Some(row);
+            row.push('\n');
+            return Some(Ok(row));
         }
         None
     }    
@@ -102,8 +136,8 @@ mod tests {
             ..StorageOptions::new("test.csv".to_string())
         };
         let mut generator = ScraperCSVGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("text\n".to_string()));
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), Some("text\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -121,11 +155,11 @@ mod tests {
             ..StorageOptions::new("test.csv".to_string())
         };
         let mut generator = ScraperCSVGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("tag,class,id,data-role,text\n".to_string()));
-        assert_eq!(generator.next(), Some("div,test,div1,main,hello world\n".to_string()));
-        assert_eq!(generator.next(), Some("span,test,span1,secondary,hello rust\n".to_string()));
-        assert_eq!(generator.next(), Some("div,test,div2,main,goodbye world\n".to_string()));
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), Some("tag,class,id,data-role,text\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("div,test,div1,main,hello world\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("span,test,span1,secondary,hello rust\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("div,test,div2,main,goodbye world\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -142,11 +176,11 @@ mod tests {
             ..StorageOptions::new("test.csv".to_string())
         };
         let mut generator = ScraperCSVGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("tag,class,id,data-role,text\n".to_string()));
-        assert_eq!(generator.next(), Some("div,test,,main,hello world\n".to_string())); // Missing id
-        assert_eq!(generator.next(), Some("span,test,span1,,hello rust\n".to_string())); // Missing data-role
-        assert_eq!(generator.next(), Some("div,test,div2,main,goodbye world\n".to_string()));
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), Some("tag,class,id,data-role,text\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("div,test,,main,hello world\n".to_string())); // Missing id
+        assert_eq!(generator.next().transpose().unwrap(), Some("span,test,span1,,hello rust\n".to_string())); // Missing data-role
+        assert_eq!(generator.next().transpose().unwrap(), Some("div,test,div2,main,goodbye world\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -162,11 +196,11 @@ mod tests {
             ..StorageOptions::new("test.csv".to_string())
         };
         let mut generator = ScraperCSVGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("text\n".to_string()));
-        assert_eq!(generator.next(), Some("hello world\n".to_string()));
-        assert_eq!(generator.next(), Some("hello rust\n".to_string()));
-        assert_eq!(generator.next(), Some("goodbye world\n".to_string()));
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), Some("text\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("hello world\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("hello rust\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("goodbye world\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -184,10 +218,26 @@ mod tests {
             ..StorageOptions::new("test.csv".to_string())
         };
         let mut generator = ScraperCSVGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("tag;class;id;data-role;text\n".to_string()));
-        assert_eq!(generator.next(), Some("div;test;div1;main;hello world\n".to_string()));
-        assert_eq!(generator.next(), Some("span;test;span1;secondary;hello rust\n".to_string()));
-        assert_eq!(generator.next(), Some("div;test;div2;main;goodbye world\n".to_string()));
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), Some("tag;class;id;data-role;text\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("div;test;div1;main;hello world\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("span;test;span1;secondary;hello rust\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("div;test;div2;main;goodbye world\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_scraper_csv_generator_metadata() {
+        let data = vec!["<div>hello world</div>".to_string(), "<div>goodbye</div>".to_string()];
+        let metadata = vec![Provenance::sample()];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Csv),
+            include_metadata: Some(true),
+            ..StorageOptions::new("test.csv".to_string())
+        };
+        let mut generator = ScraperCSVGenerator::new(&data, &options).with_metadata(&metadata);
+        assert_eq!(generator.next().transpose().unwrap(), Some("text,source_url,fetched_at,status,path,offset,hash\n".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("hello world,https://example.com/,,200,body > div,12,00ff\n".to_string()));
+        // elements without provenance keep the columns
+        assert_eq!(generator.next().transpose().unwrap(), Some("goodbye,,,,,,\n".to_string()));
+    }
+}
@@ -1,9 +1,12 @@
 use super::storage::StorageOptions;
+use super::storage::provenance_comment;
+use crate::scrapper::provenance::Provenance;
 
 pub struct CustomDataGenerator<'a> {
     pub data: &'a Vec<String>,
     pub options: &'a StorageOptions,
     pub index: usize,
+    pub metadata: &'a [Provenance],
 }
 
 impl<'a> CustomDataGenerator<'a> {
@@ -12,9 +15,15 @@ impl<'a> CustomDataGenerator<'a> {
             data,
             options,
             index: 0,
+            metadata: &[],
         }
     }
 
+    pub fn with_metadata(mut self, metadata: &'a [Provenance]) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
 }
 
 impl Iterator for CustomDataGenerator<'_> {
@@ -23,7 +32,10 @@ impl Iterator for CustomDataGenerator<'_> {
     fn next(&mut self) -> Option<Self::Item> {
         if self.index < self.data.len() {
             if let Some(custom_data_storage) = self.options.custom_data_storage {
-                custom_data_storage(&self.data[self.index]);
+                match self.metadata.get(self.index).filter(|_| self.options.include_metadata.unwrap_or(false)) {
+                    Some(provenance) => custom_data_storage(&format!("{}{}", provenance_comment(provenance), self.data[self.index])),
+                    None => custom_data_storage(&self.data[self.index]),
+                }
             }
             self.index += 1;
             Some("".to_string())
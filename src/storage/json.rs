@@ -1,9 +1,14 @@
 use std::collections::HashSet;
 
 use super::storage::ScraperGenerator;
+use super::storage::StorageError;
 use super::storage::StorageOptions;
 
-use html_parser::{Dom, Element};
+use html_parser::Element;
+
+use super::records::json_string;
+use crate::scrapper::parse::parse_element;
+use crate::scrapper::provenance::Provenance;
 
 
 pub struct ScraperJSONGenerator<'a> {
@@ -27,6 +32,11 @@ impl<'a> ScraperJSONGenerator<'a> {
         }
     }
 
+    pub fn with_metadata(mut self, metadata: &'a [Provenance]) -> Self {
+        self.tags.metadata = metadata;
+        self
+    }
+
     fn pretty_print(&self) -> bool {
         self.tags.options.pretty_print.unwrap_or(false)
     }
@@ -44,16 +54,13 @@ impl<'a> ScraperJSONGenerator<'a> {
             } else {
                 let mut attr_order: HashSet<String> = HashSet::new();
                 for tag in self.tags.data {
-                    let tag = Dom::parse(&tag)
-                        .unwrap()
-                        .children[0]
-                        .element()
-                        .unwrap()
-                        .clone();
-                    if !tag.id.is_none() {
+                    let Ok(tag) = parse_element(tag) else {
+                        continue;
+                    };
+                    if tag.id.is_some() {
                         attr_order.insert("id".to_string());
                     }
-                    if tag.classes.len() > 0 {
+                    if !tag.classes.is_empty() {
                         attr_order.insert("class".to_string());
                     }
                     for (key, _) in tag.attributes.iter() {
@@ -66,19 +73,19 @@ impl<'a> ScraperJSONGenerator<'a> {
             }
         }
         self.order.push("text".to_string());
-        return "[".to_string();
+        "[".to_string()
     }
 
     fn prettify(&self, line: String) -> String {
         let mut result = String::new();
         if self.pretty_print() {
-            result.push_str("\n");
+            result.push('\n');
         }
         for _ in 0..self.identation {
             result.push_str("  ");
         }
         result.push_str(&line);
-        return result;
+        result
     }
     
     fn handle_html_tagname_extract(&self, tag: &Element, json_row: &mut String, header: &String) {
@@ -126,18 +133,38 @@ impl<'a> ScraperJSONGenerator<'a> {
     fn handle_html_text_extract(&self, tag: &Element, json_row: &mut String, header: &String) {
         json_row.push_str(
             format!(
-                r#""{}":"{}""#,
+                r#""{}":{}"#,
                 header,
-                tag.children
-                    .iter()
-                    .map(|node| node.text().unwrap_or(""))
-                    .collect::<Vec<&str>>()
-                    .join(" ")
+                json_string(&self.tags.text(tag))
             )
             .as_str(),
         )
     }
     
+    fn handle_metadata_extract(&mut self, provenance: &Provenance, json_row: &mut String) {
+        json_row.push_str(self.prettify(r#""metadata":{"#.to_string()).as_str());
+        if self.pretty_print() {
+            self.identation += 1;
+        }
+        let fields = provenance.values()
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match (name, value) {
+                    (_, None) => "null".to_string(),
+                    ("status" | "offset", Some(number)) => number,
+                    (_, Some(value)) => json_string(&value),
+                };
+                self.prettify(format!(r#""{}":{}"#, name, value))
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        json_row.push_str(&fields);
+        if self.pretty_print() {
+            self.identation -= 1;
+        }
+        json_row.push_str(self.prettify("}".to_string()).as_str());
+    }
+
     fn handle_extract_attribute(&self, tag: &Element, json_row: &mut String, header: &String, default: &str) {
         let Some(attribute_value) = tag.attributes.get(default) else {
             json_row.clear();
@@ -157,24 +184,24 @@ impl<'a> ScraperJSONGenerator<'a> {
 }
 
 impl<'a> Iterator for ScraperJSONGenerator<'a> {
-    type Item = String;
+    type Item = Result<String, StorageError>;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.tags.failed {
+            return None;
+        }
         if self.first {
             self.first = false;
             let header = self.first_gen();
-            let header = header;
             if self.pretty_print() {
                 self.identation += 1;
             }
-            return Some(header);
+            return Some(Ok(header));
         }
         if self.index < self.tags.data.len() {
-            let tag = Dom::parse(&self.tags.data[self.index])
-                .unwrap()
-                .children[0]
-                .element()
-                .unwrap()
-                .clone();
+            let tag = match self.tags.element(self.index) {
+                Ok(tag) => tag,
+                Err(error) => return Some(Err(error)),
+            };
             let mut json_row = String::new();
             json_row.push_str(self.prettify("{".to_string()).as_str());
             if self.pretty_print() {
@@ -194,20 +221,24 @@ impl<'a> Iterator for ScraperJSONGenerator<'a> {
                 }
                 json_append = self.prettify(json_append);
                 if i > 0 {
-                    json_row.push_str(",");
+                    json_row.push(',');
                 }
                 json_row.push_str(json_append.as_str());
             }
+            if let Some(provenance) = self.tags.provenance(self.index) {
+                json_row.push(',');
+                self.handle_metadata_extract(provenance, &mut json_row);
+            }
             if self.pretty_print() {
                 self.identation -= 1;
             }
             let mut row_tail = "}".to_string();
             if self.index < self.tags.data.len() - 1 {
-                row_tail.push_str(",");
+                row_tail.push(',');
             }
             json_row.push_str(self.prettify(row_tail).as_str());
             self.index += 1;
-            return Some(json_row);
+            return Some(Ok(json_row));
         }
         if self.last {
             self.last = false;
@@ -215,7 +246,7 @@ impl<'a> Iterator for ScraperJSONGenerator<'a> {
                 self.identation -= 1;
             }
             let footer = self.prettify("]".to_string());
-            return Some(footer);
+            return Some(Ok(footer));
         }
         None
     }
@@ -234,8 +265,28 @@ mod tests {
             ..StorageOptions::new("test.json".to_string())
         };
         let mut generator = ScraperJSONGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("[".to_string()));
-        assert_eq!(generator.next(), Some("]".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("[".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("]".to_string()));
+    }
+
+    #[test]
+    fn test_scraper_json_generator_parse_error() {
+        let data = vec![
+            "<div>hello world</div>".to_string(),
+            "<!-- <div>".to_string(),
+        ];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Json),
+            ..StorageOptions::new("test.json".to_string())
+        };
+        let mut generator = ScraperJSONGenerator::new(&data, &options);
+        assert_eq!(generator.next().transpose().unwrap(), Some("[".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"text":"hello world"},"#.to_string()));
+        match generator.next() {
+            Some(Err(StorageError::Parse { record, .. })) => assert_eq!(record, 1),
+            _ => panic!("should return the parse error of the second record"),
+        }
+        assert!(generator.next().is_none(), "should stop after the error");
     }
 
     #[test]
@@ -255,11 +306,11 @@ mod tests {
             ..StorageOptions::new("test.json".to_string())
         };
         let mut generator = ScraperJSONGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("[".to_string()));
-        assert_eq!(generator.next(), Some(r#"{"tag":"div","class":["test"],"id":"div1","data-role":"main","text":"hello world"},"#.to_string()));
-        assert_eq!(generator.next(), Some(r#"{"tag":"span","class":["test"],"id":"span1","data-role":"secondary","text":"hello rust"},"#.to_string()));
-        assert_eq!(generator.next(), Some(r#"{"tag":"div","class":["test"],"id":"div2","data-role":"main","text":"goodbye world"}"#.to_string()));
-        assert_eq!(generator.next(), Some("]".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("[".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"tag":"div","class":["test"],"id":"div1","data-role":"main","text":"hello world"},"#.to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"tag":"span","class":["test"],"id":"span1","data-role":"secondary","text":"hello rust"},"#.to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"tag":"div","class":["test"],"id":"div2","data-role":"main","text":"goodbye world"}"#.to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("]".to_string()));
     }
 
     #[test]
@@ -279,11 +330,11 @@ mod tests {
             ..StorageOptions::new("test.json".to_string())
         };
         let mut generator = ScraperJSONGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("[".to_string()));
-        assert_eq!(generator.next(), Some(r#"{"tag":"div","class":["test"],"data-role":"main","text":"hello world"},"#.to_string())); // Missing id
-        assert_eq!(generator.next(), Some(r#"{"tag":"span","class":["test"],"id":"span1","text":"hello rust"},"#.to_string())); // Missing data-role
-        assert_eq!(generator.next(), Some(r#"{"tag":"div","class":["test"],"id":"div2","data-role":"main","text":"goodbye world"}"#.to_string()));
-        assert_eq!(generator.next(), Some("]".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("[".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"tag":"div","class":["test"],"data-role":"main","text":"hello world"},"#.to_string())); // Missing id
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"tag":"span","class":["test"],"id":"span1","text":"hello rust"},"#.to_string())); // Missing data-role
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"tag":"div","class":["test"],"id":"div2","data-role":"main","text":"goodbye world"}"#.to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("]".to_string()));
     }
 
     #[test]
@@ -303,11 +354,25 @@ mod tests {
             ..StorageOptions::new("test.json".to_string())
         };
         let mut generator = ScraperJSONGenerator::new(&data, &options);
-        assert_eq!(generator.next(), Some("[".to_string()));
-        assert_eq!(generator.next(), Some(r#"{"text":"hello world"},"#.to_string()));
-        assert_eq!(generator.next(), Some(r#"{"text":"hello rust"},"#.to_string()));
-        assert_eq!(generator.next(), Some(r#"{"text":"goodbye world"}"#.to_string()));
-        assert_eq!(generator.next(), Some("]".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("[".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"text":"hello world"},"#.to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"text":"hello rust"},"#.to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"text":"goodbye world"}"#.to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some("]".to_string()));
+    }
+
+    #[test]
+    fn test_scraper_json_generator_nested_text() {
+        let data = vec![
+            "<div>Fish &amp; <b>chips</b>&nbsp;&quot;to go&quot;\n  now</div>".to_string(),
+        ];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Json),
+            ..StorageOptions::new("test.json".to_string())
+        };
+        let mut generator = ScraperJSONGenerator::new(&data, &options);
+        assert_eq!(generator.next().transpose().unwrap(), Some("[".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"text":"Fish & chips \"to go\" now"}"#.to_string()));
     }
 
     #[test]
@@ -326,9 +391,9 @@ mod tests {
             pretty_print: Some(true),
             ..StorageOptions::new("test.json".to_string())
         };
-        let mut generator = ScraperJSONGenerator::new(&data, &options);
+        let generator = ScraperJSONGenerator::new(&data, &options);
         let mut result = String::new();
-        while let Some(line) = generator.next() {
+        for line in generator.map(Result::unwrap) {
             result.push_str(&line);
         }
         let expected = r#"[
@@ -373,9 +438,9 @@ mod tests {
             pretty_print: Some(true),
             ..StorageOptions::new("test.json".to_string())
         };
-        let mut generator = ScraperJSONGenerator::new(&data, &options);
+        let generator = ScraperJSONGenerator::new(&data, &options);
         let mut result = String::new();
-        while let Some(line) = generator.next() {
+        for line in generator.map(Result::unwrap) {
             result.push_str(&line);
         }
         let expected = r#"[
@@ -403,4 +468,25 @@ mod tests {
 ]"#.to_string();
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_scraper_json_generator_metadata() {
+        let data = vec!["<div>hello world</div>".to_string()];
+        let metadata = vec![Provenance::sample()];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Json),
+            include_metadata: Some(true),
+            ..StorageOptions::new("test.json".to_string())
+        };
+        let mut generator = ScraperJSONGenerator::new(&data, &options).with_metadata(&metadata);
+        assert_eq!(generator.next().transpose().unwrap(), Some("[".to_string()));
+        assert_eq!(generator.next().transpose().unwrap(), Some(r#"{"text":"hello world","metadata":{"source_url":"https://example.com/","fetched_at":null,"status":200,"path":"body > div","offset":12,"hash":"00ff"}}"#.to_string()));
+        let options = StorageOptions {
+            pretty_print: Some(true),
+            ..options
+        };
+        let result: String = ScraperJSONGenerator::new(&data, &options).with_metadata(&metadata).map(Result::unwrap).collect();
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed[0]["metadata"]["path"], "body > div");
+    }
 }
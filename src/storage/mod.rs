@@ -1,5 +1,6 @@
-use std::fs::File;
+#[allow(clippy::module_inception)]
 pub mod storage;
+pub mod records;
 mod txt;
 mod json;
 mod csv;
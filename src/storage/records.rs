@@ -0,0 +1,352 @@
+use std::fs::File;
+use std::io::Write;
+
+use super::storage::{FileFormat, StorageError, StorageOptions};
+
+/// A value of a record field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    List(Vec<String>),
+    Number(f64),
+}
+
+impl Value {
+    /// Returns the value as a single string, list items are joined with a space.
+    pub fn as_text(&self) -> String {
+        match self {
+            Value::Text(text) => text.clone(),
+            Value::List(items) => items.join(" "),
+            Value::Number(number) => number.to_string(),
+        }
+    }
+}
+
+/// A row of named values, produced by extractors whose output is not a single HTML element
+/// (tables, links, metadata...). Fields keep their insertion order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    pub fields: Vec<(String, Value)>,
+}
+
+impl Record {
+    pub fn new() -> Self {
+        Self { fields: vec![] }
+    }
+
+    /// Sets `key` to `value`, replacing the previous value of the field if any.
+    pub fn insert(&mut self, key: &str, value: Value) {
+        match self.fields.iter_mut().find(|(name, _)| name == key) {
+            Some((_, old)) => *old = value,
+            None => self.fields.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.fields.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+}
+
+/// Stores `records` using the file name, format, `pretty_print` and `delimiter` of `options`.
+/// Tag related options are ignored, every field of every record is written.
+///
+/// Columns follow the order in which fields are first seen. Missing fields are written as
+/// empty CSV cells and left out of the other formats. `FileFormat::Custom` calls
+/// `custom_data_storage` with every record rendered as a JSON object.
+pub fn store_records(records: &[Record], options: &StorageOptions) -> Result<(), StorageError> {
+    let lines = match options.file_format.as_ref().unwrap_or(&FileFormat::Txt) {
+        FileFormat::Txt => txt_lines(records),
+        FileFormat::Json => json_lines(records, options),
+        FileFormat::Csv => csv_lines(records, options),
+        FileFormat::Xml => xml_lines(records, options),
+        FileFormat::Yaml => yaml_lines(records),
+        FileFormat::Custom => {
+            if let Some(custom_data_storage) = options.custom_data_storage {
+                for record in records {
+                    custom_data_storage(&json_object(record, false, 0));
+                }
+            }
+            return Ok(());
+        }
+    };
+    let mut file = File::create(&options.file_name)?;
+    for line in lines {
+        file.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn columns(records: &[Record]) -> Vec<String> {
+    let mut columns: Vec<String> = vec![];
+    for record in records {
+        for (name, _) in record.fields.iter() {
+            if !columns.contains(name) {
+                columns.push(name.clone());
+            }
+        }
+    }
+    columns
+}
+
+fn txt_lines(records: &[Record]) -> Vec<String> {
+    records
+        .iter()
+        .map(|record| {
+            let mut line = record.fields
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value.as_text()))
+                .collect::<Vec<String>>()
+                .join("\t");
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+fn csv_lines(records: &[Record], options: &StorageOptions) -> Vec<String> {
+    let delimiter = options.delimiter.clone().unwrap_or(",".to_string());
+    let columns = columns(records);
+    let mut lines = vec![];
+    let mut header = columns
+        .iter()
+        .map(|column| csv_cell(column, &delimiter))
+        .collect::<Vec<String>>()
+        .join(&delimiter);
+    header.push('\n');
+    lines.push(header);
+    for record in records {
+        let mut row = columns
+            .iter()
+            .map(|column| csv_cell(&record.get(column).map(Value::as_text).unwrap_or_default(), &delimiter))
+            .collect::<Vec<String>>()
+            .join(&delimiter);
+        row.push('\n');
+        lines.push(row);
+    }
+    lines
+}
+
+pub(crate) fn csv_cell(value: &str, delimiter: &str) -> String {
+    if value.contains(delimiter) || value.contains('"') || value.contains('\n') {
+        return format!("\"{}\"", value.replace('"', "\"\""));
+    }
+    value.to_string()
+}
+
+fn json_lines(records: &[Record], options: &StorageOptions) -> Vec<String> {
+    let pretty_print = options.pretty_print.unwrap_or(false);
+    let mut lines = vec!["[".to_string()];
+    for (index, record) in records.iter().enumerate() {
+        let mut line = String::new();
+        if pretty_print {
+            line.push_str("\n  ");
+        }
+        line.push_str(&json_object(record, pretty_print, 1));
+        if index < records.len() - 1 {
+            line.push(',');
+        }
+        lines.push(line);
+    }
+    lines.push(if pretty_print { "\n]" } else { "]" }.to_string());
+    lines
+}
+
+pub(crate) fn json_object(record: &Record, pretty_print: bool, indent: usize) -> String {
+    let separator = if pretty_print {
+        format!(",\n{}", "  ".repeat(indent + 1))
+    } else {
+        ",".to_string()
+    };
+    let fields = record.fields
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::Text(text) => json_string(text),
+                // JSON has no representation for NaN and infinities
+                Value::Number(number) if number.is_finite() => number.to_string(),
+                Value::Number(_) => "null".to_string(),
+                Value::List(items) => format!(
+                    "[{}]",
+                    items.iter().map(|item| json_string(item)).collect::<Vec<String>>().join(if pretty_print { ", " } else { "," })
+                ),
+            };
+            format!("{}:{}", json_string(name), value)
+        })
+        .collect::<Vec<String>>()
+        .join(&separator);
+    if pretty_print && !record.fields.is_empty() {
+        return format!("{{\n{}{}\n{}}}", "  ".repeat(indent + 1), fields, "  ".repeat(indent));
+    }
+    format!("{{{}}}", fields)
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    // serializing a string can not fail
+    serde_json::to_string(value).unwrap()
+}
+
+fn xml_lines(records: &[Record], options: &StorageOptions) -> Vec<String> {
+    let pretty_print = options.pretty_print.unwrap_or(false);
+    let newline = |indent: usize| if pretty_print { format!("\n{}", "  ".repeat(indent)) } else { String::new() };
+    let mut lines = vec![r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string()];
+    for record in records {
+        let mut line = format!("{}<data>", newline(0));
+        for (name, value) in record.fields.iter() {
+            let name = xml_name(name);
+            match value {
+                Value::Text(_) | Value::Number(_) => line.push_str(&format!("{}<{1}>{2}</{1}>", newline(1), name, xml_escape(&value.as_text()))),
+                Value::List(items) => {
+                    line.push_str(&format!("{}<{}>", newline(1), name));
+                    for item in items {
+                        line.push_str(&format!("{}<item>{}</item>", newline(2), xml_escape(item)));
+                    }
+                    line.push_str(&format!("{}</{}>", newline(1), name));
+                }
+            }
+        }
+        line.push_str(&format!("{}</data>", newline(0)));
+        lines.push(line);
+    }
+    lines
+}
+
+pub(crate) fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Field names are free text (table headers for example), XML element names are not.
+fn xml_name(name: &str) -> String {
+    let mut element_name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect();
+    if !element_name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        element_name.insert(0, '_');
+    }
+    element_name
+}
+
+fn yaml_lines(records: &[Record]) -> Vec<String> {
+    let mut lines = vec![];
+    for (index, record) in records.iter().enumerate() {
+        let mut line = String::new();
+        if index > 0 {
+            line.push('\n');
+        }
+        line.push_str("data:");
+        for (name, value) in record.fields.iter() {
+            match value {
+                Value::Text(text) => line.push_str(&format!("\n  - {}: {}", yaml_scalar(name), yaml_scalar(text))),
+                Value::Number(number) => line.push_str(&format!("\n  - {}: {}", yaml_scalar(name), number)),
+                Value::List(items) => {
+                    line.push_str(&format!("\n  - {}:", yaml_scalar(name)));
+                    for item in items {
+                        line.push_str(&format!("\n    - {}", yaml_scalar(item)));
+                    }
+                }
+            }
+        }
+        if index == records.len() - 1 {
+            line.push('\n');
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+pub(crate) fn yaml_scalar(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value.trim() == value
+        && !value.contains(": ")
+        && !value.contains(" #")
+        && !value.contains('\n')
+        && !value.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c));
+    if plain {
+        return value.to_string();
+    }
+    json_string(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        let mut first = Record::new();
+        first.insert("name", Value::Text("Widget, large".to_string()));
+        first.insert("tags", Value::List(vec!["a".to_string(), "b".to_string()]));
+        let mut second = Record::new();
+        second.insert("name", Value::Text("Say \"hi\"".to_string()));
+        second.insert("price", Value::Number(10.5));
+        vec![first, second]
+    }
+
+    #[test]
+    fn test_csv_lines() {
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Csv),
+            ..StorageOptions::new("test.csv".to_string())
+        };
+        assert_eq!(csv_lines(&records(), &options), vec![
+            "name,tags,price\n",
+            "\"Widget, large\",a b,\n",
+            "\"Say \"\"hi\"\"\",,10.5\n",
+        ]);
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Json),
+            ..StorageOptions::new("test.json".to_string())
+        };
+        assert_eq!(json_lines(&records(), &options).concat(), r#"[{"name":"Widget, large","tags":["a","b"]},{"name":"Say \"hi\"","price":10.5}]"#);
+    }
+
+    #[test]
+    fn test_json_lines_pretty_print() {
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Json),
+            pretty_print: Some(true),
+            ..StorageOptions::new("test.json".to_string())
+        };
+        let expected = r#"[
+  {
+    "name":"Widget, large",
+    "tags":["a", "b"]
+  },
+  {
+    "name":"Say \"hi\"",
+    "price":10.5
+  }
+]"#;
+        assert_eq!(json_lines(&records(), &options).concat(), expected);
+    }
+
+    #[test]
+    fn test_yaml_lines() {
+        let expected = r#"data:
+  - name: Widget, large
+  - tags:
+    - a
+    - b
+data:
+  - name: Say "hi"
+  - price: 10.5
+"#;
+        assert_eq!(yaml_lines(&records()).concat(), expected);
+    }
+
+    #[test]
+    fn test_xml_lines() {
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Xml),
+            ..StorageOptions::new("test.xml".to_string())
+        };
+        assert_eq!(xml_lines(&records(), &options), vec![
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            "<data><name>Widget, large</name><tags><item>a</item><item>b</item></tags></data>",
+            "<data><name>Say \"hi\"</name><price>10.5</price></data>",
+        ]);
+    }
+}
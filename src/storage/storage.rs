@@ -1,7 +1,15 @@
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
 use std::io;
 use std::io::Write;
 
+use html_parser::Element;
+
+use crate::scrapper::parse::{parse_element, ParseError};
+use crate::scrapper::provenance::Provenance;
+use crate::scrapper::text::{extract_text, TextOptions};
+
+use super::records::json_string;
 use super::txt;
 use super::json;
 use super::csv;
@@ -29,13 +37,15 @@ pub enum FileFormat {
 /// * `include_attributes` - A list of attribute names to include in the output. Defaults to an empty list.
 /// * `include_text_content` - Whether to include the text content of HTML elements. Defaults to `true`.
 /// * `include_tag_names` - Whether to include the names of HTML tags. Defaults to `true`.
-/// * `include_metadata` - Whether to include metadata in the output. Defaults to `false`.
+/// * `include_metadata` - Whether to include the provenance of every element given to `store_with_metadata`
+///   in the output, see `Provenance`. Defaults to `false`.
 /// * `pretty_print` - Whether to pretty-print the output (e.g., for JSON). Defaults to `false`.
 /// * `delimiter` - The delimiter to use for CSV format. Defaults to `,`.
+/// * `text_options` - How the text field is extracted from every element, see `TextOptions`. Defaults to the
+///   recursive, entity decoded and whitespace collapsed text.
 /// * `append` - Whether to append to the file if it already exists. Defaults to `false`.
 /// * `compress` - Whether to compress the output file. Defaults to `false`.
 /// * `encoding` - The encoding to use for the output file (e.g., "UTF-8", "ASCII"). Defaults to `Encoding::Utf8`.
-
 pub struct StorageOptions {
     pub file_name: String,
     pub file_format: Option<FileFormat>,
@@ -43,11 +53,12 @@ pub struct StorageOptions {
     pub include_attributes: Option<Vec<String>>, // List of attribute names to include, None means all attributes
     pub include_text_content: Option<bool>,
     pub include_tag_names: Option<bool>,
-    // pub include_metadata: Option<bool>,
+    pub include_metadata: Option<bool>,
     // pub custom_data_patterns: Option<Vec<String>>, // List of custom data extraction patterns
     pub pretty_print: Option<bool>, // For JSON and XML formats
     pub delimiter: Option<String>, // For CSV format
     pub custom_data_storage: Option<fn(&String)>, // Only for custom file formats
+    pub text_options: Option<TextOptions>,
 }
 
 impl StorageOptions  {
@@ -59,10 +70,11 @@ impl StorageOptions  {
             include_attributes: None,
             include_text_content: None,
             include_tag_names: None,
-            // include_metadata: None,
+            include_metadata: None,
             pretty_print: None,
             delimiter: None,
             custom_data_storage: None,
+            text_options: None,
         }
     } 
 }
@@ -71,6 +83,8 @@ pub struct ScraperGenerator<'a> {
     pub data: &'a Vec<String>,
     pub options: &'a StorageOptions,
     pub index: usize,
+    pub metadata: &'a [Provenance],
+    pub failed: bool,
 }
 
 impl<'a> ScraperGenerator<'a> {
@@ -79,32 +93,160 @@ impl<'a> ScraperGenerator<'a> {
             data,
             options,
             index: 0,
+            metadata: &[],
+            failed: false,
+        }
+    }
+
+    /// Parses the record at `index`. After an error `failed` is set and the generators stop, the
+    /// remaining records are not written.
+    pub fn element(&mut self, index: usize) -> Result<Element, StorageError> {
+        parse_element(&self.data[index]).map_err(|error| {
+            self.failed = true;
+            StorageError::Parse { record: index, error }
+        })
+    }
+
+    /// Provenance of the element at `index`, when `include_metadata` is set.
+    pub fn provenance(&self, index: usize) -> Option<&'a Provenance> {
+        if !self.options.include_metadata.unwrap_or(false) {
+            return None;
+        }
+        self.metadata.get(index)
+    }
+
+    /// Text field of `tag`, shared by every file format.
+    pub fn text(&self, tag: &Element) -> String {
+        match self.options.text_options {
+            Some(ref text_options) => extract_text(tag, text_options),
+            None => extract_text(tag, &TextOptions::new()),
+        }
+    }
+}
+
+/// The provenance of an element as an HTML comment, for the formats writing raw elements.
+pub(crate) fn provenance_comment(provenance: &Provenance) -> String {
+    let values = provenance.values()
+        .into_iter()
+        // `--` can not appear in a comment
+        .filter_map(|(name, value)| value.map(|value| format!("{}={}", name, json_string(&value.replace("--", "%2D%2D")))))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!("<!-- {} -->", values)
+}
+
+/// Error returned by `store`. `Parse` carries the index of the record that could not be parsed.
+/// `MetadataLength` is returned by `store_with_metadata` when there is not one `Provenance` per record.
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    Parse { record: usize, error: ParseError },
+    MetadataLength { records: usize, metadata: usize },
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(error) => write!(f, "{}", error),
+            StorageError::Parse { record, error } => write!(f, "record {}: {}", record, error),
+            StorageError::MetadataLength { records, metadata } => {
+                write!(f, "{} records but {} metadata entries", records, metadata)
+            }
         }
     }
 }
 
-pub fn store(data: &Vec<String>, options: &StorageOptions) -> Result<(), io::Error> {
-    let content_iter: Box<dyn Iterator<Item = String>> = match options.file_format.as_ref().unwrap_or(&FileFormat::Txt) {
-        FileFormat::Txt => Box::new(txt::ScraperTxtGenerator::new(data, &options)),
-        FileFormat::Json => Box::new(json::ScraperJSONGenerator::new(data, &options)),
-        FileFormat::Csv => Box::new(csv::ScraperCSVGenerator::new(data, &options)),
-        FileFormat::Xml => Box::new(xml::ScraperXMLGenerator::new(data, &options)),
-        FileFormat::Yaml => Box::new(yaml::ScraperYAMLGenerator::new(data, &options)),
-        FileFormat::Custom => Box::new(custom::CustomDataGenerator::new(data, &options)),
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(error: io::Error) -> Self {
+        StorageError::Io(error)
+    }
+}
+
+pub fn store(data: &Vec<String>, options: &StorageOptions) -> Result<(), StorageError> {
+    store_with_metadata(data, &[], options)
+}
+
+/// Same as `store` with the provenance of every element at the same index, as returned by
+/// `scrape_with_provenance`. It is written when `include_metadata` is set: as a `metadata`
+/// object in JSON, XML and YAML, as extra columns in CSV and as an HTML comment before every
+/// element in the other formats. `metadata` must be empty or have the length of `data`.
+/// Records are parsed as they are written, the file is removed when one can not be parsed.
+pub fn store_with_metadata(data: &Vec<String>, metadata: &[Provenance], options: &StorageOptions) -> Result<(), StorageError> {
+    if !metadata.is_empty() && metadata.len() != data.len() {
+        return Err(StorageError::MetadataLength { records: data.len(), metadata: metadata.len() });
+    }
+    let content_iter: Box<dyn Iterator<Item = Result<String, StorageError>>> = match options.file_format.as_ref().unwrap_or(&FileFormat::Txt) {
+        FileFormat::Txt => Box::new(txt::ScraperTxtGenerator::new(data, options).with_metadata(metadata).map(Ok)),
+        FileFormat::Json => Box::new(json::ScraperJSONGenerator::new(data, options).with_metadata(metadata)),
+        FileFormat::Csv => Box::new(csv::ScraperCSVGenerator::new(data, options).with_metadata(metadata)),
+        FileFormat::Xml => Box::new(xml::ScraperXMLGenerator::new(data, options).with_metadata(metadata)),
+        FileFormat::Yaml => Box::new(yaml::ScraperYAMLGenerator::new(data, options).with_metadata(metadata)),
+        FileFormat::Custom => Box::new(custom::CustomDataGenerator::new(data, options).with_metadata(metadata).map(Ok)),
     };
 
     match options.file_format.as_ref().unwrap_or(&FileFormat::Txt) {
         FileFormat::Custom =>{
-            for _ in content_iter{}
+            for line in content_iter{
+                line?;
+            }
         },
         _ =>{
             let mut file = File::create(&options.file_name)?;
         
             for line in content_iter {
-                file.write_all(line.as_bytes())?;
+                let written = line.and_then(|line| Ok(file.write_all(line.as_bytes())?));
+                if let Err(error) = written {
+                    // the generators stop at the first record that can not be parsed, the
+                    // partial output is not left behind
+                    drop(file);
+                    fs::remove_file(&options.file_name).ok();
+                    return Err(error);
+                }
             }
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_parse_error() {
+        let data = vec![
+            "<div id='div1'>hello world</div>".to_string(),
+            "<!-- <div>".to_string(),
+        ];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Json),
+            ..StorageOptions::new("test_store_parse_error.json".to_string())
+        };
+        match store(&data, &options) {
+            Err(StorageError::Parse { record, error }) => {
+                assert_eq!(record, 1);
+                assert_eq!((error.line, error.column), (Some(1), Some(2)));
+            }
+            _ => panic!("should return a parse error for the second record"),
+        }
+        assert!(!std::path::Path::new("test_store_parse_error.json").exists(), "should not leave a partial file");
+    }
+
+    #[test]
+    fn test_store_metadata_length() {
+        let data = vec!["<div>a</div>".to_string(), "<div>b</div>".to_string()];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Json),
+            include_metadata: Some(true),
+            ..StorageOptions::new("test_store_metadata_length.json".to_string())
+        };
+        match store_with_metadata(&data, &[Provenance::sample()], &options) {
+            Err(StorageError::MetadataLength { records, metadata }) => assert_eq!((records, metadata), (2, 1)),
+            _ => panic!("should reject metadata of another length"),
+        }
+        assert!(!std::path::Path::new("test_store_metadata_length.json").exists(), "should not create the file");
+    }
+}
@@ -1,13 +1,18 @@
 use super::storage::ScraperGenerator;
 use super::storage::StorageOptions;
+use super::storage::provenance_comment;
+use crate::scrapper::provenance::Provenance;
 
 pub struct ScraperTxtGenerator<'a>(pub ScraperGenerator<'a>);
 
 impl<'a> ScraperTxtGenerator<'a> {
     pub fn new(data: &'a Vec<String>, options: &'a StorageOptions) -> Self {
-        Self {
-            0: ScraperGenerator::new(data, options),
-        }
+        Self(ScraperGenerator::new(data, options))
+    }
+
+    pub fn with_metadata(mut self, metadata: &'a [Provenance]) -> Self {
+        self.0.metadata = metadata;
+        self
     }
 }
 
@@ -16,7 +21,11 @@ impl<'a> Iterator for ScraperTxtGenerator<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         if self.0.index < self.0.data.len() {
             self.0.index += 1;
-            return Some(self.0.data[self.0.index - 1].clone());
+            let element = self.0.data[self.0.index - 1].clone();
+            if let Some(provenance) = self.0.provenance(self.0.index - 1) {
+                return Some(format!("{}{}", provenance_comment(provenance), element));
+            }
+            return Some(element);
         }
         None
     }    
@@ -44,4 +53,21 @@ mod tests {
         assert_eq!(generator.next(), Some("<div class='test' id='div2' data-role='main'>goodbye world</div>".to_string()));
         assert_eq!(generator.next(), None);
     }
+
+    #[test]
+    fn test_scraper_txt_generator_metadata() {
+        let data = vec!["<div>hello world</div>".to_string()];
+        let metadata = vec![Provenance::sample()];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Txt),
+            include_metadata: Some(true),
+            ..StorageOptions::new("test.txt".to_string())
+        };
+        let mut generator = ScraperTxtGenerator::new(&data, &options).with_metadata(&metadata);
+        assert_eq!(generator.next(), Some(r#"<!-- source_url="https://example.com/" status="200" path="body > div" offset="12" hash="00ff" --><div>hello world</div>"#.to_string()));
+        // without the option the provenance is not written
+        let options = StorageOptions::new("test.txt".to_string());
+        let mut generator = ScraperTxtGenerator::new(&data, &options).with_metadata(&metadata);
+        assert_eq!(generator.next(), Some("<div>hello world</div>".to_string()));
+    }
 }
@@ -1,9 +1,14 @@
 use std::collections::HashSet;
 
 use super::storage::ScraperGenerator;
+use super::storage::StorageError;
 use super::storage::StorageOptions;
 
-use html_parser::{Dom, Element};
+use html_parser::Element;
+
+use super::records::xml_escape;
+use crate::scrapper::parse::parse_element;
+use crate::scrapper::provenance::Provenance;
 
 pub struct ScraperXMLGenerator<'a> {
     tags: ScraperGenerator<'a>,
@@ -24,6 +29,11 @@ impl<'a> ScraperXMLGenerator<'a> {
         }
     }
 
+    pub fn with_metadata(mut self, metadata: &'a [Provenance]) -> Self {
+        self.tags.metadata = metadata;
+        self
+    }
+
     fn pretty_print(&self) -> bool {
         self.tags.options.pretty_print.unwrap_or(false)
     }
@@ -42,16 +52,13 @@ impl<'a> ScraperXMLGenerator<'a> {
             } else {
                 let mut attr_order: HashSet<String> = HashSet::new();
                 for tag in self.tags.data {
-                    let tag = Dom::parse(&tag)
-                        .unwrap()
-                        .children[0]
-                        .element()
-                        .unwrap()
-                        .clone();
-                    if !tag.id.is_none() {
+                    let Ok(tag) = parse_element(tag) else {
+                        continue;
+                    };
+                    if tag.id.is_some() {
                         attr_order.insert("id".to_string());
                     }
-                    if tag.classes.len() > 0 {
+                    if !tag.classes.is_empty() {
                         attr_order.insert("class".to_string());
                     }
                     for (key, _) in tag.attributes.iter() {
@@ -71,7 +78,7 @@ impl<'a> ScraperXMLGenerator<'a> {
     fn prettify(&self, line: String) -> String {
         if self.pretty_print() {
             let mut result = String::new();
-            result.push_str("\n");
+            result.push('\n');
             for _ in 0..self.indent {
                 result.push_str("  ");
             }
@@ -90,12 +97,12 @@ impl<'a> ScraperXMLGenerator<'a> {
         self.indent += 1;
         for class in &tag.classes {
             json_row.push_str(
-                &self.prettify(
+                self.prettify(
                     format!(r#"<class>{}</class>"#, class)).as_str()
                 );
         }
         self.indent -= 1;
-        json_row.push_str(&self.prettify("</classes>".to_string()).as_str());
+        json_row.push_str(self.prettify("</classes>".to_string()).as_str());
     }
 
     fn handle_html_id_extract(&self, tag: &Element, json_row: &mut String) {
@@ -110,16 +117,24 @@ impl<'a> ScraperXMLGenerator<'a> {
         csv_row.push_str(
             format!(
                 r#"<text>{}</text>"#,
-                tag.children
-                    .iter()
-                    .map(|node| node.text().unwrap_or(""))
-                    .collect::<Vec<&str>>()
-                    .join(" ")
+                xml_escape(&self.tags.text(tag))
             )
             .as_str(),
         )
     }
 
+    fn handle_metadata_extract(&mut self, provenance: &Provenance, xml_row: &mut String) {
+        xml_row.push_str("<metadata>");
+        self.indent += 1;
+        for (name, value) in provenance.values() {
+            if let Some(value) = value {
+                xml_row.push_str(&self.prettify(format!(r#"<{0}>{1}</{0}>"#, name, xml_escape(&value))));
+            }
+        }
+        self.indent -= 1;
+        xml_row.push_str(&self.prettify("</metadata>".to_string()));
+    }
+
     fn handle_extract_attribute(&self, tag: &Element, xml_row: &mut String, attr: &str) {
         let Some(attribute_value) = tag.attributes.get(attr) else {
             xml_row.clear();
@@ -137,21 +152,22 @@ impl<'a> ScraperXMLGenerator<'a> {
 }
 
 impl<'a> Iterator for ScraperXMLGenerator<'a> {
-    type Item = String;
+    type Item = Result<String, StorageError>;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.tags.failed {
+            return None;
+        }
         if self.first {
             self.first = false;
             let result = self.first_gen();
-            return Some(result);
+            return Some(Ok(result));
         }
         if self.iter < self.tags.data.len() {
             let mut result = String::new();
-            let tag = Dom::parse(&self.tags.data[self.iter])
-                .unwrap()
-                .children[0]
-                .element()
-                .unwrap()
-                .clone();
+            let tag = match self.tags.element(self.iter) {
+                Ok(tag) => tag,
+                Err(error) => return Some(Err(error)),
+            };
             result.push_str(self.prettify("<data>".to_string()).as_str());
             self.indent += 1;
             let order = self.order.clone();
@@ -164,12 +180,17 @@ impl<'a> Iterator for ScraperXMLGenerator<'a> {
                     "text" => self.handle_html_text_extract(&tag, &mut csv_append),
                     default => self.handle_extract_attribute(&tag, &mut csv_append, default),
                 }
-                result.push_str(&self.prettify(csv_append).as_str());
+                result.push_str(self.prettify(csv_append).as_str());
+            }
+            if let Some(provenance) = self.tags.provenance(self.iter) {
+                let mut metadata = String::new();
+                self.handle_metadata_extract(provenance, &mut metadata);
+                result.push_str(&self.prettify(metadata));
             }
             self.indent -= 1;
             result.push_str(self.prettify("</data>".to_string()).as_str());
             self.iter += 1;
-            return Some(result);
+            return Some(Ok(result));
         }
         None
     }
@@ -189,10 +210,10 @@ mod tests {
         };
         let mut generator = ScraperXMLGenerator::new(&data, &options);
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string())
         );
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -217,31 +238,31 @@ mod tests {
         };
         let mut generator = ScraperXMLGenerator::new(&data, &options);
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string())
         );
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(
                 r#"<data><tag>div</tag><classes><class>test</class></classes><id>div1</id><data-role>main</data-role><text>hello world</text></data>"#
                     .to_string()
             )
         );
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(
                 r#"<data><tag>span</tag><classes><class>test</class></classes><id>span1</id><data-role>secondary</data-role><text>hello rust</text></data>"#
                     .to_string()
             )
         );
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(
                 r#"<data><tag>div</tag><classes><class>test</class></classes><id>div2</id><data-role>main</data-role><text>goodbye world</text></data>"#
                     .to_string()
             )
         );
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -266,31 +287,31 @@ mod tests {
         };
         let mut generator = ScraperXMLGenerator::new(&data, &options);
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string())
         );
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(
                 r#"<data><tag>div</tag><classes><class>test</class></classes><data-role>main</data-role><text>hello world</text></data>"#
                     .to_string()
             )
         ); // Missing id
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(
                 r#"<data><tag>span</tag><classes><class>test</class></classes><id>span1</id><text>hello rust</text></data>"#
                     .to_string()
             )
         ); // Missing data-role
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(
                 r#"<data><tag>div</tag><classes><class>test</class></classes><id>div2</id><data-role>main</data-role><text>goodbye world</text></data>"#
                     .to_string()
             )
         );
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -311,22 +332,22 @@ mod tests {
         };
         let mut generator = ScraperXMLGenerator::new(&data, &options);
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string())
         );
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(r#"<data><text>hello world</text></data>"#.to_string())
         );
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(r#"<data><text>hello rust</text></data>"#.to_string())
         );
         assert_eq!(
-            generator.next(),
+            generator.next().transpose().unwrap(),
             Some(r#"<data><text>goodbye world</text></data>"#.to_string())
         );
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -345,9 +366,9 @@ mod tests {
             pretty_print: Some(true),
             ..StorageOptions::new("test.xml".to_string())
         };
-        let mut generator = ScraperXMLGenerator::new(&data, &options);
+        let generator = ScraperXMLGenerator::new(&data, &options);
         let mut result = String::new();
-        while let Some(line) = generator.next() {
+        for line in generator.map(Result::unwrap) {
             result.push_str(&line);
         }
         let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
@@ -380,4 +401,24 @@ mod tests {
 </data>"#.to_string();
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_scraper_xml_generator_metadata() {
+        let data = vec!["<div>hello world</div>".to_string()];
+        let metadata = vec![Provenance::sample()];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Xml),
+            include_metadata: Some(true),
+            ..StorageOptions::new("test.xml".to_string())
+        };
+        let mut generator = ScraperXMLGenerator::new(&data, &options).with_metadata(&metadata);
+        generator.next().transpose().unwrap();
+        assert_eq!(
+            generator.next().transpose().unwrap(),
+            Some(
+                r#"<data><text>hello world</text><metadata><source_url>https://example.com/</source_url><status>200</status><path>body &gt; div</path><offset>12</offset><hash>00ff</hash></metadata></data>"#
+                    .to_string()
+            )
+        );
+    }
 }
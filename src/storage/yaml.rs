@@ -1,9 +1,13 @@
 use std::collections::HashSet;
-use std::result;
 
 use super::storage::ScraperGenerator;
+use super::storage::StorageError;
 use super::storage::StorageOptions;
-use html_parser::{Dom, Element};
+use html_parser::Element;
+
+use super::records::yaml_scalar;
+use crate::scrapper::parse::parse_element;
+use crate::scrapper::provenance::Provenance;
 
 pub struct ScraperYAMLGenerator<'a> {
     tags: ScraperGenerator<'a>,
@@ -23,6 +27,11 @@ impl<'a> ScraperYAMLGenerator<'a> {
             indent: 0,
         }
     }
+
+    pub fn with_metadata(mut self, metadata: &'a [Provenance]) -> Self {
+        self.tags.metadata = metadata;
+        self
+    }
     fn first_gen(&mut self){
         let mut yaml_order: HashSet<String> = HashSet::new();
         if !self.tags.options.include_tag_content.unwrap_or(false){
@@ -39,11 +48,13 @@ impl<'a> ScraperYAMLGenerator<'a> {
         }
         else {
             for tag in self.tags.data{
-                let tag = Dom::parse(&tag).unwrap().children[0].element().unwrap().clone();
-                if !tag.id.is_none(){
+                let Ok(tag) = parse_element(tag) else {
+                    continue;
+                };
+                if tag.id.is_some(){
                     yaml_order.insert("id".to_string());
                 }
-                if tag.classes.len() > 0{
+                if !tag.classes.is_empty(){
                     yaml_order.insert("class".to_string());
                 }
                 for (key, _) in tag.attributes.iter(){
@@ -57,7 +68,7 @@ impl<'a> ScraperYAMLGenerator<'a> {
         self.order.push("text".to_string());
     }
     
-    fn pretify_str(&self, val:&str)->String{
+    fn pretify_string(&self, val:&str)->String{
         let mut result = "\n".to_string();
         for _ in 0..self.indent{
             result.push_str("  ");
@@ -65,28 +76,13 @@ impl<'a> ScraperYAMLGenerator<'a> {
         result.push_str(val);
         result
     }
-    fn pretify_string(&self, val:&String)->String{
-        let mut result = "\n".to_string();
-        for _ in 0..self.indent{
-            result.push_str("  ");
-        }
-        result.push_str(val);
-        result
-    }
-
-    fn reset(&mut self){
-        self.index = 0;
-        self.order.clear();
-        self.first = true;
-        self.indent = 0;
-    }
 
-    fn handle_html_tagname_extract(&self, tag: &Element, yaml_row: &mut String, header: &String){
+    fn handle_html_tagname_extract(&self, tag: &Element, yaml_row: &mut String, _header: &String){
         let tagname = &tag.name;
         yaml_row.push_str(&format!("- tag: {}", tagname));
     }
-    fn handle_html_classes_extract(&mut self, tag: &Element, yaml_row: &mut String, header: &String){
-        if tag.classes.len() == 0{
+    fn handle_html_classes_extract(&mut self, tag: &Element, yaml_row: &mut String, _header: &String){
+        if tag.classes.is_empty(){
             yaml_row.clear();
             return;
         }
@@ -98,7 +94,7 @@ impl<'a> ScraperYAMLGenerator<'a> {
                 ,|acc, e| {
                     let mut result = acc.clone();
                     result.push_str(
-                    &self.pretify_string(&format!("- {}", e)).as_str() 
+                    self.pretify_string(&format!("- {}", e)).as_str() 
                 );
                 result
             }
@@ -106,29 +102,35 @@ impl<'a> ScraperYAMLGenerator<'a> {
         self.indent -=1;
         yaml_row.push_str(&classes);
     }
-    fn handle_html_id_extract(&self, tag: &Element, yaml_row: &mut String, header: &String){
+    fn handle_html_id_extract(&self, tag: &Element, yaml_row: &mut String, _header: &String){
         let Some(id) = &tag.id else{
             yaml_row.clear();
             return;
         };
-        yaml_row.push_str(&format!("- id: {}", id).as_str());
+        yaml_row.push_str(format!("- id: {}", id).as_str());
     }
-    fn handle_html_text_extract(&self, tag: &Element, yaml_row: &mut String, header: &String){
-        let text = tag.children
-            .iter()
-            .map(|node| node.text().unwrap_or(""))
-            .collect::<Vec<&str>>()
-            .join(" ");
+    fn handle_html_text_extract(&self, tag: &Element, yaml_row: &mut String, _header: &String){
+        let text = self.tags.text(tag);
         if !text.chars().any(|cha| cha.is_alphanumeric()){
             yaml_row.clear();
             return;
         }
         yaml_row.push_str(
-                &format!(
+                format!(
                     r#"- text: {}"#,
-                    text
+                    yaml_scalar(&text)
                 ).as_str())
     }
+    fn handle_metadata_extract(&mut self, provenance: &Provenance, yaml_row: &mut String){
+        yaml_row.push_str("- metadata:");
+        self.indent += 1;
+        for (name, value) in provenance.values(){
+            if let Some(value) = value{
+                yaml_row.push_str(&self.pretify_string(&format!("- {}: {}", name, yaml_scalar(&value))));
+            }
+        }
+        self.indent -= 1;
+    }
     fn handle_extract_attribute(&self, tag: &Element, yaml_row: &mut String, header: &String, default: &str){
         let Some(attribute_value) = tag.attributes.get(default) else {
             yaml_row.clear();
@@ -136,23 +138,29 @@ impl<'a> ScraperYAMLGenerator<'a> {
         };
 
         yaml_row.push_str(
-            &format!(r#"- {}: {}"#, header, attribute_value.clone().unwrap_or("null".to_string())).as_str(),
+            format!(r#"- {}: {}"#, header, attribute_value.clone().unwrap_or("null".to_string())).as_str(),
         )
     }
 }
 
 impl<'a> Iterator for ScraperYAMLGenerator<'a> {
-    type Item = String;
+    type Item = Result<String, StorageError>;
     fn next(&mut self) -> Option<Self::Item> {
+        if self.tags.failed {
+            return None;
+        }
         let mut result = String::new();
         if self.first{
             self.first = false;
             self.first_gen();
         }
         if self.index < self.tags.data.len(){
-            let tag = Dom::parse(&self.tags.data[self.index]).unwrap().children[0].element().unwrap().clone();
+            let tag = match self.tags.element(self.index) {
+                Ok(tag) => tag,
+                Err(error) => return Some(Err(error)),
+            };
             if self.index > 0 {
-                result.push_str("\n");
+                result.push('\n');
             }
             self.index += 1;
             result.push_str("data:");
@@ -172,11 +180,16 @@ impl<'a> Iterator for ScraperYAMLGenerator<'a> {
                 }
                 result.push_str(&self.pretify_string(&yaml_append));
             }
+            if let Some(provenance) = self.tags.provenance(self.index - 1){
+                let mut metadata = String::new();
+                self.handle_metadata_extract(provenance, &mut metadata);
+                result.push_str(&self.pretify_string(&metadata));
+            }
             self.indent -= 1;
             if self.index == self.tags.data.len(){
-                result.push_str("\n");
+                result.push('\n');
             }
-            return Some(result);
+            return Some(Ok(result));
         }
 
         None
@@ -196,7 +209,7 @@ mod tests {
             ..StorageOptions::new("test.yaml".to_string())
         };
         let mut generator = ScraperYAMLGenerator::new(&data, &options);
-        assert_eq!(generator.next(), None);
+        assert_eq!(generator.next().transpose().unwrap(), None);
     }
 
     #[test]
@@ -214,9 +227,9 @@ mod tests {
             include_tag_names: Some(true),
             ..StorageOptions::new("test.yaml".to_string())
         };
-        let mut generator = ScraperYAMLGenerator::new(&data, &options);
+        let generator = ScraperYAMLGenerator::new(&data, &options);
         let mut result = String::new();
-        while let Some(line) = generator.next() {
+        for line in generator.map(Result::unwrap) {
             result.push_str(&line);
         }
         let expected = r#"data:
@@ -259,9 +272,9 @@ data:
             include_tag_names: Some(true),
             ..StorageOptions::new("test.yaml".to_string())
         };
-        let mut generator = ScraperYAMLGenerator::new(&data, &options);
+        let generator = ScraperYAMLGenerator::new(&data, &options);
         let mut result = String::new();
-        while let Some(line) = generator.next() {
+        for line in generator.map(Result::unwrap) {
             result.push_str(&line);
         }
         let expected = r#"data:
@@ -302,9 +315,9 @@ data:
             include_tag_names: Some(true),
             ..StorageOptions::new("test.yaml".to_string())
         };
-        let mut generator = ScraperYAMLGenerator::new(&data, &options);
+        let generator = ScraperYAMLGenerator::new(&data, &options);
         let mut result = String::new();
-        while let Some(line) = generator.next() {
+        for line in generator.map(Result::unwrap) {
             result.push_str(&line);
         }
         let expected = r#"data:
@@ -332,9 +345,9 @@ data:
             include_tag_names: Some(true),
             ..StorageOptions::new("test.yaml".to_string())
         };
-        let mut generator = ScraperYAMLGenerator::new(&data, &options);
+        let generator = ScraperYAMLGenerator::new(&data, &options);
         let mut result = String::new();
-        while let Some(line) = generator.next() {
+        for line in generator.map(Result::unwrap) {
             result.push_str(&line);
         }
         let expected = r#"data:
@@ -380,9 +393,9 @@ data:
             include_tag_names: Some(true),
             ..StorageOptions::new("test.yaml".to_string())
         };
-        let mut generator = ScraperYAMLGenerator::new(&data, &options);
+        let generator = ScraperYAMLGenerator::new(&data, &options);
         let mut result = String::new();
-        while let Some(line) = generator.next() {
+        for line in generator.map(Result::unwrap) {
             result.push_str(&line);
         }
         let expected = r#"data:
@@ -403,4 +416,26 @@ data:
 "#.to_string();
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_scraper_yaml_generator_metadata() {
+        let data = vec!["<div>hello world</div>".to_string()];
+        let metadata = vec![Provenance::sample()];
+        let options = StorageOptions {
+            file_format: Some(FileFormat::Yaml),
+            include_metadata: Some(true),
+            ..StorageOptions::new("test.yaml".to_string())
+        };
+        let result: String = ScraperYAMLGenerator::new(&data, &options).with_metadata(&metadata).map(Result::unwrap).collect();
+        let expected = r#"data:
+  - text: hello world
+  - metadata:
+    - source_url: https://example.com/
+    - status: 200
+    - path: body > div
+    - offset: 12
+    - hash: 00ff
+"#;
+        assert_eq!(result, expected);
+    }
 }